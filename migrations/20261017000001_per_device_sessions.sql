@@ -0,0 +1,9 @@
+-- Give every login its own session instead of sharing one key per member
+ALTER TABLE sessions DROP CONSTRAINT sessions_pkey;
+ALTER TABLE sessions ADD COLUMN id bigserial PRIMARY KEY;
+ALTER TABLE sessions ADD CONSTRAINT sessions_key_unique UNIQUE (key);
+ALTER TABLE sessions ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE sessions ADD COLUMN last_seen timestamptz NOT NULL DEFAULT now();
+ALTER TABLE sessions ADD COLUMN user_agent text;
+
+CREATE INDEX sessions_member_index ON sessions (member);
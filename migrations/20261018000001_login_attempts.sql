@@ -0,0 +1,13 @@
+-- Failed logins and password reset requests, used to throttle brute-forcing
+CREATE TYPE login_attempt_kind AS ENUM ('login', 'password_reset');
+
+CREATE TABLE login_attempts (
+  id bigserial PRIMARY KEY,
+  kind login_attempt_kind NOT NULL,
+  email varchar(50) NOT NULL,
+  ip varchar(45) NOT NULL,
+  "time" timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX login_attempts_email_index ON login_attempts (kind, email, "time");
+CREATE INDEX login_attempts_ip_index ON login_attempts (kind, ip, "time");
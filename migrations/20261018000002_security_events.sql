@@ -0,0 +1,22 @@
+-- Store password reset tokens hashed instead of in plain text
+ALTER TABLE password_resets RENAME COLUMN token TO token_hash;
+UPDATE password_resets SET token_hash = encode(sha256(token_hash::bytea), 'hex');
+
+-- An audit log of security-sensitive changes to members' accounts
+CREATE TYPE security_event_kind AS ENUM (
+  'password_reset_requested',
+  'password_reset_used',
+  'password_reset_expired',
+  'password_changed'
+);
+
+CREATE TABLE security_events (
+  id bigserial PRIMARY KEY,
+  kind security_event_kind NOT NULL,
+  member varchar(50) NOT NULL REFERENCES members(email) ON UPDATE CASCADE ON DELETE CASCADE,
+  "time" timestamptz NOT NULL DEFAULT now(),
+  ip varchar(45),
+  details text
+);
+
+CREATE INDEX security_events_member_index ON security_events (member, "time");
@@ -0,0 +1,30 @@
+-- Accounts at an OpenID Connect provider that can log in as a member
+CREATE TABLE external_identities (
+  issuer varchar(255) NOT NULL,
+  subject varchar(255) NOT NULL,
+  member varchar(50) NOT NULL REFERENCES members(email) ON UPDATE CASCADE ON DELETE CASCADE,
+  email varchar(255) NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX external_identities_member_index ON external_identities (member);
+
+-- In-progress logins, between redirecting to the provider and its callback
+CREATE TABLE oidc_login_states (
+  state varchar(64) PRIMARY KEY,
+  code_verifier varchar(128) NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- Provider accounts whose email didn't match a member, waiting for
+-- a logged in member to claim them
+CREATE TABLE pending_identity_links (
+  code_hash varchar(64) PRIMARY KEY,
+  issuer varchar(255) NOT NULL,
+  subject varchar(255) NOT NULL,
+  email varchar(255) NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now()
+);
+
+ALTER TYPE security_event_kind ADD VALUE 'single_sign_on_linked';
@@ -0,0 +1,18 @@
+-- Long-lived keys for bots and scripts, limited to specific permissions
+CREATE TABLE api_keys (
+  id bigserial PRIMARY KEY,
+  name varchar(100) NOT NULL,
+  key_hash varchar(64) NOT NULL UNIQUE,
+  created_by varchar(50) NOT NULL REFERENCES members(email) ON UPDATE CASCADE ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  expires_at timestamptz NOT NULL,
+  last_used timestamptz
+);
+
+CREATE TABLE api_key_permissions (
+  id bigserial PRIMARY KEY,
+  api_key bigint NOT NULL REFERENCES api_keys(id) ON UPDATE CASCADE ON DELETE CASCADE,
+  permission varchar(40) NOT NULL REFERENCES permissions(name) ON UPDATE CASCADE ON DELETE CASCADE,
+  event_type varchar(32) REFERENCES event_types(name) ON UPDATE CASCADE ON DELETE CASCADE,
+  UNIQUE (api_key, permission, event_type)
+);
@@ -0,0 +1,12 @@
+-- Sessions created by an officer logging in as another member
+ALTER TABLE sessions
+  ADD COLUMN impersonated_by varchar(50) REFERENCES members(email) ON UPDATE CASCADE ON DELETE CASCADE,
+  ADD COLUMN original_session bigint REFERENCES sessions(id) ON DELETE CASCADE;
+
+-- The member that actually caused a security event, if it wasn't the member themself
+ALTER TABLE security_events
+  ADD COLUMN actor varchar(50) REFERENCES members(email) ON UPDATE CASCADE ON DELETE SET NULL;
+
+ALTER TYPE security_event_kind ADD VALUE 'impersonation_started';
+ALTER TYPE security_event_kind ADD VALUE 'impersonation_ended';
+ALTER TYPE security_event_kind ADD VALUE 'impersonated_mutation';
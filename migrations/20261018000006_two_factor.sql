@@ -0,0 +1,26 @@
+-- Authenticator app secrets for members using two-factor authentication.
+-- The secret is unconfirmed until the member enters a code generated from it.
+CREATE TABLE totp_credentials (
+  member varchar(50) PRIMARY KEY REFERENCES members(email) ON UPDATE CASCADE ON DELETE CASCADE,
+  secret varchar(64) NOT NULL,
+  confirmed boolean NOT NULL DEFAULT false,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  last_used_step bigint
+);
+
+-- Single-use codes for logging in without the authenticator app
+CREATE TABLE totp_recovery_codes (
+  id bigserial PRIMARY KEY,
+  member varchar(50) NOT NULL REFERENCES members(email) ON UPDATE CASCADE ON DELETE CASCADE,
+  code_hash varchar(64) NOT NULL,
+  used_at timestamptz
+);
+
+CREATE INDEX totp_recovery_codes_member_index ON totp_recovery_codes (member);
+
+-- Permissions that only take effect for members with two-factor authentication
+ALTER TABLE permissions ADD COLUMN requires_two_factor boolean NOT NULL DEFAULT false;
+
+ALTER TYPE security_event_kind ADD VALUE 'two_factor_enabled';
+ALTER TYPE security_event_kind ADD VALUE 'two_factor_disabled';
+ALTER TYPE security_event_kind ADD VALUE 'recovery_code_used';
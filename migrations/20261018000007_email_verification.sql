@@ -0,0 +1,14 @@
+-- Existing members are treated as verified, while new members must confirm their email
+ALTER TABLE members ADD COLUMN email_verified boolean NOT NULL DEFAULT true;
+ALTER TABLE members ALTER COLUMN email_verified SET DEFAULT false;
+
+-- Outstanding verification links, at most one per member
+CREATE TABLE email_verifications (
+  member varchar(50) PRIMARY KEY REFERENCES members(email) ON UPDATE CASCADE ON DELETE CASCADE,
+  token_hash varchar(64) NOT NULL UNIQUE,
+  time timestamptz NOT NULL DEFAULT now(),
+  -- whether the member registered with this email, rather than changing to it
+  registration boolean NOT NULL DEFAULT false
+);
+
+ALTER TYPE security_event_kind ADD VALUE 'email_verified';
@@ -0,0 +1,13 @@
+-- Officer positions are kept as terms instead of being deleted when they end,
+-- so past officers can still be looked up by semester
+ALTER TABLE member_roles DROP CONSTRAINT member_roles_pkey;
+ALTER TABLE member_roles ADD COLUMN id bigserial PRIMARY KEY;
+ALTER TABLE member_roles ADD COLUMN started_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE member_roles ADD COLUMN ended_at timestamptz;
+
+-- Existing officers are assumed to have started with the current semester
+UPDATE member_roles SET started_at = semesters.start_date
+FROM semesters WHERE semesters.current;
+
+-- A member can only hold each position once at a time
+CREATE UNIQUE INDEX member_roles_active_index ON member_roles (member, role) WHERE ended_at IS NULL;
@@ -0,0 +1,13 @@
+-- Permissions granted directly to a member for a limited time, instead of through a role
+CREATE TABLE member_permissions (
+  id bigserial PRIMARY KEY,
+  member varchar(50) NOT NULL REFERENCES members(email) ON UPDATE CASCADE ON DELETE CASCADE,
+  permission varchar(40) NOT NULL REFERENCES permissions(name) ON UPDATE CASCADE ON DELETE CASCADE,
+  event_type varchar(32) REFERENCES event_types(name) ON UPDATE CASCADE ON DELETE CASCADE,
+  reason text NOT NULL,
+  granted_by varchar(50) REFERENCES members(email) ON UPDATE CASCADE ON DELETE SET NULL,
+  granted_at timestamptz NOT NULL DEFAULT now(),
+  expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX member_permissions_member_index ON member_permissions (member, expires_at);
@@ -0,0 +1,4 @@
+-- Whether members share their contact details with everyone in the directory,
+-- rather than just with officers
+ALTER TABLE members ADD COLUMN share_phone_number boolean NOT NULL DEFAULT false;
+ALTER TABLE members ADD COLUMN share_location boolean NOT NULL DEFAULT false;
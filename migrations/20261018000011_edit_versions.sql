@@ -0,0 +1,5 @@
+-- Bumped on every edit, so concurrent editors can't silently overwrite each other
+ALTER TABLE events ADD COLUMN version bigint NOT NULL DEFAULT 0;
+ALTER TABLE minutes ADD COLUMN version bigint NOT NULL DEFAULT 0;
+ALTER TABLE songs ADD COLUMN version bigint NOT NULL DEFAULT 0;
+ALTER TABLE uniforms ADD COLUMN version bigint NOT NULL DEFAULT 0;
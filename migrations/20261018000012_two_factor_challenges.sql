@@ -0,0 +1,7 @@
+-- Single sign-on logins by members with two-factor authentication,
+-- waiting for a code before a session is created
+CREATE TABLE two_factor_challenges (
+  token_hash varchar(64) PRIMARY KEY,
+  member varchar(50) NOT NULL REFERENCES members(email) ON UPDATE CASCADE ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT now()
+);
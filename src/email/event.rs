@@ -1,5 +1,6 @@
 use askama::Template;
 use mailgun_v3::email::EmailAddress;
+use sqlx::postgres::PgExecutor;
 use sqlx::PgPool;
 use time::macros::format_description;
 use time::OffsetDateTime;
@@ -67,7 +68,10 @@ pub struct NewEventEmail<'a> {
 }
 
 impl<'a> NewEventEmail<'a> {
-    pub async fn for_event(event: &'a Event, pool: &PgPool) -> anyhow::Result<NewEventEmail<'a>> {
+    pub async fn for_event(
+        event: &'a Event,
+        db: impl PgExecutor<'_>,
+    ) -> anyhow::Result<NewEventEmail<'a>> {
         let uniform_name: Option<String> = sqlx::query_scalar!(
             "SELECT uniforms.name FROM uniforms
              INNER JOIN gigs ON gigs.uniform = uniforms.id
@@ -75,7 +79,7 @@ impl<'a> NewEventEmail<'a> {
              WHERE events.id = $1",
             event.id
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await?;
 
         Ok(Self {
@@ -15,6 +15,7 @@ use crate::util::current_time;
 
 pub mod event;
 pub mod reset_password;
+pub mod verify_email;
 
 pub const MEMBER_LIST_NAME: &str = "Glee Club Members";
 pub const MEMBER_LIST_ADDRESS: &str = "gleeclub@lists.gatech.edu";
@@ -66,41 +67,40 @@ async fn send_emails(from: OffsetDateTime, to: OffsetDateTime, pool: &PgPool) {
     let events = match events_to_notify_about(from, to, pool).await {
         Ok(events) => events,
         Err(error) => {
-            eprintln!(
-                "Failed to load events to send emails about: {:?}",
-                error.message
-            );
+            tracing::error!(error = %error.message, "Failed to load events to send emails about");
             return;
         }
     };
 
     if !events.is_empty() {
-        println!(
-            "Between {} and {}, found {} events to email reminders for: {}",
-            from.format(&Rfc3339).unwrap(),
-            to.format(&Rfc3339).unwrap(),
-            events.len(),
-            events
+        tracing::info!(
+            from = %from.format(&Rfc3339).unwrap(),
+            to = %to.format(&Rfc3339).unwrap(),
+            count = events.len(),
+            events = %events
                 .iter()
                 .map(|event| format!("`{}`", event.name))
                 .collect::<Vec<_>>()
-                .join(", ")
+                .join(", "),
+            "Found events to email reminders for"
         );
     }
 
     for event in events {
         match EventIn48HoursEmail::for_event(&event, pool).await {
             Err(error) => {
-                eprintln!(
-                    "Failed to create email content for upcoming event `{}`: {:?}",
-                    event.name, error
+                tracing::error!(
+                    event = event.id,
+                    error = ?error,
+                    "Failed to create email content for upcoming event"
                 );
             }
             Ok(email) => {
                 if let Err(error) = send_email(email).await {
-                    eprintln!(
-                        "Failed to send email for upcoming event `{}`: {:?}",
-                        event.name, error
+                    tracing::error!(
+                        event = event.id,
+                        error = ?error,
+                        "Failed to send email for upcoming event"
                     );
                 }
             }
@@ -117,7 +117,7 @@ async fn events_to_notify_about(
         Event,
         "SELECT id, name, semester, \"type\", call_time as \"call_time: _\",
              release_time as \"release_time: _\", points, comments, location,
-             gig_count, default_attend
+             gig_count, default_attend, version
          FROM events WHERE call_time >= $1 AND call_time < $2
          ORDER BY call_time",
         from,
@@ -0,0 +1,25 @@
+use askama::Template;
+use mailgun_v3::email::EmailAddress;
+
+use crate::email::Email;
+use crate::models::member::Member;
+
+#[derive(Template)]
+#[template(path = "verify-email.html")]
+pub struct VerifyEmailEmail {
+    pub member: Member,
+    pub token: String,
+}
+
+impl Email for VerifyEmailEmail {
+    fn subject(&self) -> String {
+        "Verify Your GlubHub Email".to_owned()
+    }
+
+    fn address(&self) -> EmailAddress {
+        EmailAddress::name_address(
+            self.member.full_name_inner(),
+            self.member.email.to_owned().parse().unwrap(),
+        )
+    }
+}
@@ -1,7 +1,10 @@
+use async_graphql::{ErrorExtensions, Pos, Value};
 use axum::http::header::ToStrError;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use axum::Json;
 use thiserror::Error;
+use time::Duration;
 
 pub type GreaseResult<T> = Result<T, GreaseError>;
 
@@ -9,21 +12,325 @@ pub type GreaseResult<T> = Result<T, GreaseError>;
 pub enum GreaseError {
     #[error("An error was returned from the database: {0}")]
     DbError(#[from] sqlx::Error),
-    #[error("Error arose from GraphQL API: {0}")]
-    GqlError(String),
+    #[error("Error arose from GraphQL API: {}", .0.message)]
+    GqlError(async_graphql::Error),
     #[error("Invalid token header: {0}")]
     InvalidTokenHeader(ToStrError),
 }
 
+impl GreaseError {
+    /// The same `code` that the GraphQL API puts in error extensions
+    pub fn code(&self) -> &str {
+        match self {
+            GreaseError::DbError(_) => ApiError::INTERNAL,
+            GreaseError::GqlError(error) => match error
+                .extensions
+                .as_ref()
+                .and_then(|extensions| extensions.get("code"))
+            {
+                Some(Value::String(code)) => code,
+                _ => "BAD_REQUEST",
+            },
+            GreaseError::InvalidTokenHeader(_) => "INVALID_TOKEN_HEADER",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self.code() {
+            ApiError::NOT_FOUND => StatusCode::NOT_FOUND,
+            ApiError::UNAUTHENTICATED => StatusCode::UNAUTHORIZED,
+            ApiError::PERMISSION_DENIED => StatusCode::FORBIDDEN,
+            ApiError::CONFLICT => StatusCode::CONFLICT,
+            ApiError::VALIDATION_FAILED | ApiError::RSVP_CLOSED => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::LOCKED_OUT => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::EMAIL_UNVERIFIED => StatusCode::FORBIDDEN,
+            ApiError::TWO_FACTOR_REQUIRED => StatusCode::UNAUTHORIZED,
+            ApiError::INTERNAL => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Keeps the extensions of errors from the GraphQL API
+    pub fn into_graphql_error(self) -> async_graphql::Error {
+        let code = self.code().to_owned();
+        match self {
+            GreaseError::GqlError(error) => error,
+            other => async_graphql::Error::new(other.to_string())
+                .extend_with(|_, extensions| extensions.set("code", code)),
+        }
+    }
+}
+
 impl IntoResponse for GreaseError {
     fn into_response(self) -> Response {
-        let status = match &self {
-            &GreaseError::DbError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            &GreaseError::GqlError(_) | &GreaseError::InvalidTokenHeader(_) => {
-                StatusCode::BAD_REQUEST
+        let status = self.status();
+        let mut error = self.into_graphql_error().into_server_error(Pos::default());
+        // The error didn't come from anywhere in a query
+        error.locations.clear();
+
+        (
+            status,
+            Json(async_graphql::Response::from_errors(vec![error])),
+        )
+            .into_response()
+    }
+}
+
+/// An error that clients can tell apart by the `code` in its extensions
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    /// Nothing was found with the given key
+    NotFound {
+        entity: &'static str,
+        field: &'static str,
+        value: String,
+    },
+    /// The request needs a valid login
+    Unauthenticated(String),
+    /// The user isn't allowed to do this, usually for lack of a permission
+    /// that may be for a type of event
+    PermissionDenied {
+        permission: Option<&'static str>,
+        event_type: Option<String>,
+        message: String,
+    },
+    /// The input was well-formed, but not allowed
+    Validation(String),
+    /// The change clashes with the current state of the data
+    Conflict(String),
+    /// The record was edited since the client loaded the version it sent
+    StaleVersion {
+        entity: &'static str,
+        id: i64,
+        current_version: i64,
+    },
+    /// The member can't RSVP for or confirm the event
+    RsvpClosed { event: i64, reason: String },
+    /// A request only sent the hash of a query that isn't registered
+    PersistedQueryNotFound,
+    /// Too many failed logins, so the account or address has to wait this many seconds
+    LockedOut { retry_after: i64 },
+    /// The member has to verify their email address first
+    EmailUnverified,
+    /// The login needs a two-factor authentication code as well
+    TwoFactorRequired,
+    /// Something went wrong on the server that the client can't fix
+    Internal(String),
+}
+
+impl ApiError {
+    pub const NOT_FOUND: &'static str = "NOT_FOUND";
+    pub const UNAUTHENTICATED: &'static str = "UNAUTHENTICATED";
+    pub const PERMISSION_DENIED: &'static str = "PERMISSION_DENIED";
+    pub const VALIDATION_FAILED: &'static str = "VALIDATION_FAILED";
+    pub const CONFLICT: &'static str = "CONFLICT";
+    pub const RSVP_CLOSED: &'static str = "RSVP_CLOSED";
+    pub const PERSISTED_QUERY_NOT_FOUND: &'static str = "PERSISTED_QUERY_NOT_FOUND";
+    pub const LOCKED_OUT: &'static str = "LOCKED_OUT";
+    pub const EMAIL_UNVERIFIED: &'static str = "EMAIL_UNVERIFIED";
+    pub const TWO_FACTOR_REQUIRED: &'static str = "TWO_FACTOR_REQUIRED";
+    pub const INTERNAL: &'static str = "INTERNAL_SERVER_ERROR";
+
+    pub fn not_found(entity: &'static str, field: &'static str, value: impl ToString) -> Self {
+        Self::NotFound {
+            entity,
+            field,
+            value: value.to_string(),
+        }
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::Validation(message.into())
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::Conflict(message.into())
+    }
+
+    pub fn locked_out(wait: Duration) -> Self {
+        Self::LockedOut {
+            retry_after: wait.whole_seconds().max(1),
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::Internal(message.into())
+    }
+
+    pub fn stale_version(entity: &'static str, id: i64, current_version: i64) -> Self {
+        Self::StaleVersion {
+            entity,
+            id,
+            current_version,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound { .. } => Self::NOT_FOUND,
+            Self::Unauthenticated(_) => Self::UNAUTHENTICATED,
+            Self::PermissionDenied { .. } => Self::PERMISSION_DENIED,
+            Self::Validation(_) => Self::VALIDATION_FAILED,
+            Self::Conflict(_) | Self::StaleVersion { .. } => Self::CONFLICT,
+            Self::RsvpClosed { .. } => Self::RSVP_CLOSED,
+            Self::PersistedQueryNotFound => Self::PERSISTED_QUERY_NOT_FOUND,
+            Self::LockedOut { .. } => Self::LOCKED_OUT,
+            Self::EmailUnverified => Self::EMAIL_UNVERIFIED,
+            Self::TwoFactorRequired => Self::TWO_FACTOR_REQUIRED,
+            Self::Internal(_) => Self::INTERNAL,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::NotFound {
+                entity,
+                field,
+                value,
+            } => format!("No {} with {} {}", entity, field, value),
+            Self::StaleVersion {
+                entity,
+                id,
+                current_version,
+            } => format!(
+                "The {} with ID {} was changed by someone else and is now at version {}",
+                entity, id, current_version
+            ),
+            Self::LockedOut { retry_after } if *retry_after < 60 => format!(
+                "Too many attempts, please try again in {} seconds",
+                retry_after
+            ),
+            Self::LockedOut { retry_after } => format!(
+                "Too many attempts, please try again in {} minutes",
+                (retry_after + 59) / 60
+            ),
+            Self::EmailUnverified => "Please verify your email address first".to_owned(),
+            Self::TwoFactorRequired => "A two-factor authentication code is required".to_owned(),
+            Self::Unauthenticated(message)
+            | Self::PermissionDenied { message, .. }
+            | Self::Validation(message)
+            | Self::Conflict(message)
+            | Self::Internal(message)
+            | Self::RsvpClosed {
+                reason: message, ..
+            } => message.clone(),
+            // Apollo clients recognize this message
+            Self::PersistedQueryNotFound => "PersistedQueryNotFound".to_owned(),
+        }
+    }
+}
+
+// `ApiError` deliberately doesn't implement `Display`, since `?` would then convert
+// it with async-graphql's blanket impl and drop the extensions
+impl From<ApiError> for async_graphql::Error {
+    fn from(error: ApiError) -> Self {
+        error.extend()
+    }
+}
+
+impl ErrorExtensions for ApiError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.message()).extend_with(|_, extensions| {
+            extensions.set("code", self.code());
+            match self {
+                Self::NotFound {
+                    entity,
+                    field,
+                    value,
+                } => {
+                    extensions.set("entity", *entity);
+                    extensions.set("field", *field);
+                    extensions.set("value", value.as_str());
+                }
+                Self::PermissionDenied {
+                    permission,
+                    event_type,
+                    ..
+                } => {
+                    if let Some(permission) = permission {
+                        extensions.set("permission", *permission);
+                    }
+                    if let Some(event_type) = event_type {
+                        extensions.set("eventType", event_type.as_str());
+                    }
+                }
+                Self::StaleVersion {
+                    entity,
+                    id,
+                    current_version,
+                } => {
+                    extensions.set("entity", *entity);
+                    extensions.set("id", *id);
+                    extensions.set("currentVersion", *current_version);
+                }
+                Self::RsvpClosed { event, .. } => extensions.set("event", *event),
+                Self::LockedOut { retry_after } => extensions.set("retryAfter", *retry_after),
+                Self::Unauthenticated(_)
+                | Self::Validation(_)
+                | Self::Conflict(_)
+                | Self::PersistedQueryNotFound
+                | Self::EmailUnverified
+                | Self::TwoFactorRequired
+                | Self::Internal(_) => {}
             }
-        };
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::Value;
+    use axum::http::StatusCode;
+    use time::Duration;
+
+    use crate::error::{ApiError, GreaseError};
+
+    #[test]
+    fn api_errors_keep_their_code_and_fields() {
+        let error: async_graphql::Error = ApiError::not_found("event", "ID", 5).into();
+        let extensions = error.extensions.as_ref().unwrap();
+
+        assert_eq!(error.message, "No event with ID 5");
+        assert_eq!(
+            extensions.get("code"),
+            Some(&Value::from(ApiError::NOT_FOUND))
+        );
+        assert_eq!(extensions.get("entity"), Some(&Value::from("event")));
+        assert_eq!(extensions.get("value"), Some(&Value::from("5")));
+        assert_eq!(GreaseError::GqlError(error).status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn stale_versions_are_conflicts_with_the_current_version() {
+        let error: async_graphql::Error = ApiError::stale_version("song", 3, 7).into();
+        let extensions = error.extensions.as_ref().unwrap();
+
+        assert_eq!(
+            extensions.get("code"),
+            Some(&Value::from(ApiError::CONFLICT))
+        );
+        assert_eq!(extensions.get("id"), Some(&Value::from(3)));
+        assert_eq!(extensions.get("currentVersion"), Some(&Value::from(7)));
+        assert_eq!(GreaseError::GqlError(error).status(), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn lockouts_say_when_to_retry() {
+        let error: async_graphql::Error = ApiError::locked_out(Duration::minutes(5)).into();
+        let extensions = error.extensions.as_ref().unwrap();
 
-        (status, self.to_string()).into_response()
+        assert_eq!(
+            error.message,
+            "Too many attempts, please try again in 5 minutes"
+        );
+        assert_eq!(
+            extensions.get("code"),
+            Some(&Value::from(ApiError::LOCKED_OUT))
+        );
+        assert_eq!(extensions.get("retryAfter"), Some(&Value::from(300)));
+        assert_eq!(
+            GreaseError::GqlError(error).status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
     }
 }
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextParseQuery};
+use async_graphql::parser::types::{ExecutableDocument, OperationType, Selection};
+use async_graphql::{ServerError, ServerResult, Variables};
+use sqlx::PgPool;
+
+use crate::graphql::ClientInfo;
+use crate::models::member::security_event::{SecurityEvent, SecurityEventKind};
+use crate::models::member::session::Session;
+
+/// Writes every mutation made while impersonating a member to the security log
+pub struct ImpersonationAudit;
+
+impl ExtensionFactory for ImpersonationAudit {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ImpersonationAudit)
+    }
+}
+
+#[async_trait::async_trait]
+impl Extension for ImpersonationAudit {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+
+        if let Some(Session {
+            member,
+            impersonated_by: Some(impersonator),
+            ..
+        }) = ctx.data_opt::<Session>()
+        {
+            let mutations = mutation_fields(&document);
+            if !mutations.is_empty() {
+                let pool = ctx.data_unchecked::<PgPool>();
+                let client = ctx.data_unchecked::<ClientInfo>();
+                // Variables aren't logged since they can contain passwords
+                SecurityEvent::record_by(
+                    SecurityEventKind::ImpersonatedMutation,
+                    member,
+                    Some(impersonator),
+                    Some(&client.ip),
+                    Some(&mutations.join(", ")),
+                    pool,
+                )
+                .await
+                .map_err(|err| ServerError::new(err.message, None))?;
+            }
+        }
+
+        Ok(document)
+    }
+}
+
+/// The names of the top-level fields of all mutations in the document
+fn mutation_fields(document: &ExecutableDocument) -> Vec<String> {
+    document
+        .operations
+        .iter()
+        .filter(|(_name, operation)| operation.node.ty == OperationType::Mutation)
+        .flat_map(|(_name, operation)| operation.node.selection_set.node.items.iter())
+        .map(|selection| match &selection.node {
+            Selection::Field(field) => field.node.name.node.to_string(),
+            Selection::FragmentSpread(spread) => format!("...{}", spread.node.fragment_name.node),
+            Selection::InlineFragment(_) => "...".to_owned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::parser::parse_query;
+
+    use crate::graphql::audit::mutation_fields;
+
+    #[test]
+    fn only_mutation_fields_are_audited() {
+        let query = parse_query("query { user { email } }").unwrap();
+        assert!(mutation_fields(&query).is_empty());
+
+        let mutation = parse_query(
+            "mutation Update { updateAttendance(eventId: 1, email: \"a\", update: {}) { shouldAttend } \
+             excuseUnconfirmedForEvent(eventId: 1) }",
+        )
+        .unwrap();
+        assert_eq!(
+            mutation_fields(&mutation),
+            vec!["updateAttendance", "excuseUnconfirmedForEvent"]
+        );
+    }
+}
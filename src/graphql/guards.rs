@@ -1,9 +1,13 @@
-use async_graphql::{Context, Guard, Result};
+use async_graphql::{Context, Guard, Result, SimpleObject};
 use sqlx::PgPool;
 
+use crate::error::ApiError;
+use crate::models::api_key::ApiKey;
+use crate::models::member::session::Session;
 use crate::models::member::Member;
-use crate::models::permissions::MemberPermission;
+use crate::models::permissions::{MemberPermission, PermissionType};
 
+/// Requires a member to be logged in, which requests made with an API key never are
 pub struct LoggedIn;
 
 #[async_trait::async_trait]
@@ -11,98 +15,520 @@ impl Guard for LoggedIn {
     async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
         if ctx.data_opt::<Member>().is_some() {
             Ok(())
+        } else if ctx.data_opt::<ApiKey>().is_some() {
+            Err(ApiError::Unauthenticated(
+                "API keys can only be used for actions covered by their permissions".to_owned(),
+            )
+            .into())
         } else {
-            Err("User must be logged in".into())
+            Err(ApiError::Unauthenticated("User must be logged in".to_owned()).into())
+        }
+    }
+}
+
+/// Keeps officers logged in as another member from changing how that member logs in
+pub struct NotImpersonating;
+
+#[async_trait::async_trait]
+impl Guard for NotImpersonating {
+    async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        match ctx.data_opt::<Session>() {
+            Some(session) if session.impersonated_by.is_some() => Err(ApiError::PermissionDenied {
+                permission: None,
+                event_type: None,
+                message: "Can't change another member's login while logged in as them".to_owned(),
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The permissions held by the current request, resolved once before the query runs
+pub struct GrantedPermissions(pub Vec<MemberPermission>);
+
+impl GrantedPermissions {
+    /// Whether any of these permissions satisfies the given one
+    pub fn includes(&self, permission: &Permission) -> bool {
+        self.0.iter().any(|held| permission.is_satisfied_by(held))
+    }
+
+    /// Uses the API key's permissions instead of the member's if one was used
+    pub async fn resolve(
+        user: Option<&Member>,
+        api_key: Option<&ApiKey>,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        if let Some(api_key) = api_key {
+            Ok(Self(api_key.permissions.clone()))
+        } else if let Some(user) = user {
+            MemberPermission::for_member(&user.email, pool)
+                .await
+                .map(Self)
+        } else {
+            Ok(Self(Vec::new()))
+        }
+    }
+}
+
+/// Whether the current user can do something, and why
+#[derive(SimpleObject)]
+pub struct Decision {
+    /// Whether the action is allowed
+    pub allowed: bool,
+    /// Why the action is or isn't allowed
+    pub reason: String,
+    /// The permission that allowed the action, including the role or grant it came from
+    pub permission: Option<MemberPermission>,
+
+    #[graphql(skip)]
+    pub denial: Option<ApiError>,
+}
+
+impl Decision {
+    pub fn allow(reason: String, permission: Option<MemberPermission>) -> Self {
+        Self {
+            allowed: true,
+            reason,
+            permission,
+            denial: None,
+        }
+    }
+
+    pub fn deny(denial: ApiError) -> Self {
+        Self {
+            allowed: false,
+            reason: denial.message(),
+            permission: None,
+            denial: Some(denial),
+        }
+    }
+
+    /// Turns a denial into an error with its reason
+    pub fn into_result(self) -> Result<()> {
+        if self.allowed {
+            Ok(())
+        } else if let Some(denial) = self.denial {
+            Err(denial.into())
+        } else {
+            Err(self.reason.into())
         }
     }
 }
 
 pub struct Permission {
     name: &'static str,
+    r#type: PermissionType,
+    description: &'static str,
     event_type: Option<String>,
 }
 
 impl Permission {
-    const fn new(name: &'static str) -> Self {
+    const fn new(name: &'static str, r#type: PermissionType, description: &'static str) -> Self {
         Self {
             name,
+            r#type,
+            description,
             event_type: None,
         }
     }
 
+    /// The permission from the catalog with the given name
+    pub fn with_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|permission| permission.name == name)
+            .map(|permission| Self::new(permission.name, permission.r#type, permission.description))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Whether the permission can be limited to a type of event
+    pub fn r#type(&self) -> PermissionType {
+        self.r#type
+    }
+
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+
     pub fn for_type(self, event_type: impl Into<String>) -> Self {
         Self {
-            name: self.name,
             event_type: Some(event_type.into()),
+            ..self
         }
     }
 
-    pub async fn granted_to(&self, member: &str, pool: &PgPool) -> Result<bool> {
-        let permissions = MemberPermission::for_member(member, pool).await?;
+    fn is_satisfied_by(&self, permission: &MemberPermission) -> bool {
+        permission.name == self.name
+            && (permission.event_type.is_none() || permission.event_type == self.event_type)
+    }
 
-        Ok(permissions.iter().any(|permission| {
-            permission.name == self.name
-                && (permission.event_type.is_none() || permission.event_type == self.event_type)
-        }))
+    /// The first of the current request's permissions that satisfies this one
+    fn satisfied_by<'a>(&self, ctx: &'a Context<'_>) -> Option<&'a MemberPermission> {
+        ctx.data_opt::<GrantedPermissions>().and_then(|granted| {
+            granted
+                .0
+                .iter()
+                .find(|permission| self.is_satisfied_by(permission))
+        })
     }
 
-    pub async fn ensure_granted_to(&self, member: &str, pool: &PgPool) -> Result<()> {
-        if self.granted_to(member, pool).await? {
-            Ok(())
+    /// Whether the current request has this permission
+    pub fn granted(&self, ctx: &Context<'_>) -> bool {
+        self.satisfied_by(ctx).is_some()
+    }
+
+    pub fn ensure_granted(&self, ctx: &Context<'_>) -> Result<()> {
+        self.decide(ctx).into_result()
+    }
+
+    /// Whether the current request has this permission, and where it came from
+    pub fn decide(&self, ctx: &Context<'_>) -> Decision {
+        if let Some(permission) = self.satisfied_by(ctx) {
+            Decision::allow(
+                match (
+                    &permission.role,
+                    permission.grant_id,
+                    ctx.data_opt::<ApiKey>(),
+                ) {
+                    (_, _, Some(api_key)) => format!("Granted by API key \"{}\"", api_key.name),
+                    (Some(role), _, _) => format!("Granted by the {} role", role),
+                    (_, Some(grant_id), _) => format!("Granted directly (grant {})", grant_id),
+                    _ => "Granted".to_owned(),
+                },
+                Some(permission.clone()),
+            )
         } else {
-            Err(self.error())
+            Decision::deny(self.denied())
+        }
+    }
+
+    /// Like `decide`, but members can always act on themselves
+    pub fn decide_for_member(&self, ctx: &Context<'_>, member: &str) -> Decision {
+        match ctx.data_opt::<Member>() {
+            Some(user) if user.email == member => {
+                Decision::allow("Members can always do this for themselves".to_owned(), None)
+            }
+            _ => self.decide(ctx),
         }
     }
 
     pub fn error(&self) -> async_graphql::Error {
-        format!("Permission {} required", self.name).into()
+        self.denied().into()
     }
 
-    pub const PROCESS_GIG_REQUESTS: Self = Self::new("process-gig-requests");
-    pub const PROCESS_ABSENCE_REQUESTS: Self = Self::new("process-absence-requests");
+    pub fn denied(&self) -> ApiError {
+        self.denied_because(format!("Permission {} required", self.name))
+    }
 
-    pub const EDIT_REPERTOIRE: Self = Self::new("edit-repertoire");
+    /// A denial for this permission with a more specific reason
+    pub fn denied_because(&self, message: impl Into<String>) -> ApiError {
+        ApiError::PermissionDenied {
+            permission: Some(self.name),
+            event_type: self.event_type.clone(),
+            message: message.into(),
+        }
+    }
+
+    pub const PROCESS_GIG_REQUESTS: Self = Self::new(
+        "process-gig-requests",
+        PermissionType::Static,
+        "Accept or dismiss gig requests from the public",
+    );
+    pub const PROCESS_ABSENCE_REQUESTS: Self = Self::new(
+        "process-absence-requests",
+        PermissionType::Static,
+        "Approve or deny absence requests",
+    );
 
-    pub const EDIT_LINKS: Self = Self::new("edit-links");
-    pub const EDIT_UNIFORMS: Self = Self::new("edit-uniforms");
+    pub const EDIT_REPERTOIRE: Self = Self::new(
+        "edit-repertoire",
+        PermissionType::Static,
+        "Add, edit, and remove songs and their links",
+    );
 
-    pub const EDIT_SEMESTER: Self = Self::new("edit-semester");
-    pub const EDIT_PERMISSIONS: Self = Self::new("edit-permissions");
-    pub const EDIT_OFFICERS: Self = Self::new("edit-officers");
+    pub const EDIT_LINKS: Self = Self::new(
+        "edit-links",
+        PermissionType::Static,
+        "Edit the document links shown to members",
+    );
+    pub const EDIT_UNIFORMS: Self = Self::new(
+        "edit-uniforms",
+        PermissionType::Static,
+        "Add, edit, and remove uniforms",
+    );
 
-    pub const VIEW_TRANSACTIONS: Self = Self::new("view-transactions");
-    pub const EDIT_TRANSACTION: Self = Self::new("edit-transaction");
+    pub const EDIT_SEMESTER: Self = Self::new(
+        "edit-semester",
+        PermissionType::Static,
+        "Create and edit semesters and change the current semester",
+    );
+    pub const EDIT_PERMISSIONS: Self = Self::new(
+        "edit-permissions",
+        PermissionType::Static,
+        "Change which permissions each officer role grants",
+    );
+    pub const EDIT_OFFICERS: Self = Self::new(
+        "edit-officers",
+        PermissionType::Static,
+        "Assign and remove officer positions",
+    );
 
-    pub const EDIT_MINUTES: Self = Self::new("edit-minutes");
-    pub const VIEW_COMPLETE_MINUTES: Self = Self::new("view-complete-minutes");
+    pub const VIEW_TRANSACTIONS: Self = Self::new(
+        "view-transactions",
+        PermissionType::Static,
+        "View all members' transactions",
+    );
+    pub const EDIT_TRANSACTION: Self = Self::new(
+        "edit-transaction",
+        PermissionType::Static,
+        "Add and resolve transactions and edit fees",
+    );
 
-    pub const EDIT_USER: Self = Self::new("edit-user");
-    pub const SWITCH_USER: Self = Self::new("switch-user");
-    pub const DELETE_USER: Self = Self::new("delete-user");
+    pub const EDIT_MINUTES: Self = Self::new(
+        "edit-minutes",
+        PermissionType::Static,
+        "Write and edit meeting minutes",
+    );
+    pub const VIEW_COMPLETE_MINUTES: Self = Self::new(
+        "view-complete-minutes",
+        PermissionType::Static,
+        "View the private notes in meeting minutes",
+    );
+
+    pub const EDIT_USER: Self = Self::new(
+        "edit-user",
+        PermissionType::Static,
+        "Edit other members' profiles and accounts",
+    );
+    pub const SWITCH_USER: Self = Self::new(
+        "switch-user",
+        PermissionType::Static,
+        "Log in as another member",
+    );
+    pub const DELETE_USER: Self = Self::new(
+        "delete-user",
+        PermissionType::Static,
+        "Delete members' accounts",
+    );
     // pub const VIEW_USERS: Self = Self::new("view-users");
-    pub const VIEW_USER_PRIVATE_DETAILS: Self = Self::new("view-user-private-details");
+    pub const VIEW_USER_PRIVATE_DETAILS: Self = Self::new(
+        "view-user-private-details",
+        PermissionType::Static,
+        "View other members' grades and transactions",
+    );
 
-    pub const CREATE_EVENT: Self = Self::new("create-event");
-    pub const MODIFY_EVENT: Self = Self::new("modify-event");
+    pub const CREATE_EVENT: Self =
+        Self::new("create-event", PermissionType::Event, "Create events");
+    pub const MODIFY_EVENT: Self = Self::new("modify-event", PermissionType::Event, "Edit events");
     // pub const EDIT_ALL_EVENTS: Self = Self::new("edit-all-events");
-    pub const DELETE_EVENT: Self = Self::new("delete-event");
+    pub const DELETE_EVENT: Self =
+        Self::new("delete-event", PermissionType::Event, "Delete events");
+
+    pub const EDIT_ATTENDANCE: Self = Self::new(
+        "edit-attendance",
+        PermissionType::Event,
+        "Edit anyone's attendance at events",
+    );
+    pub const EDIT_ATTENDANCE_OWN_SECTION: Self = Self::new(
+        "edit-attendance-own-section",
+        PermissionType::Event,
+        "Edit attendance for members of your own section",
+    );
 
-    pub const EDIT_ATTENDANCE: Self = Self::new("edit-attendance");
-    pub const EDIT_ATTENDANCE_OWN_SECTION: Self = Self::new("edit-attendance-own-section");
+    pub const EDIT_CARPOOLS: Self = Self::new(
+        "edit-carpool",
+        PermissionType::Static,
+        "Edit the carpools for events",
+    );
 
-    pub const EDIT_CARPOOLS: Self = Self::new("edit-carpool");
+    /// Every permission checked by the API, which must all exist in the database
+    pub const ALL: &[Self] = &[
+        Self::PROCESS_GIG_REQUESTS,
+        Self::PROCESS_ABSENCE_REQUESTS,
+        Self::EDIT_REPERTOIRE,
+        Self::EDIT_LINKS,
+        Self::EDIT_UNIFORMS,
+        Self::EDIT_SEMESTER,
+        Self::EDIT_PERMISSIONS,
+        Self::EDIT_OFFICERS,
+        Self::VIEW_TRANSACTIONS,
+        Self::EDIT_TRANSACTION,
+        Self::EDIT_MINUTES,
+        Self::VIEW_COMPLETE_MINUTES,
+        Self::EDIT_USER,
+        Self::SWITCH_USER,
+        Self::DELETE_USER,
+        Self::VIEW_USER_PRIVATE_DETAILS,
+        Self::CREATE_EVENT,
+        Self::MODIFY_EVENT,
+        Self::DELETE_EVENT,
+        Self::EDIT_ATTENDANCE,
+        Self::EDIT_ATTENDANCE_OWN_SECTION,
+        Self::EDIT_CARPOOLS,
+    ];
 }
 
+/// Passes for a logged in member or an API key with the permission
 #[async_trait::async_trait]
 impl Guard for Permission {
     async fn check(&self, ctx: &Context<'_>) -> Result<()> {
-        if let Some(user) = ctx.data_opt::<Member>() {
-            let pool: &PgPool = ctx.data_unchecked();
-            if self.granted_to(&user.email, pool).await? {
-                return Ok(());
-            }
+        if ctx.data_opt::<Member>().is_none() && ctx.data_opt::<ApiKey>().is_none() {
+            return Err(ApiError::Unauthenticated("User must be logged in".to_owned()).into());
         }
 
-        Err(self.error())
+        self.ensure_granted(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{Request, Value};
+    use time::OffsetDateTime;
+
+    use crate::error::ApiError;
+    use crate::graphql::guards::{GrantedPermissions, Permission};
+    use crate::graphql::{build_schema, ClientInfo, QueryLimits};
+    use crate::models::api_key::ApiKey;
+    use crate::models::member::session::Session;
+    use crate::models::member::Member;
+    use crate::models::permissions::MemberPermission;
+    use crate::util::with_cleanup;
+
+    fn api_key_request(query: &str) -> Request {
+        let permissions = vec![MemberPermission {
+            name: Permission::EDIT_MINUTES.name().to_owned(),
+            event_type: None,
+            role: None,
+            grant_id: None,
+        }];
+        let api_key = ApiKey {
+            id: 1,
+            name: "Minutes Bot".to_owned(),
+            permissions: permissions.clone(),
+            created_by: "officer@gatech.edu".to_owned(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            expires_at: OffsetDateTime::UNIX_EPOCH,
+            last_used: None,
+        };
+
+        Request::new(query)
+            .data(api_key)
+            .data(GrantedPermissions(permissions))
+    }
+
+    async fn error_code(request: Request) -> Option<Value> {
+        let response = build_schema(&QueryLimits::DEFAULT).execute(request).await;
+        response.errors.first().and_then(|error| {
+            error
+                .extensions
+                .as_ref()
+                .and_then(|extensions| extensions.get("code").cloned())
+        })
+    }
+
+    #[tokio::test]
+    async fn api_keys_do_not_act_as_their_creator() {
+        let response = build_schema(&QueryLimits::DEFAULT)
+            .execute(api_key_request("{ user { email } }"))
+            .await;
+        assert!(response.errors.is_empty());
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({ "user": null })
+        );
+
+        assert_eq!(
+            error_code(api_key_request("{ sessions { id } }")).await,
+            Some(Value::from(ApiError::UNAUTHENTICATED))
+        );
+    }
+
+    #[tokio::test]
+    async fn api_keys_are_limited_to_their_permissions() {
+        assert_eq!(
+            error_code(api_key_request("{ lockouts { attempts } }")).await,
+            Some(Value::from(ApiError::PERMISSION_DENIED))
+        );
+        assert_eq!(
+            error_code(Request::new("{ lockouts { attempts } }")).await,
+            Some(Value::from(ApiError::UNAUTHENTICATED))
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn impersonators_cannot_change_the_members_login() {
+        let cleanup = ["DELETE FROM members WHERE email LIKE '%@impersonation.test'"];
+        with_cleanup(&cleanup, |pool| async move {
+            for email in ["member@impersonation.test", "officer@impersonation.test"] {
+                sqlx::query(
+                    "INSERT INTO members (email, first_name, last_name, pass_hash, phone_number, location)
+                     VALUES ($1, 'Ima', 'Person', '', '', '')",
+                )
+                .bind(email)
+                .execute(&pool)
+                .await
+                .unwrap();
+            }
+            let member = Member::with_email("member@impersonation.test", &pool)
+                .await
+                .unwrap();
+            let session = Session {
+                id: 2,
+                user_agent: None,
+                member: member.email.clone(),
+                key: String::new(),
+                created_at: OffsetDateTime::UNIX_EPOCH,
+                last_seen: OffsetDateTime::UNIX_EPOCH,
+                impersonated_by: Some("officer@impersonation.test".to_owned()),
+                original_session: Some(1),
+            };
+            let request = Request::new("mutation { beginTwoFactorEnrollment { secret } }")
+                .data(pool.clone())
+                .data(ClientInfo {
+                    user_agent: None,
+                    ip: "127.0.0.1".to_owned(),
+                })
+                .data(member)
+                .data(session)
+                .data(GrantedPermissions(Vec::new()));
+            let code = error_code(request).await;
+
+            assert_eq!(code, Some(Value::from(ApiError::PERMISSION_DENIED)));
+        })
+        .await;
+    }
+
+    #[test]
+    fn granted_permissions_only_include_what_they_cover() {
+        let granted = GrantedPermissions(vec![MemberPermission {
+            name: Permission::EDIT_ATTENDANCE.name().to_owned(),
+            event_type: Some("Rehearsal".to_owned()),
+            role: None,
+            grant_id: None,
+        }]);
+
+        assert!(granted.includes(&Permission::EDIT_ATTENDANCE.for_type("Rehearsal")));
+        assert!(!granted.includes(&Permission::EDIT_ATTENDANCE.for_type("Tutti Gig")));
+        assert!(!granted.includes(&Permission::EDIT_ATTENDANCE));
+        assert!(!granted.includes(&Permission::EDIT_MINUTES));
+    }
+
+    #[test]
+    fn catalog_names_are_unique_and_found_by_name() {
+        for (index, permission) in Permission::ALL.iter().enumerate() {
+            assert!(Permission::ALL[..index]
+                .iter()
+                .all(|other| other.name() != permission.name()));
+            assert_eq!(
+                Permission::with_name(permission.name()).map(|found| found.name()),
+                Some(permission.name())
+            );
+        }
+        assert!(Permission::with_name("not-a-permission").is_none());
     }
 }
@@ -0,0 +1,305 @@
+//! Batched loading for resolvers that would otherwise run one query per row
+
+use std::collections::HashMap;
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{Context, Data, Error, Result};
+use sqlx::PgPool;
+
+use crate::error::ApiError;
+use crate::models::event::gig::Gig;
+use crate::models::event::uniform::Uniform;
+use crate::models::event::Event;
+use crate::models::member::active_semester::ActiveSemester;
+use crate::models::member::Member;
+use crate::models::song::{Pitch, Song, SongMode};
+
+/// Adds the loaders to a request's data. They don't cache, so they are
+/// safe to keep for a whole subscription
+pub fn register(data: &mut Data, pool: &PgPool) {
+    data.insert(DataLoader::new(MemberLoader(pool.clone()), tokio::spawn));
+    data.insert(DataLoader::new(EventLoader(pool.clone()), tokio::spawn));
+    data.insert(DataLoader::new(GigLoader(pool.clone()), tokio::spawn));
+    data.insert(DataLoader::new(UniformLoader(pool.clone()), tokio::spawn));
+    data.insert(DataLoader::new(SetlistLoader(pool.clone()), tokio::spawn));
+    data.insert(DataLoader::new(PassengerLoader(pool.clone()), tokio::spawn));
+    data.insert(DataLoader::new(
+        ActiveSemesterLoader(pool.clone()),
+        tokio::spawn,
+    ));
+}
+
+pub async fn load_member(ctx: &Context<'_>, email: &str) -> Result<Member> {
+    ctx.data_unchecked::<DataLoader<MemberLoader>>()
+        .load_one(email.to_owned())
+        .await?
+        .ok_or_else(|| ApiError::not_found("member", "email", email).into())
+}
+
+pub async fn load_event(ctx: &Context<'_>, id: i64) -> Result<Event> {
+    ctx.data_unchecked::<DataLoader<EventLoader>>()
+        .load_one(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("event", "ID", id).into())
+}
+
+/// The gig details for an event, if it is a gig
+pub async fn load_gig(ctx: &Context<'_>, event_id: i64) -> Result<Option<Gig>> {
+    ctx.data_unchecked::<DataLoader<GigLoader>>()
+        .load_one(event_id)
+        .await
+}
+
+pub async fn load_uniform(ctx: &Context<'_>, id: i64) -> Result<Uniform> {
+    ctx.data_unchecked::<DataLoader<UniformLoader>>()
+        .load_one(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("uniform", "ID", id).into())
+}
+
+/// The songs to be performed at an event, in order
+pub async fn load_setlist(ctx: &Context<'_>, event_id: i64) -> Result<Vec<Song>> {
+    ctx.data_unchecked::<DataLoader<SetlistLoader>>()
+        .load_one(event_id)
+        .await
+        .map(Option::unwrap_or_default)
+}
+
+/// The members riding in a carpool
+pub async fn load_passengers(ctx: &Context<'_>, carpool_id: i64) -> Result<Vec<Member>> {
+    ctx.data_unchecked::<DataLoader<PassengerLoader>>()
+        .load_one(carpool_id)
+        .await
+        .map(Option::unwrap_or_default)
+}
+
+/// The member's registration for a semester, if they were active during it
+pub async fn load_active_semester(
+    ctx: &Context<'_>,
+    member: &str,
+    semester: &str,
+) -> Result<Option<ActiveSemester>> {
+    ctx.data_unchecked::<DataLoader<ActiveSemesterLoader>>()
+        .load_one((member.to_owned(), semester.to_owned()))
+        .await
+}
+
+pub struct MemberLoader(PgPool);
+
+#[async_trait::async_trait]
+impl Loader<String> for MemberLoader {
+    type Value = Member;
+    type Error = Error;
+
+    async fn load(&self, emails: &[String]) -> Result<HashMap<String, Member>> {
+        let members = sqlx::query_as!(
+            Member,
+            "SELECT email, first_name, preferred_name, last_name, phone_number, picture, passengers,
+                 location, on_campus, about, major, minor, hometown,
+                 arrived_at_tech, gateway_drug, conflicts, dietary_restrictions, pass_hash,
+                 email_verified, share_phone_number, share_location
+             FROM members WHERE email = ANY($1)",
+            emails
+        )
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(members
+            .into_iter()
+            .map(|member| (member.email.clone(), member))
+            .collect())
+    }
+}
+
+pub struct EventLoader(PgPool);
+
+#[async_trait::async_trait]
+impl Loader<i64> for EventLoader {
+    type Value = Event;
+    type Error = Error;
+
+    async fn load(&self, ids: &[i64]) -> Result<HashMap<i64, Event>> {
+        let events = sqlx::query_as!(
+            Event,
+            "SELECT id, name, semester, type, call_time as \"call_time: _\",
+                 release_time as \"release_time: _\", points, comments, location,
+                 gig_count, default_attend, version
+             FROM events WHERE id = ANY($1)",
+            ids
+        )
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(events.into_iter().map(|event| (event.id, event)).collect())
+    }
+}
+
+/// Loads gigs by the ID of their event
+pub struct GigLoader(PgPool);
+
+#[async_trait::async_trait]
+impl Loader<i64> for GigLoader {
+    type Value = Gig;
+    type Error = Error;
+
+    async fn load(&self, event_ids: &[i64]) -> Result<HashMap<i64, Gig>> {
+        let gigs = sqlx::query_as!(
+            Gig,
+            "SELECT event, performance_time as \"performance_time: _\", contact_name, contact_email,
+                 contact_phone, price, public as \"public: bool\", summary, description, uniform
+             FROM gigs WHERE event = ANY($1)",
+            event_ids
+        )
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(gigs.into_iter().map(|gig| (gig.event, gig)).collect())
+    }
+}
+
+pub struct UniformLoader(PgPool);
+
+#[async_trait::async_trait]
+impl Loader<i64> for UniformLoader {
+    type Value = Uniform;
+    type Error = Error;
+
+    async fn load(&self, ids: &[i64]) -> Result<HashMap<i64, Uniform>> {
+        let uniforms = sqlx::query_as!(
+            Uniform,
+            "SELECT id, name, color as \"color: _\", description, version
+             FROM uniforms WHERE id = ANY($1)",
+            ids
+        )
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(uniforms
+            .into_iter()
+            .map(|uniform| (uniform.id, uniform))
+            .collect())
+    }
+}
+
+/// Loads the setlists of events by the event's ID
+pub struct SetlistLoader(PgPool);
+
+struct SetlistEntry {
+    event: i64,
+    id: i64,
+    title: String,
+    info: String,
+    current: bool,
+    key: Option<Pitch>,
+    starting_pitch: Option<Pitch>,
+    mode: Option<SongMode>,
+    version: i64,
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for SetlistLoader {
+    type Value = Vec<Song>;
+    type Error = Error;
+
+    async fn load(&self, event_ids: &[i64]) -> Result<HashMap<i64, Vec<Song>>> {
+        let entries = sqlx::query_as!(
+            SetlistEntry,
+            "SELECT gig_songs.event, s.id, s.title, s.info, s.current, s.key as \"key: _\",
+                 s.starting_pitch as \"starting_pitch: _\", s.mode as \"mode: _\", s.version
+             FROM songs s INNER JOIN gig_songs ON s.id = gig_songs.song
+             WHERE gig_songs.event = ANY($1) ORDER BY gig_songs.order",
+            event_ids
+        )
+        .fetch_all(&self.0)
+        .await?;
+
+        let mut setlists: HashMap<i64, Vec<Song>> = HashMap::new();
+        for entry in entries {
+            setlists.entry(entry.event).or_default().push(Song {
+                id: entry.id,
+                title: entry.title,
+                info: entry.info,
+                current: entry.current,
+                key: entry.key,
+                starting_pitch: entry.starting_pitch,
+                mode: entry.mode,
+                version: entry.version,
+            });
+        }
+
+        Ok(setlists)
+    }
+}
+
+/// Loads the passengers of carpools by the carpool's ID
+pub struct PassengerLoader(PgPool);
+
+#[async_trait::async_trait]
+impl Loader<i64> for PassengerLoader {
+    type Value = Vec<Member>;
+    type Error = Error;
+
+    async fn load(&self, carpool_ids: &[i64]) -> Result<HashMap<i64, Vec<Member>>> {
+        let rides = sqlx::query!(
+            "SELECT carpool, member FROM rides_in WHERE carpool = ANY($1)",
+            carpool_ids
+        )
+        .fetch_all(&self.0)
+        .await?;
+        let emails: Vec<String> = rides.iter().map(|ride| ride.member.clone()).collect();
+        let members = MemberLoader(self.0.clone()).load(&emails).await?;
+
+        let mut passengers: HashMap<i64, Vec<Member>> = HashMap::new();
+        for ride in rides {
+            if let Some(member) = members.get(&ride.member) {
+                passengers
+                    .entry(ride.carpool)
+                    .or_default()
+                    .push(member.clone());
+            }
+        }
+        for carpool in passengers.values_mut() {
+            carpool.sort_by(|a, b| {
+                (&a.last_name, &a.preferred_name, &a.first_name).cmp(&(
+                    &b.last_name,
+                    &b.preferred_name,
+                    &b.first_name,
+                ))
+            });
+        }
+
+        Ok(passengers)
+    }
+}
+
+/// Loads active semesters by member email and semester name
+pub struct ActiveSemesterLoader(PgPool);
+
+#[async_trait::async_trait]
+impl Loader<(String, String)> for ActiveSemesterLoader {
+    type Value = ActiveSemester;
+    type Error = Error;
+
+    async fn load(
+        &self,
+        keys: &[(String, String)],
+    ) -> Result<HashMap<(String, String), ActiveSemester>> {
+        let (members, semesters): (Vec<String>, Vec<String>) = keys.iter().cloned().unzip();
+        let active_semesters = sqlx::query_as!(
+            ActiveSemester,
+            "SELECT a.member, a.semester, a.enrollment as \"enrollment: _\", a.section
+             FROM active_semesters a
+             INNER JOIN UNNEST($1::text[], $2::text[]) AS k(member, semester)
+                 ON a.member = k.member AND a.semester = k.semester",
+            &members,
+            &semesters
+        )
+        .fetch_all(&self.0)
+        .await?;
+
+        Ok(active_semesters
+            .into_iter()
+            .map(|active| ((active.member.clone(), active.semester.clone()), active))
+            .collect())
+    }
+}
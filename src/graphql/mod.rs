@@ -1,14 +1,73 @@
-use async_graphql::{EmptySubscription, Schema};
+use std::env::var;
 
+use anyhow::Context;
+use async_graphql::Schema;
+
+use crate::graphql::audit::ImpersonationAudit;
 use crate::graphql::mutation::MutationRoot;
 use crate::graphql::query::QueryRoot;
+use crate::graphql::subscription::SubscriptionRoot;
+use crate::graphql::telemetry::Telemetry;
 
+mod audit;
 pub mod guards;
+pub mod loader;
 pub mod mutation;
+pub mod pagination;
+pub mod persisted;
 pub mod query;
+pub mod schema;
+pub mod subscription;
+mod telemetry;
 
 pub const SUCCESS_MESSAGE: &str = "success";
 
-pub fn build_schema() -> Schema<QueryRoot, MutationRoot, EmptySubscription> {
-    Schema::new(QueryRoot, MutationRoot, EmptySubscription)
+/// Details about the client making a request
+pub struct ClientInfo {
+    /// The `User-Agent` header sent by the client, if any
+    pub user_agent: Option<String>,
+    /// The client's IP address, as reported by the proxy if there is one
+    pub ip: String,
+}
+
+pub type GreaseSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// How large a single query can be, so one request can't tie up the database
+pub struct QueryLimits {
+    /// How deeply fields can be nested
+    pub depth: usize,
+    /// The total cost of all fields, where lists and grades cost extra
+    pub complexity: usize,
+}
+
+impl QueryLimits {
+    pub const DEFAULT: Self = Self {
+        depth: 15,
+        complexity: 2500,
+    };
+
+    /// Reads `MAX_QUERY_DEPTH` and `MAX_QUERY_COMPLEXITY`, using the defaults for any not set
+    pub fn from_env() -> anyhow::Result<Self> {
+        let parse = |name: &str, default: usize| -> anyhow::Result<usize> {
+            var(name).map_or(Ok(default), |value| {
+                value
+                    .parse()
+                    .with_context(|| format!("{} must be a positive integer", name))
+            })
+        };
+
+        Ok(Self {
+            depth: parse("MAX_QUERY_DEPTH", Self::DEFAULT.depth)?,
+            complexity: parse("MAX_QUERY_COMPLEXITY", Self::DEFAULT.complexity)?,
+        })
+    }
+}
+
+pub fn build_schema(limits: &QueryLimits) -> GreaseSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .extension(ImpersonationAudit)
+        .extension(Telemetry)
+        .limit_depth(limits.depth)
+        .limit_complexity(limits.complexity)
+        .finish()
 }
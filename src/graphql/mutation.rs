@@ -1,10 +1,13 @@
-use async_graphql::{Context, Object, Result};
+use async_graphql::{Context, Guard, Object, Result};
 use sqlx::PgPool;
 
 use crate::email::event::NewEventEmail;
 use crate::email::send_email;
-use crate::graphql::guards::{LoggedIn, Permission};
-use crate::graphql::SUCCESS_MESSAGE;
+use crate::error::ApiError;
+use crate::graphql::guards::{GrantedPermissions, LoggedIn, NotImpersonating, Permission};
+use crate::graphql::subscription::Update;
+use crate::graphql::{ClientInfo, SUCCESS_MESSAGE};
+use crate::models::api_key::{ApiKey, CreatedApiKey, NewApiKey};
 use crate::models::event::absence_request::{AbsenceRequest, AbsenceRequestStatus};
 use crate::models::event::attendance::{Attendance, AttendanceUpdate};
 use crate::models::event::carpool::{Carpool, UpdatedCarpool};
@@ -12,12 +15,19 @@ use crate::models::event::gig::{GigRequest, GigRequestStatus, NewGigRequest};
 use crate::models::event::uniform::{NewUniform, Uniform};
 use crate::models::event::{Event, NewEvent};
 use crate::models::link::DocumentLink;
-use crate::models::member::active_semester::ActiveSemester;
+use crate::models::member::email_verification::EmailVerification;
+use crate::models::member::external_identity::ExternalIdentity;
+use crate::models::member::login_attempt::{LoginAttempt, LoginAttemptKind};
+use crate::models::member::password::Password;
+use crate::models::member::security_event::{SecurityEvent, SecurityEventKind};
 use crate::models::member::session::{PasswordReset, Session};
+use crate::models::member::two_factor::{TwoFactor, TwoFactorEnrollment};
 use crate::models::member::{Member, MemberUpdate, NewMember, RegisterForSemesterForm};
 use crate::models::minutes::{Minutes, UpdatedMeetingMinutes};
 use crate::models::money::{ClubTransaction, Fee, TransactionBatch};
-use crate::models::permissions::{MemberRole, NewRolePermission, RolePermission};
+use crate::models::permissions::{
+    MemberRole, NewPermissionGrant, NewRolePermission, PermissionGrant, RolePermission,
+};
 use crate::models::semester::{NewSemester, Semester};
 use crate::models::song::{NewSong, NewSongLink, Song, SongLink, SongLinkUpdate, SongUpdate};
 use crate::models::variable::Variable;
@@ -31,21 +41,206 @@ impl MutationRoot {
         &self,
         ctx: &Context<'_>,
         email: String,
-        pass_hash: String,
+        password: Option<String>,
+        #[graphql(desc = "Deprecated: the MD5 of the password. Use `password` instead")]
+        pass_hash: Option<String>,
+        #[graphql(desc = "A code from the member's authenticator app, or a recovery code")]
+        two_factor_code: Option<String>,
     ) -> Result<String> {
         let pool: &PgPool = ctx.data_unchecked();
-        if !Member::login_is_valid(&email, &pass_hash, pool).await? {
-            return Err("Invalid email or password".into());
+        let client: &ClientInfo = ctx.data_unchecked();
+        let password = Password::from_fields(password, pass_hash)?;
+        LoginAttempt::ensure_allowed(LoginAttemptKind::Login, &email, &client.ip, pool).await?;
+        if !Member::login_is_valid(&email, &password, pool).await? {
+            LoginAttempt::record(LoginAttemptKind::Login, &email, &client.ip, pool).await?;
+            return Err(ApiError::Unauthenticated("Invalid email or password".to_owned()).into());
         }
 
-        Session::get_or_generate_token(&email, pool).await
+        if TwoFactor::is_enabled(&email, pool).await? {
+            let code = two_factor_code.ok_or(ApiError::TwoFactorRequired)?;
+            if !TwoFactor::verify(&email, &code, pool).await? {
+                LoginAttempt::record(LoginAttemptKind::Login, &email, &client.ip, pool).await?;
+                return Err(ApiError::Unauthenticated(
+                    "Invalid two-factor authentication code".to_owned(),
+                )
+                .into());
+            }
+        }
+
+        LoginAttempt::clear_failed_logins(&email, pool).await?;
+        PasswordReset::remove(&email, pool).await?;
+
+        Session::generate(&email, client.user_agent.as_deref(), pool).await
+    }
+
+    /// Finishes a single sign-on login that was held for a two-factor code,
+    /// returning a token for the new session
+    pub async fn finish_two_factor_login(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "The `twoFactor` token the single sign-on login returned")]
+        challenge: String,
+        #[graphql(desc = "A code from the member's authenticator app, or a recovery code")]
+        code: String,
+    ) -> Result<String> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let client: &ClientInfo = ctx.data_unchecked();
+        let email = TwoFactor::challenged_member(&challenge, pool).await?;
+        LoginAttempt::ensure_allowed(LoginAttemptKind::Login, &email, &client.ip, pool).await?;
+        if !TwoFactor::verify(&email, &code, pool).await? {
+            LoginAttempt::record(LoginAttemptKind::Login, &email, &client.ip, pool).await?;
+            return Err(ApiError::Unauthenticated(
+                "Invalid two-factor authentication code".to_owned(),
+            )
+            .into());
+        }
+
+        TwoFactor::remove_challenge(&challenge, pool).await?;
+        LoginAttempt::clear_failed_logins(&email, pool).await?;
+
+        Session::generate(&email, client.user_agent.as_deref(), pool).await
     }
 
-    /// Logs the member out
+    /// Logs the member out of the current session
     pub async fn logout(&self, ctx: &Context<'_>) -> Result<&'static str> {
-        let user = ctx.data_opt::<Member>().ok_or("Not currently logged in")?;
+        let session = ctx
+            .data_opt::<Session>()
+            .ok_or_else(|| ApiError::Unauthenticated("Not currently logged in".to_owned()))?;
+        let pool: &PgPool = ctx.data_unchecked();
+        Session::remove(session.id, pool).await?;
+
+        Ok(SUCCESS_MESSAGE)
+    }
+
+    /// Logs out one of the current user's sessions and returns its ID
+    #[graphql(guard = "LoggedIn.and(NotImpersonating)")]
+    pub async fn revoke_session(&self, ctx: &Context<'_>, id: i64) -> Result<i64> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        Session::revoke(id, &user.email, pool).await?;
+
+        Ok(id)
+    }
+
+    /// Links a single sign-on account to the current user, using the
+    /// code given after signing in with an account that matched no member
+    #[graphql(guard = "LoggedIn.and(NotImpersonating)")]
+    pub async fn link_single_sign_on(
+        &self,
+        ctx: &Context<'_>,
+        code: String,
+    ) -> Result<ExternalIdentity> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        ExternalIdentity::claim_pending_link(&code, &user.email, pool).await
+    }
+
+    /// Starts setting up two-factor authentication for the current user
+    #[graphql(guard = "LoggedIn.and(NotImpersonating)")]
+    pub async fn begin_two_factor_enrollment(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<TwoFactorEnrollment> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let session = ctx.data_opt::<Session>().ok_or_else(|| {
+            ApiError::Unauthenticated(
+                "Must be logged in with a password to set up two-factor authentication".to_owned(),
+            )
+        })?;
+        TwoFactor::begin_enrollment(&session.member, pool).await
+    }
+
+    /// Turns on two-factor authentication with a code from the member's
+    /// authenticator app, returning their single-use recovery codes
+    #[graphql(guard = "LoggedIn.and(NotImpersonating)")]
+    pub async fn confirm_two_factor_enrollment(
+        &self,
+        ctx: &Context<'_>,
+        code: String,
+    ) -> Result<Vec<String>> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let client: &ClientInfo = ctx.data_unchecked();
+        let session = ctx.data_opt::<Session>().ok_or_else(|| {
+            ApiError::Unauthenticated(
+                "Must be logged in with a password to set up two-factor authentication".to_owned(),
+            )
+        })?;
+
+        let recovery_codes = TwoFactor::confirm_enrollment(&session.member, &code, pool).await?;
+        SecurityEvent::record_by(
+            SecurityEventKind::TwoFactorEnabled,
+            &session.member,
+            session.impersonated_by.as_deref(),
+            Some(&client.ip),
+            None,
+            pool,
+        )
+        .await?;
+
+        Ok(recovery_codes)
+    }
+
+    /// Turns off two-factor authentication for the current user, which
+    /// needs a current code or recovery code
+    #[graphql(guard = "LoggedIn.and(NotImpersonating)")]
+    pub async fn disable_two_factor(
+        &self,
+        ctx: &Context<'_>,
+        code: String,
+    ) -> Result<&'static str> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let client: &ClientInfo = ctx.data_unchecked();
+        let session = ctx.data_opt::<Session>().ok_or_else(|| {
+            ApiError::Unauthenticated(
+                "Must be logged in with a password to turn off two-factor authentication"
+                    .to_owned(),
+            )
+        })?;
+        if !TwoFactor::verify(&session.member, &code, pool).await? {
+            return Err(ApiError::Unauthenticated(
+                "Invalid two-factor authentication code".to_owned(),
+            )
+            .into());
+        }
+
+        TwoFactor::disable(&session.member, pool).await?;
+        SecurityEvent::record_by(
+            SecurityEventKind::TwoFactorDisabled,
+            &session.member,
+            session.impersonated_by.as_deref(),
+            Some(&client.ip),
+            None,
+            pool,
+        )
+        .await?;
+
+        Ok(SUCCESS_MESSAGE)
+    }
+
+    /// Turns off two-factor authentication for a member who lost their device
+    #[graphql(guard = "LoggedIn.and(Permission::EDIT_USER)")]
+    pub async fn reset_two_factor(&self, ctx: &Context<'_>, email: String) -> Result<&'static str> {
         let pool: &PgPool = ctx.data_unchecked();
-        Session::remove(&user.email, pool).await?;
+        let client: &ClientInfo = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        if !TwoFactor::is_enabled(&email, pool).await? {
+            return Err(ApiError::conflict(format!(
+                "{} does not have two-factor authentication on",
+                email
+            ))
+            .into());
+        }
+
+        TwoFactor::disable(&email, pool).await?;
+        SecurityEvent::record_by(
+            SecurityEventKind::TwoFactorDisabled,
+            &email,
+            Some(&user.email),
+            Some(&client.ip),
+            None,
+            pool,
+        )
+        .await?;
 
         Ok(SUCCESS_MESSAGE)
     }
@@ -53,7 +248,11 @@ impl MutationRoot {
     /// Requests a password reset email for the given member
     pub async fn forgot_password(&self, ctx: &Context<'_>, email: String) -> Result<&'static str> {
         let pool: &PgPool = ctx.data_unchecked();
-        PasswordReset::generate(&email, pool).await?;
+        let client: &ClientInfo = ctx.data_unchecked();
+        let kind = LoginAttemptKind::PasswordReset;
+        LoginAttempt::ensure_allowed(kind, &email, &client.ip, pool).await?;
+        LoginAttempt::record(kind, &email, &client.ip, pool).await?;
+        PasswordReset::generate(&email, &client.ip, pool).await?;
 
         Ok(SUCCESS_MESSAGE)
     }
@@ -63,10 +262,14 @@ impl MutationRoot {
         &self,
         ctx: &Context<'_>,
         token: String,
-        pass_hash: String,
+        password: Option<String>,
+        #[graphql(desc = "Deprecated: the MD5 of the new password. Use `password` instead")]
+        pass_hash: Option<String>,
     ) -> Result<&'static str> {
         let pool: &PgPool = ctx.data_unchecked();
-        PasswordReset::reset_from_token(&token, &pass_hash, pool).await?;
+        let client: &ClientInfo = ctx.data_unchecked();
+        let password = Password::from_fields(password, pass_hash)?;
+        PasswordReset::reset_from_token(&token, &password, &client.ip, pool).await?;
 
         Ok(SUCCESS_MESSAGE)
     }
@@ -79,7 +282,46 @@ impl MutationRoot {
     ) -> Result<Member> {
         let pool: &PgPool = ctx.data_unchecked();
         let email = new_member.email.clone();
-        Member::register(new_member, pool).await?;
+        let mut transaction = pool.begin().await?;
+        let verification_email = Member::register(new_member, &mut transaction).await?;
+        let member = Member::with_email(&email, &mut *transaction).await?;
+        transaction.commit().await?;
+        EmailVerification::send_or_log(verification_email).await;
+
+        Ok(member)
+    }
+
+    /// Confirms a member's email using the token from their verification email
+    pub async fn verify_email(&self, ctx: &Context<'_>, token: String) -> Result<Member> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let client: &ClientInfo = ctx.data_unchecked();
+        let email = EmailVerification::verify_from_token(&token, &client.ip, pool).await?;
+
+        Member::with_email(&email, pool).await
+    }
+
+    /// Sends the current user another link to verify their email
+    #[graphql(guard = "LoggedIn")]
+    pub async fn resend_verification_email(&self, ctx: &Context<'_>) -> Result<&'static str> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        let email =
+            EmailVerification::generate(&user.email, false, &mut *pool.acquire().await?).await?;
+        send_email(email).await?;
+
+        Ok(SUCCESS_MESSAGE)
+    }
+
+    /// Marks a member's email as verified without them following the link
+    #[graphql(guard = "LoggedIn.and(Permission::EDIT_USER)")]
+    pub async fn override_email_verification(
+        &self,
+        ctx: &Context<'_>,
+        email: String,
+    ) -> Result<Member> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        EmailVerification::override_for(&email, &user.email, pool).await?;
 
         Member::with_email(&email, pool).await
     }
@@ -93,7 +335,27 @@ impl MutationRoot {
     ) -> Result<Member> {
         let pool: &PgPool = ctx.data_unchecked();
         let user = ctx.data_unchecked::<Member>();
-        Member::register_for_current_semester(user.email.clone(), new_semester, pool).await?;
+        user.ensure_email_verified()?;
+        let mut transaction = pool.begin().await?;
+        Member::register_for_current_semester(user.email.clone(), new_semester, &mut transaction)
+            .await?;
+        let member = Member::with_email(&user.email, &mut *transaction).await?;
+        transaction.commit().await?;
+
+        Ok(member)
+    }
+
+    /// Chooses whether the current user shares their contact details with all members
+    #[graphql(guard = "LoggedIn")]
+    pub async fn update_privacy(
+        &self,
+        ctx: &Context<'_>,
+        share_phone_number: bool,
+        share_location: bool,
+    ) -> Result<Member> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        Member::update_privacy(&user.email, share_phone_number, share_location, pool).await?;
 
         Member::with_email(&user.email, pool).await
     }
@@ -107,10 +369,23 @@ impl MutationRoot {
     ) -> Result<Member> {
         let pool: &PgPool = ctx.data_unchecked();
         let user = ctx.data_unchecked::<Member>();
+        if new_member.email != user.email
+            || new_member.password.is_some()
+            || new_member.pass_hash.is_some()
+        {
+            NotImpersonating.check(ctx).await?;
+        }
         let new_email = new_member.email.clone();
-        Member::update(&user.email, new_member, true, pool).await?;
+        let mut transaction = pool.begin().await?;
+        let verification_email =
+            Member::update(&user.email, new_member, true, &mut transaction).await?;
+        let member = Member::with_email(&new_email, &mut *transaction).await?;
+        transaction.commit().await?;
+        if let Some(email) = verification_email {
+            EmailVerification::send_or_log(email).await;
+        }
 
-        Member::with_email(&new_email, pool).await
+        Ok(member)
     }
 
     /// Updates the given member's profile
@@ -123,21 +398,126 @@ impl MutationRoot {
     ) -> Result<Member> {
         let pool: &PgPool = ctx.data_unchecked();
         let new_email = new_member.email.clone();
-        Member::update(&email, new_member, false, pool).await?;
+        let mut transaction = pool.begin().await?;
+        let verification_email =
+            Member::update(&email, new_member, false, &mut transaction).await?;
+        let member = Member::with_email(&new_email, &mut *transaction).await?;
+        transaction.commit().await?;
+        if let Some(email) = verification_email {
+            EmailVerification::send_or_log(email).await;
+        }
+
+        Ok(member)
+    }
+
+    /// Clears login and password reset lockouts for an account and/or IP address
+    #[graphql(guard = "Permission::EDIT_USER")]
+    pub async fn clear_lockout(
+        &self,
+        ctx: &Context<'_>,
+        email: Option<String>,
+        ip: Option<String>,
+    ) -> Result<&'static str> {
+        let pool: &PgPool = ctx.data_unchecked();
+        LoginAttempt::clear(email.as_deref(), ip.as_deref(), pool).await?;
+
+        Ok(SUCCESS_MESSAGE)
+    }
+
+    /// Creates an API key for a bot or script, returning its token
+    #[graphql(guard = "LoggedIn.and(NotImpersonating).and(Permission::EDIT_PERMISSIONS)")]
+    pub async fn create_api_key(
+        &self,
+        ctx: &Context<'_>,
+        new_key: NewApiKey,
+    ) -> Result<CreatedApiKey> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        let granted = ctx.data_unchecked::<GrantedPermissions>();
+        ApiKey::create(new_key, &user.email, granted, pool).await
+    }
+
+    /// Revokes an API key and returns its ID
+    #[graphql(guard = "Permission::EDIT_PERMISSIONS")]
+    pub async fn delete_api_key(&self, ctx: &Context<'_>, id: i64) -> Result<i64> {
+        let pool: &PgPool = ctx.data_unchecked();
+        ApiKey::delete(id, pool).await?;
+
+        Ok(id)
+    }
+
+    /// Sets whether a permission only takes effect for members with
+    /// two-factor authentication turned on
+    #[graphql(guard = "Permission::EDIT_PERMISSIONS")]
+    pub async fn set_permission_requires_two_factor(
+        &self,
+        ctx: &Context<'_>,
+        permission: String,
+        required: bool,
+    ) -> Result<&'static str> {
+        let pool: &PgPool = ctx.data_unchecked();
+        crate::models::permissions::Permission::set_requires_two_factor(
+            &permission,
+            required,
+            pool,
+        )
+        .await?;
 
-        Member::with_email(&new_email, pool).await
+        Ok(SUCCESS_MESSAGE)
     }
 
-    /// Logs in as the given member
+    /// Logs in as the given member for up to an hour, returning a token
+    /// for a session that records who is really acting
     #[graphql(guard = "LoggedIn.and(Permission::SWITCH_USER)")]
     pub async fn login_as(&self, ctx: &Context<'_>, email: String) -> Result<String> {
         let pool: &PgPool = ctx.data_unchecked();
+        let client: &ClientInfo = ctx.data_unchecked();
+        let session = ctx.data_opt::<Session>().ok_or_else(|| {
+            ApiError::Unauthenticated(
+                "Must be logged in with a password to log in as another member".to_owned(),
+            )
+        })?;
+
+        let token =
+            Session::impersonate(&email, session, client.user_agent.as_deref(), pool).await?;
+        SecurityEvent::record_by(
+            SecurityEventKind::ImpersonationStarted,
+            &email,
+            Some(&session.member),
+            Some(&client.ip),
+            None,
+            pool,
+        )
+        .await?;
+
+        Ok(token)
+    }
 
-        Session::get_or_generate_token(&email, pool).await
+    /// Stops logging in as another member, returning a token for the officer's own login
+    #[graphql(guard = "LoggedIn")]
+    pub async fn end_impersonation(&self, ctx: &Context<'_>) -> Result<String> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let client: &ClientInfo = ctx.data_unchecked();
+        let session = ctx
+            .data_opt::<Session>()
+            .ok_or_else(|| ApiError::conflict("Not currently logged in as another member"))?;
+
+        let token = session.end_impersonation(pool).await?;
+        SecurityEvent::record_by(
+            SecurityEventKind::ImpersonationEnded,
+            &session.member,
+            session.impersonated_by.as_deref(),
+            Some(&client.ip),
+            None,
+            pool,
+        )
+        .await?;
+
+        Ok(token)
     }
 
     /// Deletes a member and returns their email
-    #[graphql(guard = "LoggedIn.and(Permission::DELETE_USER)")]
+    #[graphql(guard = "Permission::DELETE_USER")]
     pub async fn delete_member(&self, ctx: &Context<'_>, email: String) -> Result<String> {
         let pool: &PgPool = ctx.data_unchecked();
         Member::delete(&email, pool).await?;
@@ -146,7 +526,7 @@ impl MutationRoot {
     }
 
     /// Creates a new event
-    #[graphql(guard = "LoggedIn.and(Permission::CREATE_EVENT.for_type(&new_event.event.r#type))")]
+    #[graphql(guard = "Permission::CREATE_EVENT.for_type(&new_event.event.r#type)")]
     pub async fn create_event(
         &self,
         ctx: &Context<'_>,
@@ -154,22 +534,33 @@ impl MutationRoot {
         gig_request_id: Option<i64>,
     ) -> Result<Event> {
         let pool: &PgPool = ctx.data_unchecked();
+        let mut transaction = pool.begin().await?;
         let gig_request = if let Some(request_id) = gig_request_id {
-            Some(GigRequest::with_id(request_id, pool).await?)
+            Some(GigRequest::with_id(request_id, &mut *transaction).await?)
         } else {
             None
         };
-        let new_id = Event::create(new_event, gig_request, pool).await?;
-        let event = Event::with_id(new_id, pool).await?;
-
-        let email = NewEventEmail::for_event(&event, pool).await?;
-        send_email(email).await?;
+        let new_id = Event::create(new_event, gig_request, &mut transaction).await?;
+        let event = Event::with_id(new_id, &mut *transaction).await?;
+
+        let email = NewEventEmail::for_event(&event, &mut *transaction).await?;
+        transaction.commit().await?;
+
+        // only announce events that were saved, and keep them even if the announcement fails,
+        // since an error here would get the event created again on retry
+        if let Err(err) = send_email(email).await {
+            tracing::warn!(
+                event = event.id,
+                error = %format!("{err:#}"),
+                "Failed to announce the new event"
+            );
+        }
 
         Ok(event)
     }
 
     /// Updates the given event
-    #[graphql(guard = "LoggedIn.and(Permission::MODIFY_EVENT.for_type(&new_event.event.r#type))")]
+    #[graphql(guard = "Permission::MODIFY_EVENT.for_type(&new_event.event.r#type)")]
     pub async fn update_event(
         &self,
         ctx: &Context<'_>,
@@ -177,22 +568,23 @@ impl MutationRoot {
         new_event: NewEvent,
     ) -> Result<Event> {
         let pool: &PgPool = ctx.data_unchecked();
-        Event::update(id, new_event, pool).await?;
+        let mut transaction = pool.begin().await?;
+        Event::update(id, new_event, &mut transaction).await?;
+        let event = Event::with_id(id, &mut *transaction).await?;
+        transaction.commit().await?;
 
-        Event::with_id(id, pool).await
+        Ok(event)
     }
 
     /// Deletes an event and returns its ID
     #[graphql(guard = "LoggedIn")]
     pub async fn delete_event(&self, ctx: &Context<'_>, id: i64) -> Result<i64> {
-        let user: &Member = ctx.data_unchecked();
         let pool: &PgPool = ctx.data_unchecked();
         let event = Event::with_id(id, pool).await?;
 
         Permission::DELETE_EVENT
             .for_type(event.r#type)
-            .ensure_granted_to(&user.email, pool)
-            .await?;
+            .ensure_granted(ctx)?;
 
         Event::delete(id, pool).await?;
 
@@ -209,35 +601,18 @@ impl MutationRoot {
         update: AttendanceUpdate,
     ) -> Result<Attendance> {
         let pool: &PgPool = ctx.data_unchecked();
-        let user = ctx.data_unchecked::<Member>();
         let event = Event::with_id(event_id, pool).await?;
-
-        if !Permission::EDIT_ATTENDANCE
-            .for_type(&event.r#type)
-            .granted_to(&user.email, pool)
+        Attendance::edit_decision(ctx, &event, &email, pool)
             .await?
-        {
-            let user_section =
-                ActiveSemester::for_member_during_semester(&user.email, &event.semester, pool)
-                    .await?
-                    .map(|semester| semester.section);
-            let member_section =
-                ActiveSemester::for_member_during_semester(&email, &event.semester, pool)
-                    .await?
-                    .map(|semester| semester.section);
-
-            if user_section.is_none()
-                || user_section != member_section
-                || !Permission::EDIT_ATTENDANCE_OWN_SECTION
-                    .for_type(&event.r#type)
-                    .granted_to(&user.email, pool)
-                    .await?
-            {
-                return Err("Not allowed to edit attendance".into());
-            }
-        }
+            .into_result()?;
 
         Attendance::update(event_id, &email, update, pool).await?;
+        Update::Attendance {
+            event: event_id,
+            member: Some(email.clone()),
+        }
+        .publish(pool)
+        .await;
 
         Attendance::for_member_at_event(&email, event_id, pool).await
     }
@@ -250,14 +625,18 @@ impl MutationRoot {
         event_id: i64,
     ) -> Result<&'static str> {
         let pool: &PgPool = ctx.data_unchecked();
-        let user = ctx.data_unchecked::<Member>();
         let event = Event::with_id(event_id, pool).await?;
         Permission::EDIT_ATTENDANCE
             .for_type(event.r#type)
-            .ensure_granted_to(&user.email, pool)
-            .await?;
+            .ensure_granted(ctx)?;
 
         Attendance::excuse_unconfirmed(event_id, pool).await?;
+        Update::Attendance {
+            event: event_id,
+            member: None,
+        }
+        .publish(pool)
+        .await;
 
         Ok(SUCCESS_MESSAGE)
     }
@@ -272,7 +651,14 @@ impl MutationRoot {
     ) -> Result<Attendance> {
         let pool: &PgPool = ctx.data_unchecked();
         let user = ctx.data_unchecked::<Member>();
+        user.ensure_email_verified()?;
         Attendance::rsvp_for_event(id, &user.email, attending, pool).await?;
+        Update::Attendance {
+            event: id,
+            member: Some(user.email.clone()),
+        }
+        .publish(pool)
+        .await;
 
         Attendance::for_member_at_event(&user.email, id, pool).await
     }
@@ -282,13 +668,20 @@ impl MutationRoot {
     pub async fn confirm_for_event(&self, ctx: &Context<'_>, id: i64) -> Result<Attendance> {
         let pool: &PgPool = ctx.data_unchecked();
         let user = ctx.data_unchecked::<Member>();
+        user.ensure_email_verified()?;
         Attendance::confirm_for_event(id, &user.email, pool).await?;
+        Update::Attendance {
+            event: id,
+            member: Some(user.email.clone()),
+        }
+        .publish(pool)
+        .await;
 
         Attendance::for_member_at_event(&user.email, id, pool).await
     }
 
     /// Updates the carpools for the given event
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_CARPOOLS)")]
+    #[graphql(guard = "Permission::EDIT_CARPOOLS")]
     pub async fn update_carpools(
         &self,
         ctx: &Context<'_>,
@@ -296,13 +689,17 @@ impl MutationRoot {
         carpools: Vec<UpdatedCarpool>,
     ) -> Result<Vec<Carpool>> {
         let pool: &PgPool = ctx.data_unchecked();
-        Carpool::update(event_id, carpools, pool).await?;
+        let mut transaction = pool.begin().await?;
+        Carpool::update(event_id, carpools, &mut transaction).await?;
+        let carpools = Carpool::for_event(event_id, &mut *transaction).await?;
+        transaction.commit().await?;
+        Update::Carpools { event: event_id }.publish(pool).await;
 
-        Carpool::for_event(event_id, pool).await
+        Ok(carpools)
     }
 
     /// Responds to an absence request from the given member for the given event
-    #[graphql(guard = "LoggedIn.and(Permission::PROCESS_ABSENCE_REQUESTS)")]
+    #[graphql(guard = "Permission::PROCESS_ABSENCE_REQUESTS")]
     pub async fn respond_to_absence_request(
         &self,
         ctx: &Context<'_>,
@@ -318,6 +715,19 @@ impl MutationRoot {
         };
 
         AbsenceRequest::set_state(event_id, &email, state, pool).await?;
+        Update::AbsenceRequest {
+            event: event_id,
+            member: email.clone(),
+        }
+        .publish(pool)
+        .await;
+        // Approved absences show up on the member's attendance
+        Update::Attendance {
+            event: event_id,
+            member: Some(email.clone()),
+        }
+        .publish(pool)
+        .await;
 
         AbsenceRequest::for_member_at_event(&email, event_id, pool).await
     }
@@ -333,6 +743,12 @@ impl MutationRoot {
         let pool: &PgPool = ctx.data_unchecked();
         let user = ctx.data_unchecked::<Member>();
         AbsenceRequest::submit(event_id, &user.email, &reason, pool).await?;
+        Update::AbsenceRequest {
+            event: event_id,
+            member: user.email.clone(),
+        }
+        .publish(pool)
+        .await;
 
         AbsenceRequest::for_member_at_event(&user.email, event_id, pool).await
     }
@@ -350,25 +766,26 @@ impl MutationRoot {
     }
 
     /// Dismisses a gig request
-    #[graphql(guard = "LoggedIn.and(Permission::PROCESS_GIG_REQUESTS)")]
+    #[graphql(guard = "Permission::PROCESS_GIG_REQUESTS")]
     pub async fn dismiss_gig_request(&self, ctx: &Context<'_>, id: i64) -> Result<GigRequest> {
         let pool: &PgPool = ctx.data_unchecked();
-        GigRequest::set_status(id, GigRequestStatus::Dismissed, pool).await?;
+        GigRequest::set_status(id, GigRequestStatus::Dismissed, &mut *pool.acquire().await?)
+            .await?;
 
         GigRequest::with_id(id, pool).await
     }
 
     /// Reopens a dismissed gig request
-    #[graphql(guard = "LoggedIn.and(Permission::PROCESS_GIG_REQUESTS)")]
+    #[graphql(guard = "Permission::PROCESS_GIG_REQUESTS")]
     pub async fn reopen_gig_request(&self, ctx: &Context<'_>, id: i64) -> Result<GigRequest> {
         let pool: &PgPool = ctx.data_unchecked();
-        GigRequest::set_status(id, GigRequestStatus::Pending, pool).await?;
+        GigRequest::set_status(id, GigRequestStatus::Pending, &mut *pool.acquire().await?).await?;
 
         GigRequest::with_id(id, pool).await
     }
 
     /// Creates a new document link
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_LINKS)")]
+    #[graphql(guard = "Permission::EDIT_LINKS")]
     pub async fn create_link(
         &self,
         ctx: &Context<'_>,
@@ -382,7 +799,7 @@ impl MutationRoot {
     }
 
     /// Updates the given document link
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_LINKS)")]
+    #[graphql(guard = "Permission::EDIT_LINKS")]
     pub async fn update_link(
         &self,
         ctx: &Context<'_>,
@@ -396,7 +813,7 @@ impl MutationRoot {
     }
 
     /// Deletes the given document link
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_LINKS)")]
+    #[graphql(guard = "Permission::EDIT_LINKS")]
     pub async fn delete_link(&self, ctx: &Context<'_>, name: String) -> Result<DocumentLink> {
         let pool: &PgPool = ctx.data_unchecked();
         let link = DocumentLink::with_name(&name, pool).await?;
@@ -406,7 +823,7 @@ impl MutationRoot {
     }
 
     /// Creates a new semester
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_SEMESTER)")]
+    #[graphql(guard = "Permission::EDIT_SEMESTER")]
     pub async fn create_semester(
         &self,
         ctx: &Context<'_>,
@@ -420,7 +837,7 @@ impl MutationRoot {
     }
 
     /// Updates the given semester
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_SEMESTER)")]
+    #[graphql(guard = "Permission::EDIT_SEMESTER")]
     pub async fn update_semester(
         &self,
         ctx: &Context<'_>,
@@ -435,16 +852,19 @@ impl MutationRoot {
     }
 
     /// Set the given semester as the current semester
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_SEMESTER)")]
+    #[graphql(guard = "Permission::EDIT_SEMESTER")]
     pub async fn set_current_semester(&self, ctx: &Context<'_>, name: String) -> Result<Semester> {
         let pool: &PgPool = ctx.data_unchecked();
-        Semester::set_current(&name, pool).await?;
+        let mut transaction = pool.begin().await?;
+        Semester::set_current(&name, &mut transaction).await?;
+        let semester = Semester::with_name(&name, &mut *transaction).await?;
+        transaction.commit().await?;
 
-        Semester::with_name(&name, pool).await
+        Ok(semester)
     }
 
     /// Create some new meeting minutes
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_MINUTES)")]
+    #[graphql(guard = "Permission::EDIT_MINUTES")]
     pub async fn create_meeting_minutes(&self, ctx: &Context<'_>, name: String) -> Result<Minutes> {
         let pool: &PgPool = ctx.data_unchecked();
         let new_id = Minutes::create(&name, pool).await?;
@@ -453,7 +873,7 @@ impl MutationRoot {
     }
 
     /// Updates the given meeting minutes
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_MINUTES)")]
+    #[graphql(guard = "Permission::EDIT_MINUTES")]
     pub async fn update_meeting_minutes(
         &self,
         ctx: &Context<'_>,
@@ -467,7 +887,7 @@ impl MutationRoot {
     }
 
     /// Deletes the given meeting minutes
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_MINUTES)")]
+    #[graphql(guard = "Permission::EDIT_MINUTES")]
     pub async fn delete_meeting_minutes(&self, ctx: &Context<'_>, id: i64) -> Result<Minutes> {
         let pool: &PgPool = ctx.data_unchecked();
         let minutes = Minutes::with_id(id, pool).await?;
@@ -477,7 +897,7 @@ impl MutationRoot {
     }
 
     /// Creates a new uniform
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_UNIFORMS)")]
+    #[graphql(guard = "Permission::EDIT_UNIFORMS")]
     pub async fn create_uniform(
         &self,
         ctx: &Context<'_>,
@@ -490,7 +910,7 @@ impl MutationRoot {
     }
 
     /// Updates the given uniform
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_UNIFORMS)")]
+    #[graphql(guard = "Permission::EDIT_UNIFORMS")]
     pub async fn update_uniform(
         &self,
         ctx: &Context<'_>,
@@ -504,7 +924,7 @@ impl MutationRoot {
     }
 
     /// Deletes the given uniform
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_UNIFORMS)")]
+    #[graphql(guard = "Permission::EDIT_UNIFORMS")]
     pub async fn delete_uniform(&self, ctx: &Context<'_>, id: i64) -> Result<Uniform> {
         let pool: &PgPool = ctx.data_unchecked();
         let uniform = Uniform::with_id(id, pool).await?;
@@ -514,7 +934,7 @@ impl MutationRoot {
     }
 
     /// Creates a new song
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_REPERTOIRE)")]
+    #[graphql(guard = "Permission::EDIT_REPERTOIRE")]
     pub async fn create_song(&self, ctx: &Context<'_>, new_song: NewSong) -> Result<Song> {
         let pool: &PgPool = ctx.data_unchecked();
         let new_id = Song::create(new_song, pool).await?;
@@ -523,7 +943,7 @@ impl MutationRoot {
     }
 
     /// Updates the given song
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_REPERTOIRE)")]
+    #[graphql(guard = "Permission::EDIT_REPERTOIRE")]
     pub async fn update_song(
         &self,
         ctx: &Context<'_>,
@@ -537,7 +957,7 @@ impl MutationRoot {
     }
 
     /// Deletes the given song
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_REPERTOIRE)")]
+    #[graphql(guard = "Permission::EDIT_REPERTOIRE")]
     pub async fn delete_song(&self, ctx: &Context<'_>, id: i64) -> Result<Song> {
         let pool: &PgPool = ctx.data_unchecked();
         let song = Song::with_id(id, pool).await?;
@@ -547,7 +967,7 @@ impl MutationRoot {
     }
 
     /// Creates a new song link
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_REPERTOIRE)")]
+    #[graphql(guard = "Permission::EDIT_REPERTOIRE")]
     pub async fn create_song_link(
         &self,
         ctx: &Context<'_>,
@@ -561,7 +981,7 @@ impl MutationRoot {
     }
 
     /// Updates the given song link
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_REPERTOIRE)")]
+    #[graphql(guard = "Permission::EDIT_REPERTOIRE")]
     pub async fn update_song_link(
         &self,
         ctx: &Context<'_>,
@@ -575,7 +995,7 @@ impl MutationRoot {
     }
 
     /// Deletes the given song link
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_REPERTOIRE)")]
+    #[graphql(guard = "Permission::EDIT_REPERTOIRE")]
     pub async fn delete_song_link(&self, ctx: &Context<'_>, id: i64) -> Result<SongLink> {
         let pool: &PgPool = ctx.data_unchecked();
         let link = SongLink::with_id(id, pool).await?;
@@ -585,7 +1005,7 @@ impl MutationRoot {
     }
 
     /// Adds a permission to the given role
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_PERMISSIONS)")]
+    #[graphql(guard = "Permission::EDIT_PERMISSIONS")]
     pub async fn add_permission_to_role(
         &self,
         ctx: &Context<'_>,
@@ -598,7 +1018,7 @@ impl MutationRoot {
     }
 
     /// Removes a permission from the given role
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_PERMISSIONS)")]
+    #[graphql(guard = "Permission::EDIT_PERMISSIONS")]
     pub async fn remove_permission_from_role(
         &self,
         ctx: &Context<'_>,
@@ -610,8 +1030,31 @@ impl MutationRoot {
         Ok(true)
     }
 
+    /// Gives a member a permission directly until it expires
+    #[graphql(guard = "LoggedIn.and(Permission::EDIT_PERMISSIONS)")]
+    pub async fn grant_permission(
+        &self,
+        ctx: &Context<'_>,
+        grant: NewPermissionGrant,
+    ) -> Result<PermissionGrant> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        let id = PermissionGrant::create(grant, &user.email, pool).await?;
+
+        PermissionGrant::with_id(id, pool).await
+    }
+
+    /// Revokes a permission granted directly to a member and returns its ID
+    #[graphql(guard = "Permission::EDIT_PERMISSIONS")]
+    pub async fn revoke_permission_grant(&self, ctx: &Context<'_>, id: i64) -> Result<i64> {
+        let pool: &PgPool = ctx.data_unchecked();
+        PermissionGrant::revoke(id, pool).await?;
+
+        Ok(id)
+    }
+
     /// Assign the given member the given officer position
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_OFFICERS)")]
+    #[graphql(guard = "Permission::EDIT_OFFICERS")]
     pub async fn add_officership(
         &self,
         ctx: &Context<'_>,
@@ -619,13 +1062,13 @@ impl MutationRoot {
         email: String,
     ) -> Result<bool> {
         let pool: &PgPool = ctx.data_unchecked();
-        MemberRole::add(&email, &role, pool).await?;
+        MemberRole::add(&email, &role, &mut *pool.acquire().await?).await?;
 
         Ok(true)
     }
 
     /// Remove an officer position from the given mmember
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_OFFICERS)")]
+    #[graphql(guard = "Permission::EDIT_OFFICERS")]
     pub async fn remove_officership(
         &self,
         ctx: &Context<'_>,
@@ -639,7 +1082,7 @@ impl MutationRoot {
     }
 
     /// Update the cost for the given fee
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_TRANSACTION)")]
+    #[graphql(guard = "Permission::EDIT_TRANSACTION")]
     pub async fn update_fee_amount(
         &self,
         ctx: &Context<'_>,
@@ -653,41 +1096,53 @@ impl MutationRoot {
     }
 
     /// Charge dues for the semester
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_TRANSACTION)")]
+    #[graphql(guard = "Permission::EDIT_TRANSACTION")]
     pub async fn charge_dues(&self, ctx: &Context<'_>) -> Result<Vec<ClubTransaction>> {
         let pool: &PgPool = ctx.data_unchecked();
-        let current_semester = Semester::get_current(pool).await?;
-        Fee::charge_dues_for_semester(pool).await?;
-
-        ClubTransaction::for_semester(&current_semester.name, pool).await
+        let mut transaction = pool.begin().await?;
+        let current_semester = Semester::get_current(&mut *transaction).await?;
+        Fee::charge_dues_for_semester(&mut transaction).await?;
+        let transactions =
+            ClubTransaction::for_semester(&current_semester.name, &mut *transaction).await?;
+        transaction.commit().await?;
+
+        Ok(transactions)
     }
 
     /// Charges late dues for the semester (anyone who hasn't paid their due)
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_TRANSACTION)")]
+    #[graphql(guard = "Permission::EDIT_TRANSACTION")]
     pub async fn charge_late_dues(&self, ctx: &Context<'_>) -> Result<Vec<ClubTransaction>> {
         let pool: &PgPool = ctx.data_unchecked();
-        let current_semester = Semester::get_current(pool).await?;
-        Fee::charge_late_dues_for_semester(pool).await?;
-
-        ClubTransaction::for_semester(&current_semester.name, pool).await
+        let mut transaction = pool.begin().await?;
+        let current_semester = Semester::get_current(&mut *transaction).await?;
+        Fee::charge_late_dues_for_semester(&mut transaction).await?;
+        let transactions =
+            ClubTransaction::for_semester(&current_semester.name, &mut *transaction).await?;
+        transaction.commit().await?;
+
+        Ok(transactions)
     }
 
     /// Creates multiple transactions from the given batch
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_TRANSACTION)")]
+    #[graphql(guard = "Permission::EDIT_TRANSACTION")]
     pub async fn add_batch_of_transactions(
         &self,
         ctx: &Context<'_>,
         batch: TransactionBatch,
     ) -> Result<Vec<ClubTransaction>> {
         let pool: &PgPool = ctx.data_unchecked();
-        let current_semester = Semester::get_current(pool).await?;
-        ClubTransaction::add_batch(batch, pool).await?;
-
-        ClubTransaction::for_semester(&current_semester.name, pool).await
+        let mut transaction = pool.begin().await?;
+        let current_semester = Semester::get_current(&mut *transaction).await?;
+        ClubTransaction::add_batch(batch, &mut transaction).await?;
+        let transactions =
+            ClubTransaction::for_semester(&current_semester.name, &mut *transaction).await?;
+        transaction.commit().await?;
+
+        Ok(transactions)
     }
 
     /// Resolves the given transaction
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_TRANSACTION)")]
+    #[graphql(guard = "Permission::EDIT_TRANSACTION")]
     pub async fn resolve_transaction(
         &self,
         ctx: &Context<'_>,
@@ -701,7 +1156,7 @@ impl MutationRoot {
     }
 
     /// Sets the given global variable
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_OFFICERS)")]
+    #[graphql(guard = "Permission::EDIT_OFFICERS")]
     pub async fn set_variable(
         &self,
         ctx: &Context<'_>,
@@ -715,7 +1170,7 @@ impl MutationRoot {
     }
 
     /// Unsets the given variable and returns
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_OFFICERS)")]
+    #[graphql(guard = "Permission::EDIT_OFFICERS")]
     pub async fn unset_variable(&self, ctx: &Context<'_>, key: String) -> Result<String> {
         let pool: &PgPool = ctx.data_unchecked();
         let variable = Variable::with_key(&key, pool).await?;
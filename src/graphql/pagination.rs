@@ -0,0 +1,142 @@
+//! Relay-style pagination, where cursors are offsets into the filtered results
+
+use std::future::Future;
+
+use async_graphql::connection::{query, Connection, Edge, EmptyFields};
+use async_graphql::{OutputType, Result, SimpleObject};
+
+use crate::error::ApiError;
+
+/// The most items that can be loaded in one page
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Extra fields on every page
+#[derive(SimpleObject)]
+pub struct PageTotal {
+    /// How many items match the filters across all pages
+    pub total_count: i64,
+}
+
+pub type Page<T> = Connection<usize, T, PageTotal, EmptyFields>;
+
+/// The rows of the filtered results that a page covers
+#[derive(Debug, PartialEq)]
+pub struct PageWindow {
+    pub offset: i64,
+    pub limit: i64,
+}
+
+/// Loads a page of results. `count` gets the total number of results for
+/// the filters, and `fetch` loads the results in the requested window
+pub async fn paginate<T, C, CF, F, FF>(
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>,
+    count: C,
+    fetch: F,
+) -> Result<Page<T>>
+where
+    T: OutputType,
+    C: FnOnce() -> CF,
+    CF: Future<Output = Result<i64>>,
+    F: FnOnce(PageWindow) -> FF,
+    FF: Future<Output = Result<Vec<T>>>,
+{
+    query(
+        after,
+        before,
+        first,
+        last,
+        |after, before, first, last| async move {
+            let total_count = count().await?;
+            let window = PageWindow::for_arguments(after, before, first, last, total_count)?;
+            let offset = window.offset as usize;
+            let has_next_page = window.offset + window.limit < total_count;
+
+            let mut page =
+                Page::with_additional_fields(offset > 0, has_next_page, PageTotal { total_count });
+            page.edges.extend(
+                fetch(window)
+                    .await?
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, item)| Edge::new(offset + index, item)),
+            );
+
+            Ok::<_, async_graphql::Error>(page)
+        },
+    )
+    .await
+}
+
+impl PageWindow {
+    /// Applies `after` and `before` first, then `first` and `last`, loading
+    /// the first `MAX_PAGE_SIZE` results if neither is given
+    fn for_arguments(
+        after: Option<usize>,
+        before: Option<usize>,
+        first: Option<usize>,
+        last: Option<usize>,
+        total_count: i64,
+    ) -> Result<Self> {
+        if first
+            .into_iter()
+            .chain(last)
+            .any(|size| size > MAX_PAGE_SIZE)
+        {
+            return Err(ApiError::validation(format!(
+                "At most {} items can be loaded at once",
+                MAX_PAGE_SIZE
+            ))
+            .into());
+        }
+
+        let total_count = total_count as usize;
+        let mut end = before.unwrap_or(total_count).min(total_count);
+        let mut start = after.map_or(0, |after| after.saturating_add(1)).min(end);
+        if let Some(first) = first {
+            end = end.min(start + first);
+        }
+        if let Some(last) = last {
+            start = start.max(end.saturating_sub(last));
+        }
+        if first.is_none() && last.is_none() {
+            end = end.min(start + MAX_PAGE_SIZE);
+        }
+
+        Ok(Self {
+            offset: start as i64,
+            limit: (end - start) as i64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::pagination::{PageWindow, MAX_PAGE_SIZE};
+
+    fn window(
+        after: Option<usize>,
+        before: Option<usize>,
+        first: Option<usize>,
+        last: Option<usize>,
+    ) -> (i64, i64) {
+        let window = PageWindow::for_arguments(after, before, first, last, 250).unwrap();
+        (window.offset, window.limit)
+    }
+
+    #[test]
+    fn windows_follow_relay_arguments() {
+        assert_eq!(window(None, None, None, None), (0, MAX_PAGE_SIZE as i64));
+        assert_eq!(window(None, None, Some(10), None), (0, 10));
+        assert_eq!(window(Some(9), None, Some(10), None), (10, 10));
+        assert_eq!(window(Some(245), None, Some(10), None), (246, 4));
+        assert_eq!(window(None, None, None, Some(10)), (240, 10));
+        assert_eq!(window(None, Some(10), None, Some(20)), (0, 10));
+        assert_eq!(window(Some(5), Some(10), None, None), (6, 4));
+        assert_eq!(window(Some(300), None, Some(10), None), (250, 0));
+        assert_eq!(window(Some(usize::MAX), None, None, None), (250, 0));
+        assert!(PageWindow::for_arguments(None, None, Some(101), None, 250).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! Queries registered ahead of time, so the public site can run them by hash
+
+use std::collections::HashMap;
+use std::env::var;
+
+use async_graphql::{Request, Value};
+use sha2::{Digest, Sha256};
+
+use crate::error::ApiError;
+
+/// Everything the public site needs to run without logging in
+const PUBLIC_QUERIES: [&str; 3] = [
+    include_str!("persisted/public_events.graphql"),
+    include_str!("persisted/public_songs.graphql"),
+    include_str!("persisted/submit_gig_request.graphql"),
+];
+
+/// Registered queries by the SHA-256 hash of their text, as sent by Apollo-style clients
+pub struct PersistedQueries {
+    queries: HashMap<String, &'static str>,
+    /// Whether callers that aren't logged in can only run registered queries
+    required_when_logged_out: bool,
+}
+
+impl PersistedQueries {
+    pub fn new(required_when_logged_out: bool) -> Self {
+        Self {
+            queries: PUBLIC_QUERIES
+                .into_iter()
+                .map(|query| (Self::hash(query), query))
+                .collect(),
+            required_when_logged_out,
+        }
+    }
+
+    /// Only lets callers that aren't logged in run registered queries if
+    /// `PERSISTED_QUERIES_ONLY` is set
+    pub fn from_env() -> Self {
+        Self::new(var("PERSISTED_QUERIES_ONLY").is_ok())
+    }
+
+    /// The hex-encoded SHA-256 hash of a query's text
+    pub fn hash(query: &str) -> String {
+        format!("{:x}", Sha256::digest(query))
+    }
+
+    /// Fills in the query for requests that only sent a hash, and turns away
+    /// unregistered queries from callers that aren't logged in if required
+    pub fn resolve(&self, request: &mut Request, logged_in: bool) -> Result<(), ApiError> {
+        let hash = match request.extensions.get("persistedQuery") {
+            Some(Value::Object(persisted_query)) => match persisted_query.get("sha256Hash") {
+                Some(Value::String(hash)) => Some(hash),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let Some(hash) = hash {
+            if let Some(query) = self.queries.get(hash) {
+                request.query = query.to_string();
+                return Ok(());
+            } else if request.query.is_empty() {
+                return Err(ApiError::PersistedQueryNotFound);
+            }
+        }
+
+        if self.required_when_logged_out
+            && !logged_in
+            && !self.queries.contains_key(&Self::hash(&request.query))
+        {
+            return Err(ApiError::Unauthenticated(
+                "Only registered queries can be run without logging in".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{Name, Request, Value};
+
+    use crate::error::ApiError;
+    use crate::graphql::persisted::{PersistedQueries, PUBLIC_QUERIES};
+
+    fn with_hash(hash: String) -> Request {
+        let mut request = Request::new("");
+        request.extensions.insert(
+            "persistedQuery".to_owned(),
+            Value::Object([(Name::new("sha256Hash"), Value::String(hash))].into()),
+        );
+        request
+    }
+
+    #[test]
+    fn registered_queries_can_be_run_by_hash() {
+        let persisted = PersistedQueries::new(true);
+
+        for query in PUBLIC_QUERIES {
+            async_graphql::parser::parse_query(query).unwrap();
+
+            let mut request = with_hash(PersistedQueries::hash(query));
+            assert_eq!(persisted.resolve(&mut request, false), Ok(()));
+            assert_eq!(request.query, query);
+        }
+
+        let mut request = with_hash(PersistedQueries::hash("{ members { email } }"));
+        assert_eq!(
+            persisted.resolve(&mut request, false),
+            Err(ApiError::PersistedQueryNotFound)
+        );
+    }
+
+    #[test]
+    fn only_logged_out_callers_are_limited_to_registered_queries() {
+        let persisted = PersistedQueries::new(true);
+        let query = "{ members { email } }";
+
+        assert_eq!(
+            persisted
+                .resolve(&mut Request::new(query), false)
+                .map_err(|error| error.code()),
+            Err(ApiError::UNAUTHENTICATED)
+        );
+        assert_eq!(persisted.resolve(&mut Request::new(query), true), Ok(()));
+        assert_eq!(
+            persisted.resolve(&mut Request::new(PUBLIC_QUERIES[0]), false),
+            Ok(())
+        );
+        assert_eq!(
+            PersistedQueries::new(false).resolve(&mut Request::new(query), false),
+            Ok(())
+        );
+    }
+}
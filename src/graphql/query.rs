@@ -1,17 +1,25 @@
 use async_graphql::{Context, Object, Result};
 use sqlx::PgPool;
 
-use crate::graphql::guards::{LoggedIn, Permission};
+use crate::error::ApiError;
+use crate::graphql::guards::{Decision, LoggedIn, Permission};
+use crate::graphql::pagination::{paginate, Page};
+use crate::models::api_key::ApiKey;
 use crate::models::event::absence_request::AbsenceRequest;
-use crate::models::event::gig::GigRequest;
+use crate::models::event::attendance::Attendance;
+use crate::models::event::gig::{GigRequest, GigRequestStatus};
 use crate::models::event::public::PublicEvent;
 use crate::models::event::uniform::Uniform;
-use crate::models::event::Event;
+use crate::models::event::{Event, EventFilter};
 use crate::models::link::DocumentLink;
-use crate::models::member::{IncludeContext, Member};
+use crate::models::member::external_identity::ExternalIdentity;
+use crate::models::member::login_attempt::Lockout;
+use crate::models::member::security_event::SecurityEvent;
+use crate::models::member::session::Session;
+use crate::models::member::{Member, MemberFilter};
 use crate::models::minutes::Minutes;
-use crate::models::money::{ClubTransaction, Fee};
-use crate::models::permissions::{MemberRole, RolePermission};
+use crate::models::money::{ClubTransaction, Fee, TransactionFilter};
+use crate::models::permissions::{CatalogDrift, MemberRole, PermissionGrant, RolePermission};
 use crate::models::semester::Semester;
 use crate::models::song::{PublicSong, Song, SongLink};
 use crate::models::static_data::StaticData;
@@ -26,6 +34,93 @@ impl QueryRoot {
         ctx.data_opt::<Member>().cloned()
     }
 
+    /// The current user's active login sessions, most recently used first
+    #[graphql(guard = "LoggedIn")]
+    pub async fn sessions(&self, ctx: &Context<'_>) -> Result<Vec<Session>> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        Session::for_member(&user.email, pool).await
+    }
+
+    /// Accounts and IP addresses that are being throttled for too many
+    /// failed logins or password reset requests
+    #[graphql(guard = "Permission::EDIT_USER")]
+    pub async fn lockouts(&self, ctx: &Context<'_>) -> Result<Vec<Lockout>> {
+        let pool: &PgPool = ctx.data_unchecked();
+        Lockout::all(pool).await
+    }
+
+    /// The security log, most recent first, optionally for just one member
+    #[graphql(guard = "Permission::EDIT_USER")]
+    pub async fn security_events(
+        &self,
+        ctx: &Context<'_>,
+        member: Option<String>,
+    ) -> Result<Vec<SecurityEvent>> {
+        let pool: &PgPool = ctx.data_unchecked();
+        SecurityEvent::all(member.as_deref(), pool).await
+    }
+
+    /// The single sign-on accounts linked to the current user
+    #[graphql(guard = "LoggedIn")]
+    pub async fn linked_accounts(&self, ctx: &Context<'_>) -> Result<Vec<ExternalIdentity>> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let user = ctx.data_unchecked::<Member>();
+        ExternalIdentity::for_member(&user.email, pool).await
+    }
+
+    /// All API keys, most recently created first
+    #[graphql(guard = "Permission::EDIT_PERMISSIONS")]
+    pub async fn api_keys(&self, ctx: &Context<'_>) -> Result<Vec<ApiKey>> {
+        let pool: &PgPool = ctx.data_unchecked();
+        ApiKey::all(pool).await
+    }
+
+    /// Whether the current user can perform an action, checked the same way the
+    /// mutations check it. Actions are permission names (e.g. `edit-attendance`)
+    #[graphql(guard = "LoggedIn")]
+    pub async fn can(
+        &self,
+        ctx: &Context<'_>,
+        action: String,
+        #[graphql(desc = "The type of event to check for, if not checking for a specific event")]
+        event_type: Option<String>,
+        #[graphql(desc = "The member that would be acted on")] member: Option<String>,
+        #[graphql(desc = "The ID of the event that would be acted on")] event: Option<i64>,
+    ) -> Result<Decision> {
+        let pool: &PgPool = ctx.data_unchecked();
+        let permission = Permission::with_name(&action)
+            .ok_or_else(|| ApiError::not_found("action", "name", action))?;
+        let event = if let Some(id) = event {
+            Some(Event::with_id(id, pool).await?)
+        } else {
+            None
+        };
+
+        match (&event, &member) {
+            (Some(event), Some(member))
+                if permission.name() == Permission::EDIT_ATTENDANCE.name() =>
+            {
+                Attendance::edit_decision(ctx, event, member, pool).await
+            }
+            (_, Some(member))
+                if permission.name() == Permission::VIEW_USER_PRIVATE_DETAILS.name() =>
+            {
+                Ok(permission.decide_for_member(ctx, member))
+            }
+            _ => {
+                let event_type = event.map(|event| event.r#type).or(event_type);
+                let permission = if let Some(event_type) = event_type {
+                    permission.for_type(event_type)
+                } else {
+                    permission
+                };
+
+                Ok(permission.decide(ctx))
+            }
+        }
+    }
+
     /// The member with the given email
     #[graphql(guard = "LoggedIn")]
     pub async fn member(&self, ctx: &Context<'_>, email: String) -> Result<Member> {
@@ -33,33 +128,29 @@ impl QueryRoot {
         Member::with_email(&email, pool).await
     }
 
-    /// All members registered on the site. Only loads active members by default
-    #[graphql(guard = "LoggedIn")]
+    /// Members registered on the site, sorted by name. Only loads active members by default
+    #[graphql(guard = "LoggedIn", complexity = "10 * child_complexity")]
     pub async fn members(
         &self,
         ctx: &Context<'_>,
-        #[graphql(
-            default = true,
-            desc = "Load members that are active in the class for this semester"
-        )]
-        include_class: bool,
-        #[graphql(
-            default = true,
-            desc = "Load members that are registered in the club for this semester"
-        )]
-        include_club: bool,
-        #[graphql(default = false, desc = "Load members that are currently inactive")]
-        include_inactive: bool,
-    ) -> Result<Vec<Member>> {
+        #[graphql(default)] filter: MemberFilter,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Page<Member>> {
         let pool: &PgPool = ctx.data_unchecked();
         let semester = Semester::get_current(pool).await?;
-        let included = IncludeContext {
-            class: include_class,
-            club: include_club,
-            inactive: include_inactive,
-        };
 
-        Member::all_included(included, &semester.name, pool).await
+        paginate(
+            after,
+            before,
+            first,
+            last,
+            || Member::count(&filter, &semester.name, pool),
+            |window| Member::page(&filter, &semester.name, window, pool),
+        )
+        .await
     }
 
     /// The event with the given ID
@@ -69,12 +160,29 @@ impl QueryRoot {
         Event::with_id(id, pool).await
     }
 
-    /// All events in the current semester
-    #[graphql(guard = "LoggedIn")]
-    pub async fn events(&self, ctx: &Context<'_>) -> Result<Vec<Event>> {
+    /// Events in the current semester, in the order they happen
+    #[graphql(guard = "LoggedIn", complexity = "5 * child_complexity")]
+    pub async fn events(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: EventFilter,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Page<Event>> {
         let pool: &PgPool = ctx.data_unchecked();
         let semester = Semester::get_current(pool).await?;
-        Event::for_semester(&semester.name, pool).await
+
+        paginate(
+            after,
+            before,
+            first,
+            last,
+            || Event::count(&filter, &semester.name, pool),
+            |window| Event::page(&filter, &semester.name, window, pool),
+        )
+        .await
     }
 
     /// All events visible on the external site
@@ -84,7 +192,7 @@ impl QueryRoot {
     }
 
     /// All absence requests for the current semester
-    #[graphql(guard = "LoggedIn.and(Permission::PROCESS_ABSENCE_REQUESTS)")]
+    #[graphql(guard = "Permission::PROCESS_ABSENCE_REQUESTS")]
     pub async fn absence_requests(&self, ctx: &Context<'_>) -> Result<Vec<AbsenceRequest>> {
         let pool: &PgPool = ctx.data_unchecked();
         let current_semester = Semester::get_current(pool).await?;
@@ -92,17 +200,33 @@ impl QueryRoot {
     }
 
     /// The gig request with the given ID
-    #[graphql(guard = "LoggedIn.and(Permission::PROCESS_GIG_REQUESTS)")]
+    #[graphql(guard = "Permission::PROCESS_GIG_REQUESTS")]
     pub async fn gig_request(&self, ctx: &Context<'_>, id: i64) -> Result<GigRequest> {
         let pool: &PgPool = ctx.data_unchecked();
         GigRequest::with_id(id, pool).await
     }
 
-    /// All gig requests made this semester and other unresolved requests
-    #[graphql(guard = "LoggedIn.and(Permission::PROCESS_GIG_REQUESTS)")]
-    pub async fn gig_requests(&self, ctx: &Context<'_>) -> Result<Vec<GigRequest>> {
+    /// Gig requests, oldest first
+    #[graphql(guard = "Permission::PROCESS_GIG_REQUESTS")]
+    pub async fn gig_requests(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Only load requests with this status")] status: Option<GigRequestStatus>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Page<GigRequest>> {
         let pool: &PgPool = ctx.data_unchecked();
-        GigRequest::all(pool).await
+        paginate(
+            after,
+            before,
+            first,
+            last,
+            || GigRequest::count(status, pool),
+            |window| GigRequest::page(status, window, pool),
+        )
+        .await
     }
 
     /// The meeting minutes with the given ID
@@ -112,11 +236,26 @@ impl QueryRoot {
         Minutes::with_id(id, pool).await
     }
 
-    /// All meeting minutes
+    /// All meeting minutes, newest first
     #[graphql(guard = "LoggedIn")]
-    pub async fn all_meeting_minutes(&self, ctx: &Context<'_>) -> Result<Vec<Minutes>> {
+    pub async fn all_meeting_minutes(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Page<Minutes>> {
         let pool: &PgPool = ctx.data_unchecked();
-        Minutes::all(pool).await
+        paginate(
+            after,
+            before,
+            first,
+            last,
+            || Minutes::count(pool),
+            |window| Minutes::page(window, pool),
+        )
+        .await
     }
 
     /// The current semester
@@ -167,11 +306,26 @@ impl QueryRoot {
         Song::with_id(id, pool).await
     }
 
-    /// All songs in our repertoire
+    /// All songs in our repertoire, sorted by title
     #[graphql(guard = "LoggedIn")]
-    pub async fn songs(&self, ctx: &Context<'_>) -> Result<Vec<Song>> {
+    pub async fn songs(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Page<Song>> {
         let pool: &PgPool = ctx.data_unchecked();
-        Song::all(pool).await
+        paginate(
+            after,
+            before,
+            first,
+            last,
+            || Song::count(pool),
+            |window| Song::page(window, pool),
+        )
+        .await
     }
 
     /// The song link with the given ID
@@ -192,37 +346,79 @@ impl QueryRoot {
         StaticData
     }
 
-    /// All transactions for this semester
-    #[graphql(guard = "LoggedIn.and(Permission::VIEW_TRANSACTIONS)")]
-    pub async fn transactions(&self, ctx: &Context<'_>) -> Result<Vec<ClubTransaction>> {
+    /// Transactions for this semester, oldest first
+    #[graphql(guard = "Permission::VIEW_TRANSACTIONS")]
+    pub async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: TransactionFilter,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<Page<ClubTransaction>> {
         let pool: &PgPool = ctx.data_unchecked();
         let current_semester = Semester::get_current(pool).await?;
-        ClubTransaction::for_semester(&current_semester.name, pool).await
+
+        paginate(
+            after,
+            before,
+            first,
+            last,
+            || ClubTransaction::count(&filter, &current_semester.name, pool),
+            |window| ClubTransaction::page(&filter, &current_semester.name, window, pool),
+        )
+        .await
     }
 
     /// All fees
-    #[graphql(guard = "LoggedIn.and(Permission::VIEW_TRANSACTIONS)")]
+    #[graphql(guard = "Permission::VIEW_TRANSACTIONS")]
     pub async fn fees(&self, ctx: &Context<'_>) -> Result<Vec<Fee>> {
         let pool: &PgPool = ctx.data_unchecked();
         Fee::all(pool).await
     }
 
     /// All current officers
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_OFFICERS)")]
+    #[graphql(guard = "Permission::EDIT_OFFICERS")]
     pub async fn officers(&self, ctx: &Context<'_>) -> Result<Vec<MemberRole>> {
         let pool: &PgPool = ctx.data_unchecked();
         MemberRole::current_officers(pool).await
     }
 
+    /// Differences between the permissions the API checks for and the database
+    #[graphql(guard = "Permission::EDIT_PERMISSIONS")]
+    pub async fn permission_catalog_drift(&self, ctx: &Context<'_>) -> Result<CatalogDrift> {
+        let pool: &PgPool = ctx.data_unchecked();
+        CatalogDrift::check(pool).await
+    }
+
+    /// Everyone who held an officer position during the given semester
+    #[graphql(guard = "Permission::EDIT_OFFICERS")]
+    pub async fn officers_for_semester(
+        &self,
+        ctx: &Context<'_>,
+        semester: String,
+    ) -> Result<Vec<MemberRole>> {
+        let pool: &PgPool = ctx.data_unchecked();
+        MemberRole::officers_for_semester(&semester, pool).await
+    }
+
     /// The current role permissions
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_OFFICERS)")]
+    #[graphql(guard = "Permission::EDIT_OFFICERS")]
     pub async fn current_permissions(&self, ctx: &Context<'_>) -> Result<Vec<RolePermission>> {
         let pool: &PgPool = ctx.data_unchecked();
         RolePermission::all(pool).await
     }
 
+    /// Permissions granted directly to members that haven't expired yet
+    #[graphql(guard = "Permission::EDIT_PERMISSIONS")]
+    pub async fn permission_grants(&self, ctx: &Context<'_>) -> Result<Vec<PermissionGrant>> {
+        let pool: &PgPool = ctx.data_unchecked();
+        PermissionGrant::active(pool).await
+    }
+
     /// The variable with the given key
-    #[graphql(guard = "LoggedIn.and(Permission::EDIT_OFFICERS)")]
+    #[graphql(guard = "Permission::EDIT_OFFICERS")]
     pub async fn variable(&self, ctx: &Context<'_>, key: String) -> Result<Variable> {
         let pool: &PgPool = ctx.data_unchecked();
         Variable::with_key(&key, pool).await
@@ -0,0 +1,305 @@
+//! Exporting the schema as SDL and finding what changed between two versions of it
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+
+use anyhow::Context;
+use async_graphql::parser::parse_schema;
+use async_graphql::parser::types::{
+    BaseType, FieldDefinition, InputValueDefinition, Type, TypeDefinition, TypeKind,
+    TypeSystemDefinition,
+};
+use async_graphql::Positioned;
+
+use crate::graphql::{build_schema, QueryLimits};
+
+/// The checked-in copy of the schema that GlubHub is typed against
+pub const SNAPSHOT_PATH: &str = "schema.graphql";
+
+/// The schema as SDL, which doesn't need a database
+pub fn export() -> String {
+    build_schema(&QueryLimits::DEFAULT).sdl()
+}
+
+/// A difference between two versions of the schema
+#[derive(Debug, PartialEq)]
+pub struct SchemaChange {
+    /// Whether queries written against the old schema could stop working
+    pub breaking: bool,
+    pub description: String,
+}
+
+impl Display for SchemaChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let kind = if self.breaking {
+            "breaking"
+        } else {
+            "additive"
+        };
+        write!(f, "{}: {}", kind, self.description)
+    }
+}
+
+/// Every difference between the old and new schema, breaking changes first.
+/// Descriptions are ignored
+pub fn compare(old: &str, new: &str) -> anyhow::Result<Vec<SchemaChange>> {
+    let old_types = types_in(old).context("Failed to parse the old schema")?;
+    let new_types = types_in(new).context("Failed to parse the new schema")?;
+    let mut changes = Changes::default();
+
+    for (name, old_type) in &old_types {
+        match new_types.get(name) {
+            Some(new_type) => changes.compare_types(name, old_type, new_type),
+            None => changes.breaking(format!("removed type {}", name)),
+        }
+    }
+    for name in new_types.keys() {
+        if !old_types.contains_key(name) {
+            changes.additive(format!("added type {}", name));
+        }
+    }
+
+    let mut changes = changes.0;
+    changes.sort_by_key(|change| !change.breaking);
+
+    Ok(changes)
+}
+
+fn types_in(sdl: &str) -> anyhow::Result<BTreeMap<String, TypeDefinition>> {
+    let document = parse_schema(sdl)?;
+
+    Ok(document
+        .definitions
+        .into_iter()
+        .filter_map(|definition| match definition {
+            TypeSystemDefinition::Type(definition) => {
+                Some((definition.node.name.node.to_string(), definition.node))
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+fn by_name<T>(items: &[Positioned<T>], name: impl Fn(&T) -> &str) -> BTreeMap<&str, &T> {
+    items
+        .iter()
+        .map(|item| (name(&item.node), &item.node))
+        .collect()
+}
+
+#[derive(Default)]
+struct Changes(Vec<SchemaChange>);
+
+impl Changes {
+    fn breaking(&mut self, description: String) {
+        self.0.push(SchemaChange {
+            breaking: true,
+            description,
+        });
+    }
+
+    fn additive(&mut self, description: String) {
+        self.0.push(SchemaChange {
+            breaking: false,
+            description,
+        });
+    }
+
+    fn compare_types(&mut self, name: &str, old: &TypeDefinition, new: &TypeDefinition) {
+        match (&old.kind, &new.kind) {
+            (TypeKind::Scalar, TypeKind::Scalar) => {}
+            (TypeKind::Object(old), TypeKind::Object(new)) => {
+                self.compare_fields(name, &old.fields, &new.fields)
+            }
+            (TypeKind::Interface(old), TypeKind::Interface(new)) => {
+                self.compare_fields(name, &old.fields, &new.fields)
+            }
+            (TypeKind::InputObject(old), TypeKind::InputObject(new)) => {
+                self.compare_inputs(&format!("input field {}", name), &old.fields, &new.fields)
+            }
+            (TypeKind::Enum(old), TypeKind::Enum(new)) => {
+                let old_values = by_name(&old.values, |value| &value.value.node);
+                let new_values = by_name(&new.values, |value| &value.value.node);
+                self.compare_names(&format!("value {}", name), &old_values, &new_values);
+            }
+            (TypeKind::Union(old), TypeKind::Union(new)) => {
+                let old_members = by_name(&old.members, |member| member);
+                let new_members = by_name(&new.members, |member| member);
+                self.compare_names(&format!("member {}", name), &old_members, &new_members);
+            }
+            _ => self.breaking(format!("changed {} to a different kind of type", name)),
+        }
+    }
+
+    /// Removing anything with a name is breaking, and adding it is not
+    fn compare_names<T>(&mut self, owner: &str, old: &BTreeMap<&str, T>, new: &BTreeMap<&str, T>) {
+        for name in old.keys().filter(|name| !new.contains_key(*name)) {
+            self.breaking(format!("removed {}.{}", owner, name));
+        }
+        for name in new.keys().filter(|name| !old.contains_key(*name)) {
+            self.additive(format!("added {}.{}", owner, name));
+        }
+    }
+
+    fn compare_fields(
+        &mut self,
+        type_name: &str,
+        old: &[Positioned<FieldDefinition>],
+        new: &[Positioned<FieldDefinition>],
+    ) {
+        let old_fields = by_name(old, |field| &field.name.node);
+        let new_fields = by_name(new, |field| &field.name.node);
+        self.compare_names(&format!("field {}", type_name), &old_fields, &new_fields);
+
+        for (name, old_field) in &old_fields {
+            let new_field = match new_fields.get(name) {
+                Some(new_field) => new_field,
+                None => continue,
+            };
+            let field = format!("{}.{}", type_name, name);
+
+            let (old_type, new_type) = (&old_field.ty.node, &new_field.ty.node);
+            if old_type != new_type {
+                // Clients can always handle a field that stops being null
+                let description = format!(
+                    "changed type of {} from {} to {}",
+                    field, old_type, new_type
+                );
+                if only_rules_out_nulls(old_type, new_type) {
+                    self.additive(description);
+                } else {
+                    self.breaking(description);
+                }
+            }
+
+            self.compare_inputs(
+                &format!("argument {}", field),
+                &old_field.arguments,
+                &new_field.arguments,
+            );
+        }
+    }
+
+    /// Compares the arguments of a field or the fields of an input object
+    fn compare_inputs(
+        &mut self,
+        owner: &str,
+        old: &[Positioned<InputValueDefinition>],
+        new: &[Positioned<InputValueDefinition>],
+    ) {
+        let old_inputs = by_name(old, |input| &input.name.node);
+        let new_inputs = by_name(new, |input| &input.name.node);
+
+        for (name, old_input) in &old_inputs {
+            match new_inputs.get(name) {
+                None => self.breaking(format!("removed {}.{}", owner, name)),
+                Some(new_input) if old_input.ty.node != new_input.ty.node => {
+                    let description = format!(
+                        "changed type of {}.{} from {} to {}",
+                        owner, name, old_input.ty.node, new_input.ty.node
+                    );
+                    // Clients can always leave out a value that stops being required
+                    if only_rules_out_nulls(&new_input.ty.node, &old_input.ty.node) {
+                        self.additive(description);
+                    } else {
+                        self.breaking(description);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (name, new_input) in &new_inputs {
+            if old_inputs.contains_key(name) {
+                continue;
+            }
+
+            let description = format!("added {}.{}", owner, name);
+            if new_input.ty.node.nullable || new_input.default_value.is_some() {
+                self.additive(description);
+            } else {
+                self.breaking(format!("{} which is required", description));
+            }
+        }
+    }
+}
+
+/// Whether `strict` is the same type as `loose` with nulls ruled out in some places,
+/// including inside lists
+fn only_rules_out_nulls(loose: &Type, strict: &Type) -> bool {
+    let bases_match = match (&loose.base, &strict.base) {
+        (BaseType::Named(loose), BaseType::Named(strict)) => loose == strict,
+        (BaseType::List(loose), BaseType::List(strict)) => only_rules_out_nulls(loose, strict),
+        _ => false,
+    };
+
+    bases_match && (loose.nullable || !strict.nullable)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphql::schema::{compare, export, SchemaChange};
+
+    const OLD: &str = r#"
+        type Query { members(includeClass: Boolean): [Member!]! event(id: Int!, tags: [String!]): Event }
+        type Member { email: String! name: String nicknames: [String] majors: [String!] }
+        type Event { id: Int! }
+        enum Status { PENDING ACCEPTED }
+    "#;
+
+    const NEW: &str = r#"
+        type Query { members(first: Int, search: String!): [Member!]! event(id: Int, tags: [String]): Event }
+        type Member { name: String! phone: String nicknames: [String!] majors: [String] }
+        enum Status { PENDING DISMISSED }
+        type Song { id: Int! }
+    "#;
+
+    #[test]
+    fn changes_are_classified() {
+        let changes = compare(OLD, NEW).unwrap();
+        let described = |breaking: bool| {
+            changes
+                .iter()
+                .filter(|change| change.breaking == breaking)
+                .map(|change| change.description.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            described(true),
+            [
+                "removed type Event",
+                "removed field Member.email",
+                "changed type of Member.majors from [String!] to [String]",
+                "removed argument Query.members.includeClass",
+                "added argument Query.members.search which is required",
+                "removed value Status.ACCEPTED",
+            ]
+        );
+        assert_eq!(
+            described(false),
+            [
+                "added field Member.phone",
+                "changed type of Member.name from String to String!",
+                "changed type of Member.nicknames from [String] to [String!]",
+                "changed type of argument Query.event.id from Int! to Int",
+                "changed type of argument Query.event.tags from [String!] to [String]",
+                "added argument Query.members.first",
+                "added value Status.DISMISSED",
+                "added type Song",
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot_is_up_to_date() {
+        let snapshot = include_str!("../../schema.graphql");
+        let changes = compare(snapshot, &export()).unwrap();
+
+        assert_eq!(
+            changes,
+            Vec::<SchemaChange>::new(),
+            "Run `grease schema --write` to update schema.graphql"
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use std::time::Duration;
+
+use async_graphql::{Context, Result, Subscription};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Sender;
+
+use crate::error::ApiError;
+use crate::graphql::guards::{GrantedPermissions, LoggedIn, Permission};
+use crate::models::api_key::ApiKey;
+use crate::models::event::absence_request::AbsenceRequest;
+use crate::models::event::attendance::Attendance;
+use crate::models::event::carpool::Carpool;
+use crate::models::event::Event;
+use crate::models::member::session::Session;
+use crate::models::member::Member;
+use crate::util::current_time;
+
+/// The Postgres channel that updates are sent over, so every instance hears them
+const UPDATES_CHANNEL: &str = "grease_updates";
+
+/// Something that changed, sent to subscribers after a mutation succeeds
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "topic", rename_all = "camelCase")]
+pub enum Update {
+    /// A member's attendance changed, or everyone's if `member` is empty
+    Attendance { event: i64, member: Option<String> },
+    /// An absence request was submitted or responded to
+    AbsenceRequest { event: i64, member: String },
+    /// The carpools for an event were changed
+    Carpools { event: i64 },
+}
+
+impl Update {
+    /// Notifies all instances of the update. The mutation already succeeded, so
+    /// failing to notify is only logged
+    pub async fn publish(self, pool: &PgPool) {
+        let result = match serde_json::to_string(&self) {
+            Ok(payload) => sqlx::query("SELECT pg_notify($1, $2)")
+                .bind(UPDATES_CHANNEL)
+                .bind(payload)
+                .execute(pool)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        if let Err(err) = result {
+            tracing::error!(update = ?self, error = %err, "Failed to publish update");
+        }
+    }
+
+    /// Forwards updates from Postgres to this instance's subscribers until the server stops
+    pub async fn listen(sender: Sender<Update>, pool: PgPool) {
+        let mut listener = loop {
+            match Self::connect_listener(&pool).await {
+                Ok(listener) => break listener,
+                Err(err) => {
+                    tracing::error!(error = %err, "Failed to listen for updates");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        };
+
+        loop {
+            // The listener reconnects on its own, so errors are only logged
+            match listener.recv().await {
+                Ok(notification) => match serde_json::from_str(notification.payload()) {
+                    Ok(update) => {
+                        // Sending only fails if no one is subscribed
+                        sender.send(update).ok();
+                    }
+                    Err(err) => tracing::warn!(error = %err, "Received an invalid update"),
+                },
+                Err(err) => {
+                    tracing::warn!(error = %err, "Lost connection while listening for updates");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+
+    async fn connect_listener(pool: &PgPool) -> sqlx::Result<PgListener> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(UPDATES_CHANNEL).await?;
+
+        Ok(listener)
+    }
+
+    /// The updates received by this instance from now on that `select` keeps, along with
+    /// the subscriber's current permissions. Logins can be revoked or expire and roles can
+    /// change while a subscription is open, so access is checked again before each update
+    /// and the subscription ends with an error once it's gone
+    fn stream<T, F>(
+        ctx: &Context<'_>,
+        required: Option<Permission>,
+        select: F,
+    ) -> impl Stream<Item = Result<(T, GrantedPermissions)>>
+    where
+        F: Fn(Update) -> Option<T>,
+    {
+        let receiver = ctx.data_unchecked::<Sender<Update>>().subscribe();
+        let state = (receiver, Subscriber::new(ctx), required, select);
+
+        stream::unfold(Some(state), |state| async move {
+            let (mut receiver, subscriber, required, select) = state?;
+            loop {
+                let selected = match receiver.recv().await {
+                    Ok(update) => match select(update) {
+                        Some(selected) => selected,
+                        None => continue,
+                    },
+                    // Slow subscribers miss some updates rather than holding up everyone else
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                };
+
+                let access = subscriber
+                    .current_permissions()
+                    .await
+                    .and_then(|permissions| match &required {
+                        Some(required) if !permissions.includes(required) => Err(required.error()),
+                        _ => Ok(permissions),
+                    });
+                return Some(match access {
+                    Ok(permissions) => (
+                        Ok((selected, permissions)),
+                        Some((receiver, subscriber, required, select)),
+                    ),
+                    Err(err) => (Err(err), None),
+                });
+            }
+        })
+    }
+}
+
+/// The login a subscription was started with
+struct Subscriber {
+    session_token: Option<String>,
+    api_key: Option<i64>,
+    pool: PgPool,
+}
+
+impl Subscriber {
+    fn new(ctx: &Context<'_>) -> Self {
+        Self {
+            session_token: ctx.data_opt::<Session>().map(|session| session.key.clone()),
+            api_key: ctx.data_opt::<ApiKey>().map(|api_key| api_key.id),
+            pool: ctx.data_unchecked::<PgPool>().clone(),
+        }
+    }
+
+    /// The subscriber's permissions right now, resolved the same way as for a new request
+    async fn current_permissions(&self) -> Result<GrantedPermissions> {
+        if let Some(token) = &self.session_token {
+            let session = Session::with_token(token, &self.pool).await?;
+            let member = Member::with_email(&session.member, &self.pool).await?;
+            GrantedPermissions::resolve(Some(&member), None, &self.pool).await
+        } else if let Some(id) = self.api_key {
+            let api_key = ApiKey::with_id_opt(id, &self.pool)
+                .await?
+                .filter(|api_key| api_key.expires_at > current_time())
+                .ok_or_else(|| {
+                    ApiError::Unauthenticated("The API key was deleted or has expired".to_owned())
+                })?;
+            GrantedPermissions::resolve(None, Some(&api_key), &self.pool).await
+        } else {
+            Err(ApiError::Unauthenticated("User must be logged in".to_owned()).into())
+        }
+    }
+}
+
+/// Turns each batch of changes into one message per change, or an error if they couldn't be loaded
+fn flatten<T>(batch: Result<Vec<T>>) -> impl Stream<Item = Result<T>> {
+    match batch {
+        Ok(items) => stream::iter(items.into_iter().map(Ok).collect::<Vec<_>>()),
+        Err(err) => stream::iter(vec![Err(err)]),
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Attendance changes at an event. Members who can't edit attendance for the
+    /// event only receive changes to their own attendance
+    #[graphql(guard = "LoggedIn")]
+    async fn attendance_updates(
+        &self,
+        ctx: &Context<'_>,
+        event_id: i64,
+    ) -> Result<impl Stream<Item = Result<Attendance>>> {
+        let pool: PgPool = ctx.data_unchecked::<PgPool>().clone();
+        let event = Event::with_id(event_id, &pool).await?;
+        let can_edit = Permission::EDIT_ATTENDANCE.for_type(&event.r#type);
+        let own_email = ctx.data_unchecked::<Member>().email.clone();
+
+        let updates = Update::stream(ctx, None, move |update| match update {
+            Update::Attendance { event, member } if event == event_id => Some(member),
+            _ => None,
+        });
+
+        Ok(updates
+            .then(move |access| {
+                let pool = pool.clone();
+                let only_member = match &access {
+                    Ok((_, permissions)) if permissions.includes(&can_edit) => None,
+                    _ => Some(own_email.clone()),
+                };
+
+                async move {
+                    let (member, _permissions) = access?;
+                    let member = match (member, only_member) {
+                        (Some(member), Some(only_member)) if member != only_member => {
+                            return Ok(Vec::new())
+                        }
+                        (member, only_member) => member.or(only_member),
+                    };
+
+                    if let Some(member) = member {
+                        Attendance::for_member_at_event(&member, event_id, &pool)
+                            .await
+                            .map(|attendance| vec![attendance])
+                    } else {
+                        Attendance::for_event(event_id, &pool).await
+                    }
+                }
+            })
+            .flat_map(flatten))
+    }
+
+    /// Absence requests as they are submitted and responded to
+    #[graphql(guard = "Permission::PROCESS_ABSENCE_REQUESTS")]
+    async fn absence_request_updates(
+        &self,
+        ctx: &Context<'_>,
+    ) -> impl Stream<Item = Result<AbsenceRequest>> {
+        let pool: PgPool = ctx.data_unchecked::<PgPool>().clone();
+        let updates =
+            Update::stream(
+                ctx,
+                Some(Permission::PROCESS_ABSENCE_REQUESTS),
+                |update| match update {
+                    Update::AbsenceRequest { event, member } => Some((event, member)),
+                    _ => None,
+                },
+            );
+
+        updates.then(move |access| {
+            let pool = pool.clone();
+
+            async move {
+                let ((event, member), _permissions) = access?;
+                AbsenceRequest::for_member_at_event(&member, event, &pool).await
+            }
+        })
+    }
+
+    /// The carpools for an event, sent again whenever they change
+    #[graphql(guard = "LoggedIn")]
+    async fn carpool_updates(
+        &self,
+        ctx: &Context<'_>,
+        event_id: i64,
+    ) -> impl Stream<Item = Result<Vec<Carpool>>> {
+        let pool: PgPool = ctx.data_unchecked::<PgPool>().clone();
+        let updates = Update::stream(ctx, None, move |update| match update {
+            Update::Carpools { event } if event == event_id => Some(event),
+            _ => None,
+        });
+
+        updates.then(move |access| {
+            let pool = pool.clone();
+
+            async move {
+                let (event, _permissions) = access?;
+                Carpool::for_event(event, &pool).await
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_graphql::{Request, Value};
+    use futures_util::StreamExt;
+    use tokio::sync::broadcast;
+
+    use crate::error::ApiError;
+    use crate::graphql::guards::GrantedPermissions;
+    use crate::graphql::subscription::Update;
+    use crate::graphql::{build_schema, QueryLimits};
+    use crate::models::member::session::Session;
+    use crate::models::member::Member;
+    use crate::util::with_cleanup;
+
+    #[test]
+    fn updates_survive_the_trip_through_postgres() {
+        let update = Update::Attendance {
+            event: 3,
+            member: Some("member@gatech.edu".to_owned()),
+        };
+        let payload = serde_json::to_string(&update).unwrap();
+
+        assert_eq!(
+            payload,
+            r#"{"topic":"attendance","event":3,"member":"member@gatech.edu"}"#
+        );
+        assert_eq!(serde_json::from_str::<Update>(&payload).unwrap(), update);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn subscriptions_end_once_their_login_is_revoked() {
+        let cleanup = ["DELETE FROM members WHERE email = 'subscriber@subscriptions.test'"];
+        with_cleanup(&cleanup, |pool| async move {
+            let email = "subscriber@subscriptions.test";
+
+            sqlx::query(
+                "INSERT INTO members (email, first_name, last_name, pass_hash, phone_number, location)
+                 VALUES ($1, 'Subs', 'Criber', '', '', '')",
+            )
+            .bind(email)
+            .execute(&pool)
+            .await
+            .unwrap();
+            let token = Session::generate(email, None, &pool).await.unwrap();
+            let session = Session::with_token(&token, &pool).await.unwrap();
+            let session_id = session.id;
+            let member = Member::with_email(email, &pool).await.unwrap();
+
+            let (updates, _) = broadcast::channel(16);
+            let schema = build_schema(&QueryLimits::DEFAULT);
+            let request = Request::new("subscription { carpoolUpdates(eventId: -1) { id } }")
+                .data(pool.clone())
+                .data(updates.clone())
+                .data(member)
+                .data(session)
+                .data(GrantedPermissions(Vec::new()));
+            let mut responses = schema.execute_stream(request);
+            // start the subscription, which waits for the first update
+            assert!(
+                tokio::time::timeout(Duration::from_millis(100), responses.next())
+                    .await
+                    .is_err()
+            );
+
+            updates.send(Update::Carpools { event: -1 }).unwrap();
+            let allowed = responses.next().await.unwrap();
+            Session::remove(session_id, &pool).await.unwrap();
+            updates.send(Update::Carpools { event: -1 }).unwrap();
+            let revoked = responses.next().await.unwrap();
+            let ended = responses.next().await.is_none();
+
+            assert!(allowed.errors.is_empty());
+            assert_eq!(
+                revoked.errors[0]
+                    .extensions
+                    .as_ref()
+                    .and_then(|extensions| extensions.get("code")),
+                Some(&Value::from(ApiError::UNAUTHENTICATED))
+            );
+            assert!(ended);
+        })
+        .await;
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextResolve, ResolveInfo,
+};
+use async_graphql::parser::types::{DocumentOperations, ExecutableDocument};
+use async_graphql::{ServerResult, Value, Variables};
+use tracing::{debug_span, info_span, Instrument, Span};
+
+/// Names the operation on the request's span and gives every resolver its own span.
+/// Top-level fields are traced at `info`, and nested fields only at `debug`
+pub struct Telemetry;
+
+impl ExtensionFactory for Telemetry {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(Telemetry)
+    }
+}
+
+#[async_trait::async_trait]
+impl Extension for Telemetry {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+
+        let names = match &document.operations {
+            DocumentOperations::Single(operation) => {
+                vec![operation.node.ty.to_string()]
+            }
+            DocumentOperations::Multiple(operations) => operations
+                .iter()
+                .map(|(name, operation)| format!("{} {}", operation.node.ty, name))
+                .collect(),
+        };
+        Span::current().record("operation", names.join(", ").as_str());
+
+        Ok(document)
+    }
+
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<Value>> {
+        let span = if info.path_node.parent.is_none() {
+            info_span!("resolve", field = %info.path_node, parent_type = info.parent_type)
+        } else {
+            debug_span!("resolve", field = %info.path_node, parent_type = info.parent_type)
+        };
+
+        async move {
+            let result = next.run(ctx, info).await;
+            if let Err(error) = &result {
+                tracing::info!(error = %error.message, "Field failed");
+            }
+
+            result
+        }
+        .instrument(span)
+        .await
+    }
+}
@@ -6,6 +6,7 @@ mod email;
 mod error;
 mod graphql;
 mod models;
+mod oidc;
 mod util;
 
 #[cfg(test)]
@@ -13,37 +14,70 @@ mod tests;
 
 use std::env::var;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::Context;
-use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
-use async_graphql::{Request, Response as GraphQLResponse};
-use axum::extract::{Path, Query};
+use async_graphql::http::{
+    playground_source, GraphQLPlaygroundConfig, WebSocket, WebSocketProtocols, WsMessage,
+    ALL_WEBSOCKET_PROTOCOLS,
+};
+use async_graphql::{Data, Pos, Request, Response as GraphQLResponse};
+use axum::extract::ws::{CloseFrame, Message, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Path, Query};
 use axum::headers::{ContentType, HeaderMap, HeaderValue};
-use axum::http::header::CONTENT_TYPE;
+use axum::http::header::{CONTENT_TYPE, SEC_WEBSOCKET_PROTOCOL, USER_AGENT};
 use axum::http::{Method, StatusCode};
+use axum::response::IntoResponse;
 use axum::routing::get;
 use axum::{Extension, Json, Router, TypedHeader};
+use futures_util::future::ready;
+use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
 use sqlx::PgPool;
+use tokio::sync::broadcast::{self, Sender};
 use tower_http::cors::CorsLayer;
+use tracing::field::Empty;
+use tracing::{info_span, Instrument, Span};
+use tracing_subscriber::EnvFilter;
 
 use crate::email::run_email_loop;
-use crate::error::{GreaseError, GreaseResult};
-use crate::graphql::build_schema;
+use crate::error::{ApiError, GreaseError, GreaseResult};
+use crate::graphql::guards::GrantedPermissions;
+use crate::graphql::persisted::PersistedQueries;
+use crate::graphql::subscription::Update;
+use crate::graphql::{build_schema, loader, schema, ClientInfo, GreaseSchema, QueryLimits};
+use crate::models::api_key::ApiKey;
+use crate::models::member::session::Session;
 use crate::models::member::Member;
+use crate::models::permissions::CatalogDrift;
+use crate::oidc::{OidcConfig, OidcProvider};
 
 const GREASE_TOKEN: &'static str = "GREASE_TOKEN";
 const API_URL: &'static str = "https://grease.fly.dev";
+const SUBSCRIPTIONS_URL: &str = "wss://grease.fly.dev/subscriptions";
+/// Statements are logged at `info` by sqlx, so only slow ones (logged at `warn`) are kept
+const DEFAULT_LOG_FILTER: &str = "info,sqlx=warn";
+/// The header Fly's proxy uses to pass along the client's real IP address
+const FLY_CLIENT_IP: &str = "Fly-Client-IP";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("schema") {
+        return schema_command(&args[1..]);
+    }
+
     dotenv::dotenv().ok();
+    init_logging()?;
 
     let db_uri = var("DATABASE_URL").context("DATABASE_URL not set")?;
     let pool = PgPool::connect(&db_uri)
         .await
         .context("Failed to connect to database")?;
 
+    check_permission_catalog(&pool).await?;
+
     // Only run the email sending loop if an interval time is set
     if let Ok(email_send_interval_seconds) = var("EMAIL_SEND_INTERVAL_SECONDS") {
         let email_send_interval_seconds = email_send_interval_seconds
@@ -52,10 +86,31 @@ async fn main() -> anyhow::Result<()> {
         tokio::spawn(run_email_loop(email_send_interval_seconds, pool.clone()));
     }
 
-    let app = Router::new()
+    let schema = build_schema(&QueryLimits::from_env()?);
+    let persisted_queries = Arc::new(PersistedQueries::from_env());
+
+    // Updates from every instance are relayed through Postgres to this instance's subscribers
+    let (updates, _) = broadcast::channel(64);
+    tokio::spawn(Update::listen(updates.clone(), pool.clone()));
+
+    let mut router = Router::new()
         .route("/", get(graphql_playground).post(query_graphql))
-        .route("/files/:name", get(song_file))
+        .route("/subscriptions", get(subscribe_graphql))
+        .route("/files/:name", get(song_file));
+
+    // Single sign-on is only available if a provider is configured
+    if let Some(config) = OidcConfig::from_env()? {
+        let provider = OidcProvider::discover(config)
+            .await
+            .context("Failed to set up single sign-on")?;
+        router = router.merge(oidc::routes(provider));
+    }
+
+    let app = router
         .layer(Extension(pool))
+        .layer(Extension(updates))
+        .layer(Extension(schema))
+        .layer(Extension(persisted_queries))
         .layer(
             CorsLayer::new()
                 .allow_origin("*".parse::<HeaderValue>().unwrap())
@@ -63,42 +118,311 @@ async fn main() -> anyhow::Result<()> {
                 .allow_methods([Method::GET, Method::POST]),
         );
 
-    println!("Running grease on port 3000");
+    tracing::info!(port = 3000, "Running grease");
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 
     Ok(())
 }
 
+/// `grease schema` prints the schema, `--write` saves it as the snapshot, and `--check`
+/// compares it to the snapshot and fails if the changes would break existing clients
+fn schema_command(args: &[String]) -> anyhow::Result<()> {
+    let sdl = schema::export();
+
+    match args {
+        [] => print!("{}", sdl),
+        [flag] if flag == "--write" => {
+            std::fs::write(schema::SNAPSHOT_PATH, sdl)
+                .with_context(|| format!("Failed to write {}", schema::SNAPSHOT_PATH))?;
+        }
+        [flag, rest @ ..] if flag == "--check" && rest.len() <= 1 => {
+            let path = rest.first().map_or(schema::SNAPSHOT_PATH, String::as_str);
+            let snapshot = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path))?;
+            let changes = schema::compare(&snapshot, &sdl)?;
+            for change in &changes {
+                println!("{}", change);
+            }
+
+            let breaking = changes.iter().filter(|change| change.breaking).count();
+            if breaking > 0 {
+                anyhow::bail!("{} breaking change(s) from {}", breaking, path);
+            } else if changes.is_empty() {
+                println!("The schema matches {}", path);
+            }
+        }
+        _ => anyhow::bail!("Usage: grease schema [--write | --check [snapshot]]"),
+    }
+
+    Ok(())
+}
+
+/// Logs to stdout, filtered by `RUST_LOG` (default `info`, with only slow SQL queries).
+/// Set `LOG_FORMAT=json` for structured logs in production
+fn init_logging() -> anyhow::Result<()> {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(DEFAULT_LOG_FILTER))
+        .context("RUST_LOG is not a valid log filter")?;
+    let logger = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match var("LOG_FORMAT").as_deref() {
+        Ok("json") => logger.json().try_init(),
+        Ok("text") | Err(_) => logger.try_init(),
+        Ok(other) => anyhow::bail!("LOG_FORMAT must be `json` or `text`, not `{}`", other),
+    }
+    .map_err(|err| anyhow::anyhow!(err))
+    .context("Failed to set up logging")
+}
+
+/// Makes sure every permission and event type the API checks for exists in the
+/// database, inserting missing permissions first if `SEED_PERMISSIONS` is set
+async fn check_permission_catalog(pool: &PgPool) -> anyhow::Result<()> {
+    let gql_err = |err: async_graphql::Error| anyhow::anyhow!(err.message);
+
+    if var("SEED_PERMISSIONS").is_ok() {
+        CatalogDrift::seed_missing_permissions(pool)
+            .await
+            .map_err(gql_err)?;
+    }
+
+    let drift = CatalogDrift::check(pool).await.map_err(gql_err)?;
+    if !drift.unused_permissions.is_empty() {
+        tracing::warn!(
+            permissions = %drift.unused_permissions.join(", "),
+            "Permissions in the database are never checked"
+        );
+    }
+    if drift.is_breaking() {
+        anyhow::bail!(
+            "The database doesn't match the API's permissions (missing permissions: [{}], \
+             mismatched permission types: [{}], missing event types: [{}]). \
+             Set SEED_PERMISSIONS to insert missing permissions",
+            drift.missing_permissions.join(", "),
+            drift.mismatched_permissions.join(", "),
+            drift.missing_event_types.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
 async fn query_graphql(
-    Json(request): Json<Request>,
+    Json(mut request): Json<Request>,
     headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
     Extension(pool): Extension<PgPool>,
+    Extension(schema): Extension<GreaseSchema>,
+    Extension(persisted_queries): Extension<Arc<PersistedQueries>>,
 ) -> GreaseResult<Json<GraphQLResponse>> {
-    let user = if let Some(token) = get_token(&headers)? {
+    let span = info_span!(
+        "request",
+        operation = Empty,
+        member = Empty,
+        api_key = Empty,
+        duration_ms = Empty,
+        errors = Empty
+    );
+    let started = Instant::now();
+
+    let result = async {
+        let token = get_token(&headers)?;
+        if let Err(error) = persisted_queries.resolve(&mut request, token.is_some()) {
+            let mut error = async_graphql::Error::from(error).into_server_error(Pos::default());
+            // The error didn't come from anywhere in a query
+            error.locations.clear();
+            return Ok(Json(GraphQLResponse::from_errors(vec![error])));
+        }
+
+        let client = client_info(&headers, address);
+        let mut request = Request::new(request.query).variables(request.variables);
+        request.data = request_data(token, client, &pool).await?;
+
+        Ok(Json(schema.execute(request).await))
+    }
+    .instrument(span.clone())
+    .await;
+
+    let errors = match &result {
+        Ok(Json(response)) => response.errors.len(),
+        Err(_) => 1,
+    };
+    span.record("duration_ms", started.elapsed().as_millis() as u64);
+    span.record("errors", errors);
+    span.in_scope(|| {
+        if errors > 0 {
+            tracing::warn!("Request finished with errors");
+        } else {
+            tracing::info!("Request finished");
+        }
+    });
+
+    result
+}
+
+async fn subscribe_graphql(
+    upgrade: WebSocketUpgrade,
+    headers: HeaderMap,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(pool): Extension<PgPool>,
+    Extension(updates): Extension<Sender<Update>>,
+    Extension(schema): Extension<GreaseSchema>,
+) -> impl IntoResponse {
+    let protocol = headers
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|protocols| protocols.to_str().ok())
+        .and_then(|protocols| {
+            protocols
+                .split(',')
+                .find_map(|protocol| protocol.trim().parse().ok())
+        })
+        .unwrap_or(WebSocketProtocols::SubscriptionsTransportWS);
+    let client = client_info(&headers, address);
+    // One span for the whole connection, like the `request` span for queries
+    let span = info_span!(
+        "subscription",
+        operation = Empty,
+        member = Empty,
+        api_key = Empty,
+        duration_ms = Empty
+    );
+
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            let started = Instant::now();
+            async move {
+                let (mut sink, stream) = socket.split();
+                let messages = stream
+                    .take_while(|message| ready(message.is_ok()))
+                    .filter_map(|message| {
+                        ready(match message {
+                            Ok(Message::Text(text)) => Some(text.into_bytes()),
+                            Ok(Message::Binary(bytes)) => Some(bytes),
+                            _ => None,
+                        })
+                    });
+
+                let mut responses = WebSocket::new(schema, messages, protocol).on_connection_init(
+                    move |payload| async move {
+                        // Browsers can't set headers on websockets, so the token is sent on connection
+                        let token = payload.get(GREASE_TOKEN).and_then(|token| token.as_str());
+                        // Every subscription needs a login, and this keeps logged out callers
+                        // from getting around persisted queries over a websocket
+                        if token.is_none() {
+                            return Err(ApiError::Unauthenticated(
+                                "Log in to subscribe to updates".to_owned(),
+                            )
+                            .into());
+                        }
+                        let mut data = request_data(token, client, &pool)
+                            .await
+                            .map_err(GreaseError::into_graphql_error)?;
+                        data.insert(updates);
+
+                        Ok(data)
+                    },
+                );
+
+                while let Some(response) = responses.next().await {
+                    let message = match response {
+                        WsMessage::Text(text) => Message::Text(text),
+                        WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                            code,
+                            reason: reason.into(),
+                        })),
+                    };
+                    if sink.send(message).await.is_err() {
+                        break;
+                    }
+                }
+
+                Span::current().record("duration_ms", started.elapsed().as_millis() as u64);
+                tracing::info!("Subscription closed");
+            }
+            .instrument(span)
+        })
+}
+
+/// Looks up who is making a request, for both queries and subscriptions
+async fn request_data(
+    token: Option<&str>,
+    client: ClientInfo,
+    pool: &PgPool,
+) -> GreaseResult<Data> {
+    let (session, api_key) = match token {
+        Some(token) if token.starts_with(ApiKey::TOKEN_PREFIX) => (
+            None,
+            Some(
+                ApiKey::with_token(token, pool)
+                    .await
+                    .map_err(GreaseError::GqlError)?,
+            ),
+        ),
+        Some(token) => (
+            Some(
+                Session::with_token(token, pool)
+                    .await
+                    .map_err(GreaseError::GqlError)?,
+            ),
+            None,
+        ),
+        None => (None, None),
+    };
+    // Requests made with an API key don't act as any member, so they can only
+    // use resolvers guarded by a permission the key grants
+    if let Some(session) = &session {
+        Span::current().record("member", session.member.as_str());
+    } else if let Some(api_key) = &api_key {
+        Span::current().record("api_key", api_key.id);
+    }
+    let user = if let Some(session) = &session {
         Some(
-            Member::with_token(token, &pool)
+            Member::with_email(&session.member, pool)
                 .await
-                .map_err(|err| GreaseError::GqlError(err.message))?,
+                .map_err(GreaseError::GqlError)?,
         )
     } else {
         None
     };
+    let permissions = GrantedPermissions::resolve(user.as_ref(), api_key.as_ref(), pool)
+        .await
+        .map_err(GreaseError::GqlError)?;
 
-    let request = Request::new(request.query)
-        .variables(request.variables)
-        .data(pool);
-    let request = if let Some(user) = user {
-        request.data(user)
-    } else {
-        request
-    };
+    let mut data = Data::default();
+    loader::register(&mut data, pool);
+    data.insert(pool.clone());
+    data.insert(client);
+    data.insert(permissions);
+    if let Some(user) = user {
+        data.insert(user);
+    }
+    if let Some(session) = session {
+        data.insert(session);
+    }
+    if let Some(api_key) = api_key {
+        data.insert(api_key);
+    }
+
+    Ok(data)
+}
 
-    Ok(Json(build_schema().execute(request).await))
+fn client_info(headers: &HeaderMap, address: SocketAddr) -> ClientInfo {
+    ClientInfo {
+        user_agent: headers
+            .get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(ToOwned::to_owned),
+        ip: headers
+            .get(FLY_CLIENT_IP)
+            .and_then(|ip| ip.to_str().ok())
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| address.ip().to_string()),
+    }
 }
 
 #[derive(Deserialize)]
@@ -111,7 +435,7 @@ async fn graphql_playground(
     headers: HeaderMap,
     params: Query<OptionalToken>,
 ) -> GreaseResult<(TypedHeader<ContentType>, String)> {
-    let mut config = GraphQLPlaygroundConfig::new(API_URL);
+    let mut config = GraphQLPlaygroundConfig::new(API_URL).subscription_endpoint(SUBSCRIPTIONS_URL);
     if let Some(header) = get_token(&headers)?.or(params.token.as_deref()) {
         config = config.with_header(GREASE_TOKEN, header);
     }
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+
+use async_graphql::{ComplexObject, Context, InputObject, Result, SimpleObject};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::graphql::guards::{GrantedPermissions, Permission};
+use crate::graphql::loader::load_member;
+use crate::models::member::Member;
+use crate::models::permissions::MemberPermission;
+use crate::models::{DateTime, DateTimeInput};
+use crate::util::{current_time, hash_token};
+
+/// A long-lived key for bots and scripts, limited to specific permissions
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct ApiKey {
+    /// The ID of the key
+    pub id: i64,
+    /// What the key is used for
+    pub name: String,
+    /// The permissions the key grants, instead of its creator's permissions
+    pub permissions: Vec<MemberPermission>,
+
+    #[graphql(skip)]
+    pub created_by: String,
+    #[graphql(skip)]
+    pub created_at: OffsetDateTime,
+    #[graphql(skip)]
+    pub expires_at: OffsetDateTime,
+    #[graphql(skip)]
+    pub last_used: Option<OffsetDateTime>,
+}
+
+#[ComplexObject]
+impl ApiKey {
+    /// The member that created the key
+    pub async fn created_by(&self, ctx: &Context<'_>) -> Result<Member> {
+        load_member(ctx, &self.created_by).await
+    }
+
+    /// When the key was created
+    pub async fn created_at(&self) -> DateTime {
+        DateTime::from(self.created_at)
+    }
+
+    /// When the key will stop working
+    pub async fn expires_at(&self) -> DateTime {
+        DateTime::from(self.expires_at)
+    }
+
+    /// When the key was last used to make a request
+    pub async fn last_used(&self) -> Option<DateTime> {
+        self.last_used.map(DateTime::from)
+    }
+}
+
+/// A newly created API key, along with the only copy of its token
+#[derive(SimpleObject)]
+pub struct CreatedApiKey {
+    /// The token to send as the `GREASE_TOKEN` header, which can't be retrieved later
+    pub token: String,
+    /// The key that was created
+    pub api_key: ApiKey,
+}
+
+#[derive(InputObject)]
+pub struct NewApiKey {
+    /// What the key will be used for
+    pub name: String,
+    /// When the key will stop working
+    pub expires_at: DateTimeInput,
+    /// The permissions the key grants
+    pub permissions: Vec<NewApiKeyPermission>,
+}
+
+#[derive(InputObject)]
+pub struct NewApiKeyPermission {
+    /// The name of the permission
+    pub permission: String,
+    /// Optionally, the type of event the permission applies to
+    pub event_type: Option<String>,
+}
+
+impl ApiKey {
+    /// Distinguishes API keys from session tokens in the `GREASE_TOKEN` header
+    pub const TOKEN_PREFIX: &'static str = "grease_";
+
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn with_id(id: i64, pool: &PgPool) -> Result<Self> {
+        Self::with_id_opt(id, pool)
+            .await?
+            .ok_or_else(|| ApiError::not_found("API key", "ID", id).into())
+    }
+
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn with_id_opt(id: i64, pool: &PgPool) -> Result<Option<Self>> {
+        let key = sqlx::query!(
+            "SELECT id, name, created_by, created_at, expires_at, last_used
+             FROM api_keys WHERE id = $1",
+            id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(key) = key {
+            Ok(Some(Self {
+                permissions: Self::permissions_for(key.id, pool).await?,
+                id: key.id,
+                name: key.name,
+                created_by: key.created_by,
+                created_at: key.created_at,
+                expires_at: key.expires_at,
+                last_used: key.last_used,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Loads the key for a request, marking it as used
+    #[tracing::instrument(skip_all)]
+    pub async fn with_token(token: &str, pool: &PgPool) -> Result<Self> {
+        let id = sqlx::query_scalar!(
+            "SELECT id FROM api_keys WHERE key_hash = $1",
+            hash_token(token)
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| {
+            ApiError::Unauthenticated("No API key matches the provided token".to_owned())
+        })?;
+        let key = Self::with_id(id, pool).await?;
+
+        if key.expires_at <= current_time() {
+            return Err(ApiError::Unauthenticated(format!(
+                "The API key \"{}\" has expired",
+                key.name
+            ))
+            .into());
+        }
+
+        sqlx::query!("UPDATE api_keys SET last_used = now() WHERE id = $1", id)
+            .execute(pool)
+            .await?;
+
+        Ok(key)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
+        let keys = sqlx::query!(
+            "SELECT id, name, created_by, created_at, expires_at, last_used
+             FROM api_keys ORDER BY created_at DESC"
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut permissions = HashMap::<i64, Vec<MemberPermission>>::new();
+        for permission in sqlx::query!(
+            "SELECT api_key, permission, event_type FROM api_key_permissions
+             ORDER BY permission, event_type"
+        )
+        .fetch_all(pool)
+        .await?
+        {
+            permissions
+                .entry(permission.api_key)
+                .or_default()
+                .push(MemberPermission {
+                    name: permission.permission,
+                    event_type: permission.event_type,
+                    role: None,
+                    grant_id: None,
+                });
+        }
+
+        Ok(keys
+            .into_iter()
+            .map(|key| Self {
+                permissions: permissions.remove(&key.id).unwrap_or_default(),
+                id: key.id,
+                name: key.name,
+                created_by: key.created_by,
+                created_at: key.created_at,
+                expires_at: key.expires_at,
+                last_used: key.last_used,
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(skip_all, fields(id = id))]
+    async fn permissions_for(id: i64, pool: &PgPool) -> Result<Vec<MemberPermission>> {
+        sqlx::query_as!(
+            MemberPermission,
+            "SELECT permission as name, event_type, NULL::text as role, NULL::bigint as grant_id
+             FROM api_key_permissions
+             WHERE api_key = $1 ORDER BY permission, event_type",
+            id
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Creates a new key, returning it along with its token. Keys can only
+    /// have permissions that their creator holds
+    #[tracing::instrument(skip_all, fields(created_by = %created_by))]
+    pub async fn create(
+        new_key: NewApiKey,
+        created_by: &str,
+        creator_permissions: &GrantedPermissions,
+        pool: &PgPool,
+    ) -> Result<CreatedApiKey> {
+        if new_key.permissions.is_empty() {
+            return Err(ApiError::validation("API keys must grant at least one permission").into());
+        }
+        for new_permission in &new_key.permissions {
+            let permission =
+                Permission::with_name(&new_permission.permission).ok_or_else(|| {
+                    ApiError::not_found("permission", "name", &new_permission.permission)
+                })?;
+            let permission = match &new_permission.event_type {
+                Some(event_type) => permission.for_type(event_type),
+                None => permission,
+            };
+            if !creator_permissions.includes(&permission) {
+                return Err(permission
+                    .denied_because(format!(
+                        "You can't give an API key the {} permission without holding it",
+                        permission.name()
+                    ))
+                    .into());
+            }
+        }
+        let expires_at = OffsetDateTime::from(new_key.expires_at);
+        if expires_at <= current_time() {
+            return Err(ApiError::validation("API keys must expire in the future").into());
+        }
+
+        let mut transaction = pool.begin().await?;
+        let token = format!("{}{}", Self::TOKEN_PREFIX, Uuid::new_v4().to_simple());
+        let id = sqlx::query_scalar!(
+            "INSERT INTO api_keys (name, key_hash, created_by, expires_at)
+             VALUES ($1, $2, $3, $4)
+             RETURNING id",
+            new_key.name,
+            hash_token(&token),
+            created_by,
+            expires_at
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        for permission in new_key.permissions {
+            sqlx::query!(
+                "INSERT INTO api_key_permissions (api_key, permission, event_type)
+                 VALUES ($1, $2, $3)
+                 ON CONFLICT(api_key, permission, event_type) DO NOTHING",
+                id,
+                permission.permission,
+                permission.event_type
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(CreatedApiKey {
+            token,
+            api_key: Self::with_id(id, pool).await?,
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn delete(id: i64, pool: &PgPool) -> Result<()> {
+        Self::with_id(id, pool).await?; // ensure that key exists
+
+        sqlx::query!("DELETE FROM api_keys WHERE id = $1", id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -2,6 +2,8 @@ use async_graphql::{ComplexObject, Context, Enum, Result, SimpleObject};
 use sqlx::PgPool;
 use time::OffsetDateTime;
 
+use crate::error::ApiError;
+use crate::graphql::loader::{load_event, load_member};
 use crate::models::event::Event;
 use crate::models::member::Member;
 use crate::models::DateTime;
@@ -32,14 +34,12 @@ impl AbsenceRequest {
 
     /// The event they requested absence from
     pub async fn event(&self, ctx: &Context<'_>) -> Result<Event> {
-        let pool: &PgPool = ctx.data_unchecked();
-        Event::with_id(self.event, pool).await
+        load_event(ctx, self.event).await
     }
 
     /// The member that requested an absence
     pub async fn member(&self, ctx: &Context<'_>) -> Result<Member> {
-        let pool: &PgPool = ctx.data_unchecked();
-        Member::with_email(&self.member, pool).await
+        load_member(ctx, &self.member).await
     }
 }
 
@@ -56,18 +56,21 @@ pub enum AbsenceRequestStatus {
 }
 
 impl AbsenceRequest {
+    #[tracing::instrument(skip_all, fields(email = %email, event_id = event_id))]
     pub async fn for_member_at_event(email: &str, event_id: i64, pool: &PgPool) -> Result<Self> {
         Self::for_member_at_event_opt(email, event_id, pool)
             .await?
             .ok_or_else(|| {
-                format!(
-                    "No absence request for member {} at event with id {}",
-                    email, event_id
+                ApiError::not_found(
+                    "absence request",
+                    "member and event ID",
+                    format!("{} at {}", email, event_id),
                 )
             })
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(email = %email, event_id = event_id))]
     pub async fn for_member_at_event_opt(
         email: &str,
         event_id: i64,
@@ -85,6 +88,7 @@ impl AbsenceRequest {
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(semester_name = %semester_name))]
     pub async fn for_semester(semester_name: &str, pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
@@ -99,6 +103,7 @@ impl AbsenceRequest {
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(event_id = event_id, email = %email))]
     pub async fn submit(event_id: i64, email: &str, reason: &str, pool: &PgPool) -> Result<()> {
         sqlx::query!(
             "INSERT INTO absence_requests (member, event, reason) VALUES ($1, $2, $3)",
@@ -112,6 +117,7 @@ impl AbsenceRequest {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(event_id = event_id, member = %member))]
     pub async fn set_state(
         event_id: i64,
         member: &str,
@@ -1,6 +1,10 @@
 use async_graphql::{ComplexObject, Context, InputObject, Result, SimpleObject};
-use sqlx::PgPool;
+use sqlx::postgres::PgConnection;
+use sqlx::{Connection, PgPool};
 
+use crate::error::ApiError;
+use crate::graphql::guards::{Decision, Permission};
+use crate::graphql::loader::{load_active_semester, load_event, load_member};
 use crate::models::event::absence_request::{AbsenceRequest, AbsenceRequestStatus};
 use crate::models::event::Event;
 use crate::models::member::active_semester::ActiveSemester;
@@ -30,8 +34,7 @@ pub struct Attendance {
 impl Attendance {
     /// The email of the member this attendance belongs to
     pub async fn member(&self, ctx: &Context<'_>) -> Result<Member> {
-        let pool: &PgPool = ctx.data_unchecked();
-        Member::with_email(&self.member, pool).await
+        load_member(ctx, &self.member).await
     }
 
     /// The absence request made by the current member, if they requested one
@@ -42,12 +45,10 @@ impl Attendance {
 
     /// If the member is not allowed to RSVP, this is why
     pub async fn rsvp_issue(&self, ctx: &Context<'_>) -> Result<Option<String>> {
-        let pool: &PgPool = ctx.data_unchecked();
-        let event = Event::with_id(self.event, pool).await?;
-        let is_active =
-            ActiveSemester::for_member_during_semester(&self.member, &event.semester, pool)
-                .await?
-                .is_some();
+        let event = load_event(ctx, self.event).await?;
+        let is_active = load_active_semester(ctx, &self.member, &event.semester)
+            .await?
+            .is_some();
 
         Ok(event.rsvp_issue_for(Some(self), is_active))
     }
@@ -68,18 +69,72 @@ impl Attendance {
 }
 
 impl Attendance {
+    /// Whether the current user can edit the given member's attendance at the event,
+    /// either with `EDIT_ATTENDANCE` or with `EDIT_ATTENDANCE_OWN_SECTION` for their section
+    pub async fn edit_decision(
+        ctx: &Context<'_>,
+        event: &Event,
+        email: &str,
+        pool: &PgPool,
+    ) -> Result<Decision> {
+        let decision = Permission::EDIT_ATTENDANCE
+            .for_type(&event.r#type)
+            .decide(ctx);
+        if decision.allowed {
+            return Ok(decision);
+        }
+
+        let own_section_decision = Permission::EDIT_ATTENDANCE_OWN_SECTION
+            .for_type(&event.r#type)
+            .decide(ctx);
+        let user = match ctx.data_opt::<Member>() {
+            Some(user) if own_section_decision.allowed => user,
+            _ => {
+                return Ok(Decision::deny(
+                    Permission::EDIT_ATTENDANCE
+                        .for_type(&event.r#type)
+                        .denied_because("Not allowed to edit attendance"),
+                ))
+            }
+        };
+
+        let user_section =
+            ActiveSemester::for_member_during_semester(&user.email, &event.semester, pool)
+                .await?
+                .map(|semester| semester.section);
+        let member_section =
+            ActiveSemester::for_member_during_semester(email, &event.semester, pool)
+                .await?
+                .map(|semester| semester.section);
+
+        if user_section.is_none() || user_section != member_section {
+            Ok(Decision::deny(
+                Permission::EDIT_ATTENDANCE_OWN_SECTION
+                    .for_type(&event.r#type)
+                    .denied_because(
+                        "Not allowed to edit attendance for members outside your section",
+                    ),
+            ))
+        } else {
+            Ok(own_section_decision)
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(email = %email, event_id = event_id))]
     pub async fn for_member_at_event(email: &str, event_id: i64, pool: &PgPool) -> Result<Self> {
         Self::for_member_at_event_opt(email, event_id, pool)
             .await?
             .ok_or_else(|| {
-                format!(
-                    "No attendance for member {} at event with ID {}",
-                    email, event_id
+                ApiError::not_found(
+                    "attendance",
+                    "member and event ID",
+                    format!("{} at {}", email, event_id),
                 )
             })
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(email = %email, event_id = event_id))]
     pub async fn for_member_at_event_opt(
         email: &str,
         event_id: i64,
@@ -97,6 +152,7 @@ impl Attendance {
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(event_id = event_id))]
     pub async fn for_event(event_id: i64, pool: &PgPool) -> Result<Vec<Self>> {
         // verify_exists
         Event::with_id(event_id, pool).await?;
@@ -112,8 +168,14 @@ impl Attendance {
         .map_err(Into::into)
     }
 
-    pub async fn create_for_new_member(email: &str, semester: &str, pool: &PgPool) -> Result<()> {
-        let events = Event::for_semester(semester, pool).await?;
+    #[tracing::instrument(skip_all, fields(email = %email, semester = %semester))]
+    pub async fn create_for_new_member(
+        email: &str,
+        semester: &str,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
+        let mut transaction = conn.begin().await?;
+        let events = Event::for_semester(semester, &mut transaction).await?;
 
         // make batch query
         let now = current_time();
@@ -130,16 +192,20 @@ impl Attendance {
                 should_attend,
                 email
             )
-            .execute(pool)
+            .execute(&mut *transaction)
             .await?;
         }
 
+        transaction.commit().await?;
+
         Ok(())
     }
 
-    pub async fn create_for_new_event(event_id: i64, pool: &PgPool) -> Result<()> {
-        let event = Event::with_id(event_id, pool).await?;
-        let active_members = Member::active_during(&event.semester, pool).await?;
+    #[tracing::instrument(skip_all, fields(event_id = event_id))]
+    pub async fn create_for_new_event(event_id: i64, conn: &mut PgConnection) -> Result<()> {
+        let mut transaction = conn.begin().await?;
+        let event = Event::with_id(event_id, &mut *transaction).await?;
+        let active_members = Member::active_during(&event.semester, &mut *transaction).await?;
 
         for member in active_members {
             sqlx::query!(
@@ -148,13 +214,16 @@ impl Attendance {
                 event.default_attend,
                 member.email
             )
-            .execute(pool)
+            .execute(&mut *transaction)
             .await?;
         }
 
+        transaction.commit().await?;
+
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(event_id = event_id))]
     pub async fn excuse_unconfirmed(event_id: i64, pool: &PgPool) -> Result<()> {
         Event::with_id(event_id, pool).await?;
 
@@ -168,6 +237,7 @@ impl Attendance {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(event_id = event_id, email = %email))]
     pub async fn update(
         event_id: i64,
         email: &str,
@@ -194,6 +264,7 @@ impl Attendance {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(event_id = event_id, email = %email))]
     pub async fn rsvp_for_event(
         event_id: i64,
         email: &str,
@@ -220,6 +291,7 @@ impl Attendance {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(event_id = event_id, email = %email))]
     pub async fn confirm_for_event(event_id: i64, email: &str, pool: &PgPool) -> Result<()> {
         // verify_exists
         Event::with_id(event_id, pool).await?;
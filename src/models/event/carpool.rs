@@ -1,6 +1,8 @@
 use async_graphql::{ComplexObject, Context, InputObject, Result, SimpleObject};
-use sqlx::PgPool;
+use sqlx::postgres::{PgConnection, PgExecutor};
+use sqlx::Connection;
 
+use crate::graphql::loader::{load_member, load_passengers};
 use crate::models::event::Event;
 use crate::models::member::Member;
 
@@ -21,60 +23,46 @@ pub struct Carpool {
 impl Carpool {
     /// The driver of the carpool
     pub async fn driver(&self, ctx: &Context<'_>) -> Result<Member> {
-        let pool: &PgPool = ctx.data_unchecked();
-        Member::with_email(&self.driver, &pool).await
+        load_member(ctx, &self.driver).await
     }
 
     /// The passengers of the carpool
     pub async fn passengers(&self, ctx: &Context<'_>) -> Result<Vec<Member>> {
-        let pool: &PgPool = ctx.data_unchecked();
-        sqlx::query_as!(
-            Member,
-            "SELECT email, first_name, preferred_name, last_name, phone_number, picture, passengers,
-                 location, on_campus, about, major, minor, hometown,
-                 arrived_at_tech, gateway_drug, conflicts, dietary_restrictions, pass_hash
-             FROM members WHERE email IN
-             (SELECT member FROM rides_in WHERE carpool = $1)
-             ORDER BY last_name, preferred_name, first_name",
-            self.id
-        )
-        .fetch_all(pool)
-        .await
-        .map_err(Into::into)
+        load_passengers(ctx, self.id).await
     }
 }
 
 impl Carpool {
-    pub async fn for_event(event_id: i64, pool: &PgPool) -> Result<Vec<Carpool>> {
+    #[tracing::instrument(skip_all, fields(event_id = event_id))]
+    pub async fn for_event(event_id: i64, db: impl PgExecutor<'_>) -> Result<Vec<Carpool>> {
         sqlx::query_as!(Self, "SELECT * FROM carpools WHERE event = $1", event_id)
-            .fetch_all(pool)
+            .fetch_all(db)
             .await
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(event_id = event_id))]
     pub async fn update(
         event_id: i64,
         updated_carpools: Vec<UpdatedCarpool>,
-        pool: &PgPool,
+        conn: &mut PgConnection,
     ) -> Result<()> {
+        let mut transaction = conn.begin().await?;
         // verify exists
-        Event::with_id(event_id, pool).await?;
+        Event::with_id(event_id, &mut *transaction).await?;
 
         sqlx::query!("DELETE FROM carpools WHERE event = $1", event_id)
-            .execute(pool)
+            .execute(&mut *transaction)
             .await?;
 
         for carpool in updated_carpools {
-            sqlx::query!(
-                "INSERT INTO carpools (event, driver) VALUES ($1, $2)",
+            let new_carpool_id = sqlx::query_scalar!(
+                "INSERT INTO carpools (event, driver) VALUES ($1, $2) RETURNING id",
                 event_id,
                 carpool.driver
             )
-            .execute(pool)
+            .fetch_one(&mut *transaction)
             .await?;
-            let new_carpool_id = sqlx::query_scalar!("SELECT id FROM carpools ORDER BY id DESC")
-                .fetch_one(pool)
-                .await?;
 
             for passenger in carpool.passengers {
                 sqlx::query!(
@@ -82,11 +70,13 @@ impl Carpool {
                     passenger,
                     new_carpool_id
                 )
-                .execute(pool)
+                .execute(&mut *transaction)
                 .await?;
             }
         }
 
+        transaction.commit().await?;
+
         Ok(())
     }
 }
@@ -99,3 +89,61 @@ pub struct UpdatedCarpool {
     /// The emails of the passengers
     pub passengers: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::models::event::carpool::{Carpool, UpdatedCarpool};
+    use crate::util::test_pool;
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn failed_update_keeps_existing_carpools() {
+        let pool = test_pool().await;
+        let mut transaction = pool.begin().await.unwrap();
+        for statement in [
+            "INSERT INTO semesters (name, start_date, end_date)
+             VALUES ('Rollback Test', '2000-01-01', '2000-06-01')",
+            "INSERT INTO event_types (name, weight) VALUES ('Rollback Test', 1)",
+            "INSERT INTO members (email, first_name, last_name, pass_hash, phone_number, location)
+             VALUES ('driver@rollback.test', 'Dee', 'Driver', '', '', '')",
+        ] {
+            sqlx::query(statement)
+                .execute(&mut transaction)
+                .await
+                .unwrap();
+        }
+        let event_id: i64 = sqlx::query_scalar(
+            "INSERT INTO events (name, semester, type, call_time, points)
+             VALUES ('Away Gig', 'Rollback Test', 'Rollback Test', '2000-02-05 18:00Z', 10)
+             RETURNING id",
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO carpools (event, driver) VALUES ($1, 'driver@rollback.test')")
+            .bind(event_id)
+            .execute(&mut transaction)
+            .await
+            .unwrap();
+
+        // the old carpools are deleted first, and the second driver doesn't exist
+        let updated_carpools = ["driver@rollback.test", "nobody@rollback.test"]
+            .into_iter()
+            .map(|driver| UpdatedCarpool {
+                driver: driver.to_owned(),
+                passengers: Vec::new(),
+            })
+            .collect();
+        assert!(
+            Carpool::update(event_id, updated_carpools, &mut transaction)
+                .await
+                .is_err()
+        );
+
+        let carpools = Carpool::for_event(event_id, &mut transaction)
+            .await
+            .unwrap();
+        assert_eq!(carpools.len(), 1);
+        assert_eq!(carpools[0].driver, "driver@rollback.test");
+    }
+}
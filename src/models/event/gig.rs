@@ -1,13 +1,17 @@
 use async_graphql::{ComplexObject, Context, Enum, InputObject, Result, SimpleObject};
+use sqlx::postgres::{PgConnection, PgExecutor};
 use sqlx::PgPool;
 use time::OffsetDateTime;
 
+use crate::error::ApiError;
+use crate::graphql::loader::{load_event, load_uniform};
+use crate::graphql::pagination::PageWindow;
 use crate::models::event::uniform::Uniform;
 use crate::models::event::Event;
 use crate::models::{DateTime, DateTimeInput, TimeScalar};
 
 /// The gig info included for an event, if it is a gig
-#[derive(SimpleObject)]
+#[derive(SimpleObject, Clone)]
 #[graphql(complex)]
 pub struct Gig {
     /// The ID of the event this gig belongs to
@@ -37,8 +41,7 @@ pub struct Gig {
 impl Gig {
     /// The uniform for this gig
     pub async fn uniform(&self, ctx: &Context<'_>) -> Result<Uniform> {
-        let pool: &PgPool = ctx.data_unchecked();
-        Uniform::with_id(self.uniform, pool).await
+        load_uniform(ctx, self.uniform).await
     }
 
     /// When members are expected to actually perform
@@ -48,7 +51,8 @@ impl Gig {
 }
 
 impl Gig {
-    pub async fn for_event(event_id: i64, pool: &PgPool) -> Result<Option<Self>> {
+    #[tracing::instrument(skip_all, fields(event_id = event_id))]
+    pub async fn for_event(event_id: i64, db: impl PgExecutor<'_>) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
             "SELECT event, performance_time as \"performance_time: _\", contact_name, contact_email,
@@ -56,11 +60,12 @@ impl Gig {
              FROM gigs WHERE event = $1",
             event_id
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
     pub async fn for_semester(semester: &str, pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
@@ -124,8 +129,7 @@ impl GigRequest {
     /// If and when an event is created from a request, this is the event
     pub async fn event(&self, ctx: &Context<'_>) -> Result<Option<Event>> {
         if let Some(event_id) = self.event {
-            let pool: &PgPool = ctx.data_unchecked();
-            Ok(Some(Event::with_id(event_id, pool).await?))
+            load_event(ctx, event_id).await.map(Some)
         } else {
             Ok(None)
         }
@@ -143,13 +147,15 @@ impl GigRequest {
 }
 
 impl GigRequest {
-    pub async fn with_id(id: i64, pool: &PgPool) -> Result<Self> {
-        Self::with_id_opt(id, pool)
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn with_id(id: i64, db: impl PgExecutor<'_>) -> Result<Self> {
+        Self::with_id_opt(id, db)
             .await?
-            .ok_or_else(|| format!("No gig request with ID {}", id).into())
+            .ok_or_else(|| ApiError::not_found("gig request", "ID", id).into())
     }
 
-    pub async fn with_id_opt(id: i64, pool: &PgPool) -> Result<Option<Self>> {
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn with_id_opt(id: i64, db: impl PgExecutor<'_>) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
             "SELECT id, \"time\" as \"time: _\", name, organization, contact_name, contact_phone, contact_email,
@@ -157,29 +163,54 @@ impl GigRequest {
              FROM gig_requests WHERE id = $1",
             id
         )
-            .fetch_optional(pool)
+            .fetch_optional(db)
             .await
             .map_err(Into::into)
     }
 
-    pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
+    /// How many gig requests there are, optionally with the given status
+    #[tracing::instrument(skip_all)]
+    pub async fn count(status: Option<GigRequestStatus>, pool: &PgPool) -> Result<i64> {
+        sqlx::query_scalar!(
+            "SELECT COUNT(*) as \"count!\" FROM gig_requests
+             WHERE ($1::gig_request_status IS NULL OR status = $1)",
+            status as _
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// A page of gig requests, optionally with the given status, oldest first
+    #[tracing::instrument(skip_all)]
+    pub async fn page(
+        status: Option<GigRequestStatus>,
+        window: PageWindow,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
             "SELECT id, \"time\" as \"time: _\", name, organization, contact_name, contact_phone, contact_email,
                  start_time as \"start_time: _\", location, comments, status as \"status: _\", event
-             FROM gig_requests ORDER BY time"
+             FROM gig_requests WHERE ($1::gig_request_status IS NULL OR status = $1)
+             ORDER BY time, id LIMIT $2 OFFSET $3",
+            status as _,
+            window.limit,
+            window.offset
         )
-            .fetch_all(pool)
-            .await
-            .map_err(Into::into)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn submit(new_request: NewGigRequest, pool: &PgPool) -> Result<i64> {
-        sqlx::query!(
+        sqlx::query_scalar!(
             "INSERT INTO gig_requests (
                 name, organization, contact_name, contact_phone,
                 contact_email, start_time, location, comments)
-             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             RETURNING id",
             new_request.name,
             new_request.organization,
             new_request.contact_name,
@@ -189,26 +220,42 @@ impl GigRequest {
             new_request.location,
             new_request.comments
         )
-        .execute(pool)
+        .fetch_one(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Marks the event that was created for the gig request
+    #[tracing::instrument(skip_all, fields(id = id, event_id = event_id))]
+    pub async fn link_event(id: i64, event_id: i64, db: impl PgExecutor<'_>) -> Result<()> {
+        sqlx::query!(
+            "UPDATE gig_requests SET event = $1 WHERE id = $2",
+            event_id,
+            id
+        )
+        .execute(db)
         .await?;
 
-        sqlx::query_scalar!("SELECT id FROM gig_requests ORDER BY id DESC")
-            .fetch_one(pool)
-            .await
-            .map_err(Into::into)
+        Ok(())
     }
 
-    pub async fn set_status(id: i64, status: GigRequestStatus, pool: &PgPool) -> Result<()> {
-        let request = Self::with_id(id, pool).await?;
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn set_status(
+        id: i64,
+        status: GigRequestStatus,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
+        let request = Self::with_id(id, &mut *conn).await?;
 
         if request.status == status {
             return Ok(());
         }
 
         match request.status {
-            GigRequestStatus::Accepted => {
-                Err("Cannot change the status of an accepted gig request".into())
-            }
+            GigRequestStatus::Accepted => Err(ApiError::conflict(
+                "Cannot change the status of an accepted gig request",
+            )
+            .into()),
             GigRequestStatus::Dismissed if status == GigRequestStatus::Accepted => Err(
                 "Cannot directly accept a gig request if it is dismissed (please reopen it first)"
                     .into(),
@@ -227,7 +274,7 @@ impl GigRequest {
                     status as _,
                     id
                 )
-                .execute(pool)
+                .execute(&mut *conn)
                 .await?;
 
                 Ok(())
@@ -235,8 +282,9 @@ impl GigRequest {
         }
     }
 
-    pub async fn build_new_gig(&self, pool: &PgPool) -> Result<NewGig> {
-        let default_uniform = Uniform::get_default(pool).await?;
+    #[tracing::instrument(skip_all)]
+    pub async fn build_new_gig(&self, db: impl PgExecutor<'_>) -> Result<NewGig> {
+        let default_uniform = Uniform::get_default(db).await?;
 
         Ok(NewGig {
             performance_time: DateTimeInput::from(self.start_time).time,
@@ -1,9 +1,13 @@
 use async_graphql::{ComplexObject, Context, Enum, InputObject, Result, SimpleObject};
-use sqlx::PgPool;
+use sqlx::postgres::{PgConnection, PgExecutor};
+use sqlx::{Connection, PgPool};
 use time::{Duration, OffsetDateTime};
 
 use super::{DateScalar, DateTimeInput};
+use crate::error::ApiError;
 use crate::graphql::guards::{LoggedIn, Permission};
+use crate::graphql::loader::{load_gig, load_setlist};
+use crate::graphql::pagination::PageWindow;
 use crate::models::event::attendance::Attendance;
 use crate::models::event::carpool::Carpool;
 use crate::models::event::gig::{Gig, GigRequest, GigRequestStatus, NewGig};
@@ -52,6 +56,17 @@ impl EventType {
     pub const OMBUDS: &'static str = "Ombuds";
     pub const OTHER: &'static str = "Other";
 
+    /// Every event type the API refers to, which must all exist in the database
+    pub const ALL: [&str; 6] = [
+        Self::REHEARSAL,
+        Self::SECTIONAL,
+        Self::VOLUNTEER_GIG,
+        Self::TUTTI_GIG,
+        Self::OMBUDS,
+        Self::OTHER,
+    ];
+
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM event_types ORDER BY name")
             .fetch_all(pool)
@@ -61,7 +76,7 @@ impl EventType {
 }
 
 /// An event where members are singing
-#[derive(SimpleObject)]
+#[derive(SimpleObject, Clone)]
 #[graphql(complex)]
 pub struct Event {
     /// The ID of the event
@@ -82,6 +97,8 @@ pub struct Event {
     pub gig_count: bool,
     /// Whether members are assumed to attend (we assume as much for most events)
     pub default_attend: bool,
+    /// Goes up with every edit, so updates based on an older version can be rejected
+    pub version: i64,
 
     #[graphql(skip)]
     pub call_time: OffsetDateTime,
@@ -103,8 +120,7 @@ impl Event {
 
     /// The gig for this event, if it is a gig
     pub async fn gig(&self, ctx: &Context<'_>) -> Result<Option<Gig>> {
-        let pool: &PgPool = ctx.data_unchecked();
-        Gig::for_event(self.id, pool).await
+        load_gig(ctx, self.id).await
     }
 
     /// The attendance for the current user at this event
@@ -129,7 +145,7 @@ impl Event {
     }
 
     /// Attendance for all current members for the event
-    #[graphql(guard = "LoggedIn")]
+    #[graphql(guard = "LoggedIn", complexity = "10 * child_complexity")]
     pub async fn all_attendance(
         &self,
         ctx: &Context<'_>,
@@ -140,16 +156,14 @@ impl Event {
         empty_if_not_permitted: bool,
     ) -> Result<Vec<Attendance>> {
         let pool: &PgPool = ctx.data_unchecked();
-        let user: &Member = ctx.data_unchecked();
         if !Permission::EDIT_ATTENDANCE
             .for_type(&self.r#type)
-            .granted_to(&user.email, pool)
-            .await?
+            .granted(ctx)
         {
             if empty_if_not_permitted {
                 return Ok(vec![]);
             } else {
-                return Err(Permission::EDIT_ATTENDANCE.error());
+                return Err(Permission::EDIT_ATTENDANCE.for_type(&self.r#type).error());
             }
         }
 
@@ -164,45 +178,95 @@ impl Event {
 
     /// All songs we plan to sing at this event, in order
     pub async fn setlist(&self, ctx: &Context<'_>) -> Result<Vec<Song>> {
-        let pool: &PgPool = ctx.data_unchecked();
-        Song::setlist_for_event(self.id, pool).await
+        load_setlist(ctx, self.id).await
     }
 }
 
 impl Event {
-    pub async fn with_id(id: i64, pool: &PgPool) -> Result<Self> {
-        Self::with_id_opt(id, pool)
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn with_id(id: i64, db: impl PgExecutor<'_>) -> Result<Self> {
+        Self::with_id_opt(id, db)
             .await?
-            .ok_or_else(|| format!("No event with id {}", id))
+            .ok_or_else(|| ApiError::not_found("event", "ID", id))
             .map_err(Into::into)
     }
 
-    pub async fn with_id_opt(id: i64, pool: &PgPool) -> Result<Option<Self>> {
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn with_id_opt(id: i64, db: impl PgExecutor<'_>) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
             "SELECT id, name, semester, type, call_time as \"call_time: _\",
                   release_time as \"release_time: _\", points, comments, location,
-                  gig_count, default_attend
+                  gig_count, default_attend, version
              FROM events WHERE id = $1",
             id
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await
         .map_err(Into::into)
     }
 
-    pub async fn for_semester(semester: &str, pool: &PgPool) -> Result<Vec<Self>> {
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn for_semester(semester: &str, conn: &mut PgConnection) -> Result<Vec<Self>> {
         // verify_exists
-        Semester::with_name(semester, pool).await?;
+        Semester::with_name(semester, &mut *conn).await?;
 
         sqlx::query_as!(
             Self,
             "SELECT id, name, semester, \"type\", call_time as \"call_time: _\",
                   release_time as \"release_time: _\", points, comments, location,
-                  gig_count, default_attend
+                  gig_count, default_attend, version
              FROM events WHERE semester = $1 ORDER BY call_time",
             semester
         )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// How many events in the semester match the filter
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn count(filter: &EventFilter, semester: &str, pool: &PgPool) -> Result<i64> {
+        sqlx::query_scalar!(
+            "SELECT COUNT(*) as \"count!\" FROM events
+             WHERE semester = $1 AND ($2::text IS NULL OR type = $2)
+                 AND ($3::timestamptz IS NULL OR call_time >= $3)
+                 AND ($4::timestamptz IS NULL OR call_time < $4)",
+            semester,
+            filter.r#type,
+            filter.from.clone().map(OffsetDateTime::from),
+            filter.until.clone().map(OffsetDateTime::from)
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// A page of the events that match the filter, in the order they happen
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn page(
+        filter: &EventFilter,
+        semester: &str,
+        window: PageWindow,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            "SELECT id, name, semester, \"type\", call_time as \"call_time: _\",
+                  release_time as \"release_time: _\", points, comments, location,
+                  gig_count, default_attend, version
+             FROM events
+             WHERE semester = $1 AND ($2::text IS NULL OR type = $2)
+                 AND ($3::timestamptz IS NULL OR call_time >= $3)
+                 AND ($4::timestamptz IS NULL OR call_time < $4)
+             ORDER BY call_time, id LIMIT $5 OFFSET $6",
+            semester,
+            filter.r#type,
+            filter.from.clone().map(OffsetDateTime::from),
+            filter.until.clone().map(OffsetDateTime::from),
+            window.limit,
+            window.offset
+        )
         .fetch_all(pool)
         .await
         .map_err(Into::into)
@@ -217,8 +281,12 @@ impl Event {
         attendance: Option<&Attendance>,
         is_active: bool,
     ) -> Result<()> {
-        if let Some(rsvp_issue) = self.rsvp_issue_for(attendance, is_active) {
-            Err(rsvp_issue.into())
+        if let Some(reason) = self.rsvp_issue_for(attendance, is_active) {
+            Err(ApiError::RsvpClosed {
+                event: self.id,
+                reason,
+            }
+            .into())
         } else {
             Ok(())
         }
@@ -248,15 +316,18 @@ impl Event {
         }
     }
 
+    /// Creates the event (and its repeats), returning the first one's ID. When made for
+    /// a gig request, the request is linked to that event and marked as accepted.
+    #[tracing::instrument(skip_all)]
     pub async fn create(
         new_event: NewEvent,
         from_request: Option<GigRequest>,
-        pool: &PgPool,
+        conn: &mut PgConnection,
     ) -> Result<i64> {
         let first_call_date = new_event.event.call_time.date.clone();
         if let Some(release_time) = &new_event.event.release_time {
             if release_time <= &new_event.event.call_time {
-                return Err("Release time must be after call time".into());
+                return Err(ApiError::validation("Release time must be after call time").into());
             }
         }
 
@@ -275,16 +346,18 @@ impl Event {
         };
 
         if call_and_release_times.is_empty() {
-            return Err("The repeat setting would render no events".into());
+            return Err(ApiError::validation("The repeat setting would render no events").into());
         }
 
-        let new_event_count = call_and_release_times.len();
+        let mut transaction = conn.begin().await?;
+        let mut new_ids = Vec::new();
         for (call_time, release_time) in call_and_release_times {
-            sqlx::query!(
+            let new_id = sqlx::query_scalar!(
                 "INSERT INTO events
                      (name, semester, \"type\", call_time, release_time, points,
                       comments, location, gig_count, default_attend)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                 RETURNING id",
                 new_event.event.name,
                 new_event.event.semester,
                 new_event.event.r#type,
@@ -296,24 +369,19 @@ impl Event {
                 new_event.event.gig_count,
                 new_event.event.default_attend
             )
-            .execute(pool)
+            .fetch_one(&mut *transaction)
             .await?;
+            new_ids.push(new_id);
         }
 
-        let new_ids = sqlx::query_scalar!(
-            "SELECT id FROM events ORDER BY id DESC LIMIT $1",
-            new_event_count as i64
-        )
-        .fetch_all(pool)
-        .await?;
         for new_id in &new_ids {
-            Attendance::create_for_new_event(*new_id, pool).await?;
+            Attendance::create_for_new_event(*new_id, &mut transaction).await?;
         }
 
         let gig = if new_event.gig.is_some() {
             new_event.gig
         } else if let Some(request) = &from_request {
-            Some(request.build_new_gig(pool).await?)
+            Some(request.build_new_gig(&mut *transaction).await?)
         } else {
             None
         };
@@ -341,28 +409,36 @@ impl Event {
                     gig.summary,
                     gig.description
                 )
-                .fetch_all(pool)
+                .fetch_all(&mut *transaction)
                 .await?;
             }
         }
 
+        // repeated events are represented by the first one
+        let first_id = *new_ids
+            .first()
+            .ok_or_else(|| ApiError::internal("Failed to find the new event's ID"))?;
+
         if let Some(request) = from_request {
-            GigRequest::set_status(request.id, GigRequestStatus::Accepted, pool).await?;
+            GigRequest::link_event(request.id, first_id, &mut *transaction).await?;
+            GigRequest::set_status(request.id, GigRequestStatus::Accepted, &mut transaction)
+                .await?;
         }
 
-        new_ids
-            .into_iter()
-            .last()
-            .ok_or_else(|| "Failed to find latest event ID".into())
+        transaction.commit().await?;
+
+        Ok(first_id)
     }
 
-    pub async fn update(id: i64, update: NewEvent, pool: &PgPool) -> Result<()> {
-        Self::with_id(id, pool).await?;
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn update(id: i64, update: NewEvent, conn: &mut PgConnection) -> Result<()> {
+        let mut transaction = conn.begin().await?;
 
-        sqlx::query!(
+        let updated = sqlx::query!(
             "UPDATE events SET name = $1, semester = $2, \"type\" = $3, call_time = $4, release_time = $5,
-                 points = $6, comments = $7, location = $8, gig_count = $9, default_attend = $10
-             WHERE id = $11",
+                 points = $6, comments = $7, location = $8, gig_count = $9, default_attend = $10,
+                 version = version + 1
+             WHERE id = $11 AND ($12::bigint IS NULL OR version = $12)",
             update.event.name,
             update.event.semester,
             update.event.r#type,
@@ -373,12 +449,18 @@ impl Event {
             update.event.location,
             update.event.gig_count,
             update.event.default_attend,
-            id
+            id,
+            update.version
         )
-        .execute(pool)
+        .execute(&mut *transaction)
         .await?;
 
-        if Gig::for_event(id, pool).await?.is_some() {
+        if updated.rows_affected() == 0 {
+            let current = Self::with_id(id, &mut *transaction).await?;
+            return Err(ApiError::stale_version("event", id, current.version).into());
+        }
+
+        if Gig::for_event(id, &mut *transaction).await?.is_some() {
             if let Some(gig) = update.gig {
                 let performance_time = OffsetDateTime::from(DateTime {
                     date: update.event.call_time.date,
@@ -388,13 +470,16 @@ impl Event {
                     "UPDATE gigs SET performance_time = $1, uniform = $2, contact_name = $3, contact_email = $4,
                      contact_phone = $5, price = $6, public = $7, summary = $8, description = $9
                      WHERE event = $10", performance_time, gig.uniform, gig.contact_name, gig.contact_email,
-                    gig.contact_phone, gig.price, gig.public, gig.summary, gig.description, id).execute(pool).await?;
+                    gig.contact_phone, gig.price, gig.public, gig.summary, gig.description, id).execute(&mut *transaction).await?;
             }
         }
 
+        transaction.commit().await?;
+
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn delete(id: i64, pool: &PgPool) -> Result<()> {
         // verify exists
         Event::with_id(id, pool).await?;
@@ -407,6 +492,17 @@ impl Event {
     }
 }
 
+/// Which events to load for a semester
+#[derive(InputObject, Default)]
+pub struct EventFilter {
+    /// Only load events of this type
+    pub r#type: Option<String>,
+    /// Only load events with a call time at or after this
+    pub from: Option<DateTimeInput>,
+    /// Only load events with a call time before this
+    pub until: Option<DateTimeInput>,
+}
+
 /// A new event, broken into different groups of fields
 #[derive(InputObject)]
 pub struct NewEvent {
@@ -416,6 +512,9 @@ pub struct NewEvent {
     pub gig: Option<NewGig>,
     /// How often to optionally repeat the event
     pub repeat: Option<NewEventPeriod>,
+    /// The version being edited, if the edit should be rejected when it's out of date
+    /// (ignored when creating events)
+    pub version: Option<i64>,
 }
 
 /// The event-specific fields on a new event
@@ -475,10 +574,17 @@ impl NewEventPeriod {
 
 #[cfg(test)]
 mod tests {
-    use time::{Date, Duration, Month};
-
-    use crate::models::event::{NewEventPeriod, Period};
-    use crate::models::DateScalar;
+    use async_graphql::Value;
+    use futures_util::future::join_all;
+    use sqlx::PgPool;
+    use time::{Date, Duration, Month, Time};
+
+    use crate::error::ApiError;
+    use crate::models::event::carpool::{Carpool, UpdatedCarpool};
+    use crate::models::event::gig::{Gig, GigRequest, GigRequestStatus, NewGig};
+    use crate::models::event::{Event, NewEvent, NewEventFields, NewEventPeriod, Period};
+    use crate::models::{DateScalar, DateTimeInput, TimeScalar};
+    use crate::util::{test_pool, with_cleanup};
 
     #[test]
     fn event_times_generates_correctly() {
@@ -498,4 +604,252 @@ mod tests {
         assert_eq!(event_times.len(), 5);
         assert_eq!(event_times[4].0.date(), end_date);
     }
+
+    /// Five weekly events for a semester and event type that share a name
+    fn weekly_events(name: &str, test: &str, gig: Option<NewGig>) -> NewEvent {
+        let start_date = Date::from_calendar_date(2000, Month::February, 5).unwrap();
+
+        NewEvent {
+            event: NewEventFields {
+                name: name.to_owned(),
+                semester: test.to_owned(),
+                r#type: test.to_owned(),
+                call_time: DateTimeInput {
+                    date: DateScalar(start_date),
+                    time: TimeScalar(Time::from_hms(18, 0, 0).unwrap()),
+                },
+                release_time: None,
+                points: 10,
+                comments: Some(String::new()),
+                location: Some(String::new()),
+                gig_count: Some(true),
+                default_attend: true,
+            },
+            gig,
+            repeat: Some(NewEventPeriod {
+                period: Period::Weekly,
+                repeat_until: DateScalar(start_date + Duration::weeks(4)),
+            }),
+            version: None,
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn failed_create_leaves_no_events_behind() {
+        let pool = test_pool().await;
+        let mut transaction = pool.begin().await.unwrap();
+        sqlx::query(
+            "INSERT INTO semesters (name, start_date, end_date)
+             VALUES ('Rollback Test', '2000-01-01', '2000-06-01')",
+        )
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO event_types (name, weight) VALUES ('Rollback Test', 1)")
+            .execute(&mut transaction)
+            .await
+            .unwrap();
+
+        // the gigs are inserted after the events, and this uniform doesn't exist
+        let gig = NewGig {
+            performance_time: TimeScalar(Time::from_hms(19, 0, 0).unwrap()),
+            uniform: -1,
+            contact_name: "Contact".to_owned(),
+            contact_email: "contact@example.com".to_owned(),
+            contact_phone: "5555555555".to_owned(),
+            price: None,
+            public: false,
+            summary: String::new(),
+            description: String::new(),
+        };
+        let new_event = weekly_events("Weekly Gig", "Rollback Test", Some(gig));
+
+        assert!(Event::create(new_event, None, &mut transaction)
+            .await
+            .is_err());
+
+        let events: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM events WHERE semester = 'Rollback Test'")
+                .fetch_one(&mut transaction)
+                .await
+                .unwrap();
+        assert_eq!(events, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn creating_an_event_accepts_its_gig_request() {
+        let pool = test_pool().await;
+        let mut transaction = pool.begin().await.unwrap();
+        sqlx::query(
+            "INSERT INTO semesters (name, start_date, end_date)
+             VALUES ('Gig Request Test', '2000-01-01', '2000-06-01')",
+        )
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO event_types (name, weight) VALUES ('Gig Request Test', 1)")
+            .execute(&mut transaction)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO uniforms (name, description) VALUES ('Gig Request Test', '')")
+            .execute(&mut transaction)
+            .await
+            .unwrap();
+        let request_id: i64 = sqlx::query_scalar(
+            "INSERT INTO gig_requests (name, organization, contact_name, contact_phone,
+                 contact_email, start_time, location)
+             VALUES ('Requested Gig', 'Org', 'Contact', '5555555555',
+                 'contact@example.com', '2000-02-05 18:00:00+00', 'Somewhere')
+             RETURNING id",
+        )
+        .fetch_one(&mut transaction)
+        .await
+        .unwrap();
+
+        let request = GigRequest::with_id(request_id, &mut transaction)
+            .await
+            .unwrap();
+        let new_event = weekly_events("Requested Gig", "Gig Request Test", None);
+        let id = Event::create(new_event, Some(request), &mut transaction)
+            .await
+            .unwrap();
+
+        let request = GigRequest::with_id(request_id, &mut transaction)
+            .await
+            .unwrap();
+        assert_eq!(request.event, Some(id));
+        assert!(request.status == GigRequestStatus::Accepted);
+        assert!(Gig::for_event(id, &mut transaction)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn updates_from_an_old_version_are_rejected() {
+        let pool = test_pool().await;
+        let mut transaction = pool.begin().await.unwrap();
+        sqlx::query(
+            "INSERT INTO semesters (name, start_date, end_date)
+             VALUES ('Version Test', '2000-01-01', '2000-06-01')",
+        )
+        .execute(&mut transaction)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO event_types (name, weight) VALUES ('Version Test', 1)")
+            .execute(&mut transaction)
+            .await
+            .unwrap();
+
+        let id = Event::create(
+            weekly_events("Original", "Version Test", None),
+            None,
+            &mut transaction,
+        )
+        .await
+        .unwrap();
+
+        let mut first_edit = weekly_events("First Edit", "Version Test", None);
+        first_edit.version = Some(0);
+        Event::update(id, first_edit, &mut transaction)
+            .await
+            .unwrap();
+
+        let mut second_edit = weekly_events("Second Edit", "Version Test", None);
+        second_edit.version = Some(0);
+        let error = Event::update(id, second_edit, &mut transaction)
+            .await
+            .unwrap_err();
+        let extensions = error.extensions.as_ref().unwrap();
+        assert_eq!(
+            extensions.get("code"),
+            Some(&Value::from(ApiError::CONFLICT))
+        );
+        assert_eq!(extensions.get("currentVersion"), Some(&Value::from(1)));
+
+        let event = Event::with_id(id, &mut transaction).await.unwrap();
+        assert_eq!(event.name, "First Edit");
+        assert_eq!(event.version, 1);
+    }
+
+    /// Creates a series of events with a carpool, and checks that both ended up where they should
+    async fn create_with_carpool(index: usize, pool: &PgPool) {
+        let name = format!("Concurrent {}", index);
+        let driver = format!("driver{}@concurrency.test", index);
+        let passenger = format!("passenger{}@concurrency.test", index);
+        let mut conn = pool.acquire().await.unwrap();
+
+        let new_event = weekly_events(&name, "Concurrency Test", None);
+        let id = Event::create(new_event, None, &mut conn).await.unwrap();
+        let carpool = UpdatedCarpool {
+            driver: driver.clone(),
+            passengers: vec![passenger.clone()],
+        };
+        Carpool::update(id, vec![carpool], &mut conn).await.unwrap();
+
+        let series: Vec<i64> =
+            sqlx::query_scalar("SELECT id FROM events WHERE name = $1 ORDER BY call_time")
+                .bind(&name)
+                .fetch_all(&mut *conn)
+                .await
+                .unwrap();
+        assert_eq!(series.len(), 5);
+        assert_eq!(series[0], id);
+
+        let carpools = Carpool::for_event(id, &mut *conn).await.unwrap();
+        assert_eq!(carpools.len(), 1);
+        assert_eq!(carpools[0].driver, driver);
+        let passengers: Vec<String> =
+            sqlx::query_scalar("SELECT member FROM rides_in WHERE carpool = $1")
+                .bind(carpools[0].id)
+                .fetch_all(&mut *conn)
+                .await
+                .unwrap();
+        assert_eq!(passengers, [passenger]);
+    }
+
+    // The rows are committed so that every connection can see the others' writes
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn concurrent_creates_find_their_own_rows() {
+        const CREATES: usize = 8;
+        let cleanup = [
+            "DELETE FROM semesters WHERE name = 'Concurrency Test'",
+            "DELETE FROM event_types WHERE name = 'Concurrency Test'",
+            "DELETE FROM members WHERE email LIKE '%@concurrency.test'",
+        ];
+
+        with_cleanup(&cleanup, |pool| async move {
+            sqlx::query(
+                "INSERT INTO semesters (name, start_date, end_date)
+                 VALUES ('Concurrency Test', '2000-01-01', '2000-06-01')",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query("INSERT INTO event_types (name, weight) VALUES ('Concurrency Test', 1)")
+                .execute(&pool)
+                .await
+                .unwrap();
+            for index in 0..CREATES {
+                for role in ["driver", "passenger"] {
+                    sqlx::query(
+                        "INSERT INTO members
+                         (email, first_name, last_name, pass_hash, phone_number, location)
+                         VALUES ($1, 'Cam', 'Carpool', '', '', '')",
+                    )
+                    .bind(format!("{}{}@concurrency.test", role, index))
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+                }
+            }
+
+            join_all((0..CREATES).map(|index| create_with_carpool(index, &pool))).await;
+        })
+        .await;
+    }
 }
@@ -84,6 +84,7 @@ impl PublicEvent {
 }
 
 impl PublicEvent {
+    #[tracing::instrument(skip_all)]
     pub async fn all_for_current_semester(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
@@ -2,10 +2,12 @@ use async_graphql::{
     InputObject, InputValueError, InputValueResult, Result, Scalar, ScalarType, SimpleObject, Value,
 };
 use regex::Regex;
+use sqlx::postgres::PgExecutor;
 use sqlx::PgPool;
 
+use crate::error::ApiError;
 /// A uniform members might need to wear for an event
-#[derive(SimpleObject)]
+#[derive(SimpleObject, Clone)]
 pub struct Uniform {
     /// The ID of the uniform
     pub id: i64,
@@ -15,10 +17,12 @@ pub struct Uniform {
     pub color: Option<UniformColor>,
     /// The explanation of what to wear when wearing the uniform
     pub description: String,
+    /// Goes up with every edit, so updates based on an older version can be rejected
+    pub version: i64,
 }
 
 /// A color for a uniform when rendered on the site
-#[derive(sqlx::Type)]
+#[derive(sqlx::Type, Clone)]
 #[sqlx(transparent)]
 pub struct UniformColor(String);
 
@@ -45,16 +49,18 @@ impl ScalarType for UniformColor {
 }
 
 impl Uniform {
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id(id: i64, pool: &PgPool) -> Result<Self> {
         Self::with_id_opt(id, pool)
             .await?
-            .ok_or_else(|| format!("No uniform with ID {}", id).into())
+            .ok_or_else(|| ApiError::not_found("uniform", "ID", id).into())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id_opt(id: i64, pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT id, name, color as \"color: _\", description
+            "SELECT id, name, color as \"color: _\", description, version
              FROM uniforms WHERE id = $1",
             id
         )
@@ -63,10 +69,11 @@ impl Uniform {
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT id, name, color as \"color: _\", description
+            "SELECT id, name, color as \"color: _\", description, version
              FROM uniforms ORDER BY name"
         )
         .fetch_all(pool)
@@ -74,50 +81,54 @@ impl Uniform {
         .map_err(Into::into)
     }
 
-    pub async fn get_default(pool: &PgPool) -> Result<Self> {
+    #[tracing::instrument(skip_all)]
+    pub async fn get_default(db: impl PgExecutor<'_>) -> Result<Self> {
         sqlx::query_as!(
             Self,
-            "SELECT id, name, color as \"color: _\", description
+            "SELECT id, name, color as \"color: _\", description, version
              FROM uniforms ORDER BY name"
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await?
-        .ok_or_else(|| "There are currently no uniforms".into())
+        .ok_or_else(|| ApiError::validation("There are currently no uniforms").into())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn create(new_uniform: NewUniform, pool: &PgPool) -> Result<i64> {
-        sqlx::query!(
-            "INSERT INTO uniforms (name, color, description) VALUES ($1, $2, $3)",
+        sqlx::query_scalar!(
+            "INSERT INTO uniforms (name, color, description) VALUES ($1, $2, $3) RETURNING id",
             new_uniform.name,
             new_uniform.color.map(|c| c.0),
             new_uniform.description
         )
-        .execute(pool)
-        .await?;
-
-        sqlx::query_scalar!("SELECT id FROM uniforms ORDER BY id DESC")
-            .fetch_one(pool)
-            .await
-            .map_err(Into::into)
+        .fetch_one(pool)
+        .await
+        .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn update(id: i64, update: NewUniform, pool: &PgPool) -> Result<()> {
-        // verify exists
-        Uniform::with_id(id, pool).await?;
-
-        sqlx::query!(
-            "UPDATE uniforms SET name = $1, color = $2, description = $3 WHERE id = $4",
+        let updated = sqlx::query!(
+            "UPDATE uniforms SET name = $1, color = $2, description = $3, version = version + 1
+             WHERE id = $4 AND ($5::bigint IS NULL OR version = $5)",
             update.name,
             update.color as _,
             update.description,
-            id
+            id,
+            update.version
         )
         .execute(pool)
         .await?;
 
+        if updated.rows_affected() == 0 {
+            let current = Uniform::with_id(id, pool).await?;
+            return Err(ApiError::stale_version("uniform", id, current.version).into());
+        }
+
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn delete(id: i64, pool: &PgPool) -> Result<()> {
         sqlx::query!("DELETE FROM uniforms WHERE id = $1", id)
             .execute(pool)
@@ -136,4 +147,7 @@ pub struct NewUniform {
     pub color: Option<UniformColor>,
     /// A description of the uniform
     pub description: String,
+    /// The version being edited, if the edit should be rejected when it's out of date
+    /// (ignored when creating a uniform)
+    pub version: Option<i64>,
 }
@@ -41,13 +41,14 @@ pub struct GradesContext {
 }
 
 impl GradesContext {
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
     pub async fn for_members_during_semester(
         emails: &Vec<String>,
         semester: &str,
         pool: &PgPool,
     ) -> Result<Self> {
         let semester = Semester::with_name(semester, pool).await?;
-        let events = Event::for_semester(&semester.name, pool)
+        let events = Event::for_semester(&semester.name, &mut *pool.acquire().await?)
             .await?
             .into_iter()
             .map(Arc::new)
@@ -122,6 +123,7 @@ impl GradesContext {
 }
 
 impl AttendanceContext {
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
     async fn for_members_during_semester(
         emails: &Vec<String>,
         semester: &str,
@@ -46,6 +46,7 @@ pub struct GradeChange {
 }
 
 impl Grades {
+    #[tracing::instrument(skip_all, fields(email = %email, semester = %semester))]
     pub async fn for_member(email: &str, semester: &str, pool: &PgPool) -> Result<Grades> {
         let now = current_time();
         let context =
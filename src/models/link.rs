@@ -1,6 +1,7 @@
 use async_graphql::{Result, SimpleObject};
 use sqlx::PgPool;
 
+use crate::error::ApiError;
 /// A link to a Google Doc or other important document
 #[derive(SimpleObject)]
 pub struct DocumentLink {
@@ -11,12 +12,14 @@ pub struct DocumentLink {
 }
 
 impl DocumentLink {
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn with_name(name: &str, pool: &PgPool) -> Result<Self> {
         Self::with_name_opt(name, pool)
             .await?
-            .ok_or_else(|| format!("No document named {}", name).into())
+            .ok_or_else(|| ApiError::not_found("document", "name", name).into())
     }
 
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn with_name_opt(name: &str, pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM document_links WHERE name = $1", name)
             .fetch_optional(pool)
@@ -24,6 +27,7 @@ impl DocumentLink {
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM document_links ORDER BY name")
             .fetch_all(pool)
@@ -31,9 +35,12 @@ impl DocumentLink {
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn create(name: &str, url: &str, pool: &PgPool) -> Result<()> {
         if Self::with_name_opt(name, pool).await?.is_some() {
-            return Err(format!("A document named {} already exists", name).into());
+            return Err(
+                ApiError::conflict(format!("A document named {} already exists", name)).into(),
+            );
         }
 
         sqlx::query!(
@@ -47,6 +54,7 @@ impl DocumentLink {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn set_url(name: &str, url: &str, pool: &PgPool) -> Result<()> {
         // verify exists
         Self::with_name(name, pool).await?;
@@ -62,6 +70,7 @@ impl DocumentLink {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn delete(name: &str, pool: &PgPool) -> Result<()> {
         // verify exists
         Self::with_name(name, pool).await?;
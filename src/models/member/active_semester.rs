@@ -1,10 +1,12 @@
 use async_graphql::{ComplexObject, Context, Enum, InputObject, Result, SimpleObject};
-use sqlx::PgPool;
+use sqlx::postgres::{PgConnection, PgExecutor};
+use sqlx::{Connection, PgPool};
 
+use crate::error::ApiError;
 use crate::models::grades::Grades;
 
 /// A semester the given member was active during
-#[derive(SimpleObject)]
+#[derive(SimpleObject, Clone)]
 #[graphql(complex)]
 pub struct ActiveSemester {
     /// The email of the member
@@ -20,6 +22,7 @@ pub struct ActiveSemester {
 #[ComplexObject]
 impl ActiveSemester {
     /// The grades for the member in the given semester
+    #[graphql(complexity = "50 + child_complexity")]
     pub async fn grades(&self, ctx: &Context<'_>) -> Result<Grades> {
         let pool: &PgPool = ctx.data_unchecked();
         Grades::for_member(&self.member, &self.semester, pool).await
@@ -37,6 +40,7 @@ pub enum Enrollment {
 }
 
 impl ActiveSemester {
+    #[tracing::instrument(skip_all, fields(member = %member))]
     pub async fn all_for_member(member: &str, pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
@@ -52,10 +56,11 @@ impl ActiveSemester {
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(member = %member, semester = %semester))]
     pub async fn for_member_during_semester(
         member: &str,
         semester: &str,
-        pool: &PgPool,
+        db: impl PgExecutor<'_>,
     ) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
@@ -64,43 +69,58 @@ impl ActiveSemester {
             member,
             semester
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await
         .map_err(Into::into)
     }
 
-    pub async fn create_for_member(new_semester: NewActiveSemester, pool: &PgPool) -> Result<()> {
-        if Self::for_member_during_semester(&new_semester.member, &new_semester.semester, pool)
-            .await?
-            .is_some()
+    #[tracing::instrument(skip_all)]
+    pub async fn create_for_member(
+        new_semester: NewActiveSemester,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
+        let mut transaction = conn.begin().await?;
+        if Self::for_member_during_semester(
+            &new_semester.member,
+            &new_semester.semester,
+            &mut *transaction,
+        )
+        .await?
+        .is_some()
         {
-            return Err("Member is already active for the current semester".into());
+            return Err(
+                ApiError::conflict("Member is already active for the current semester").into(),
+            );
         }
 
         sqlx::query!(
             "INSERT INTO active_semesters (member, semester, enrollment, section) VALUES ($1, $2, $3, $4)",
             new_semester.member, new_semester.semester, new_semester.enrollment as _, new_semester.section
-        ).execute(pool).await?;
+        ).execute(&mut *transaction).await?;
+        transaction.commit().await?;
 
         Ok(())
     }
 
-    pub async fn update(update: NewActiveSemester, pool: &PgPool) -> Result<()> {
+    #[tracing::instrument(skip_all)]
+    pub async fn update(update: NewActiveSemester, conn: &mut PgConnection) -> Result<()> {
+        let mut transaction = conn.begin().await?;
         let active_semester =
-            Self::for_member_during_semester(&update.member, &update.semester, pool).await?;
+            Self::for_member_during_semester(&update.member, &update.semester, &mut *transaction)
+                .await?;
 
         match (update.enrollment, active_semester) {
             (Some(enrollment), Some(_active_semester)) => {
                 sqlx::query!(
                     "UPDATE active_semesters SET enrollment = $1, section = $2 WHERE member = $3 AND semester = $4",
                     enrollment as _, update.section, update.member, update.semester
-                ).execute(pool).await?;
+                ).execute(&mut *transaction).await?;
             }
             (Some(enrollment), None) => {
                 sqlx::query!(
                     "INSERT INTO active_semesters (member, semester, enrollment, section) VALUES ($1, $2, $3, $4)",
                     update.member, update.semester, enrollment as _, update.section
-                ).execute(pool).await?;
+                ).execute(&mut *transaction).await?;
             }
             (None, Some(_active_semester)) => {
                 sqlx::query!(
@@ -108,12 +128,14 @@ impl ActiveSemester {
                     update.member,
                     update.semester
                 )
-                .execute(pool)
+                .execute(&mut *transaction)
                 .await?;
             }
             (None, None) => {}
         }
 
+        transaction.commit().await?;
+
         Ok(())
     }
 }
@@ -0,0 +1,171 @@
+use async_graphql::Result;
+use sqlx::postgres::PgConnection;
+use sqlx::{Connection, PgPool};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::email::send_email;
+use crate::email::verify_email::VerifyEmailEmail;
+use crate::error::ApiError;
+use crate::models::event::attendance::Attendance;
+use crate::models::member::security_event::{SecurityEvent, SecurityEventKind};
+use crate::models::member::Member;
+use crate::models::semester::Semester;
+use crate::util::{current_time, hash_token};
+
+/// A single-use link for a member to confirm their email address
+pub struct EmailVerification {
+    /// The email of the member
+    pub member: String,
+    /// When the verification email was sent
+    pub time: OffsetDateTime,
+    /// Whether the member registered with this email, rather than changing to it
+    pub registration: bool,
+}
+
+impl EmailVerification {
+    /// How long a verification link can be used after it is sent
+    pub const EXPIRATION: Duration = Duration::WEEK;
+
+    /// Creates a new verification link for the member, replacing any previous one.
+    /// The email with the link should only be sent once the transaction is committed
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn generate(
+        email: &str,
+        registration: bool,
+        conn: &mut PgConnection,
+    ) -> Result<VerifyEmailEmail> {
+        let mut transaction = conn.begin().await?;
+        let member = Member::with_email(email, &mut *transaction).await?;
+        if member.email_verified {
+            return Err(ApiError::conflict("Your email has already been verified").into());
+        }
+
+        let new_token = Uuid::new_v4().to_string();
+        sqlx::query!(
+            "INSERT INTO email_verifications (member, token_hash, registration)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (member) DO UPDATE
+             SET token_hash = $2, time = now(),
+                 registration = email_verifications.registration OR $3",
+            email,
+            hash_token(&new_token),
+            registration
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(VerifyEmailEmail {
+            member,
+            token: new_token,
+        })
+    }
+
+    /// Sends a verification email for a change that was already saved, which shouldn't
+    /// fail because of it since the member can always ask for another link
+    pub async fn send_or_log(email: VerifyEmailEmail) {
+        let member = email.member.email.clone();
+        if let Err(error) = send_email(email).await {
+            tracing::error!(
+                member = %member,
+                error = %format!("{error:#}"),
+                "Failed to send verification email"
+            );
+        }
+    }
+
+    /// Confirms the member's email using an emailed token, which can only be used once
+    #[tracing::instrument(skip_all)]
+    pub async fn verify_from_token(token: &str, ip: &str, pool: &PgPool) -> Result<String> {
+        let mut transaction = pool.begin().await?;
+        let verification = sqlx::query_as!(
+            EmailVerification,
+            "DELETE FROM email_verifications WHERE token_hash = $1
+             RETURNING member, time, registration",
+            hash_token(token)
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or_else(|| {
+            ApiError::Unauthenticated(
+                "No pending verification was found for the given token, please request another"
+                    .to_owned(),
+            )
+        })?;
+
+        if verification.time + Self::EXPIRATION < current_time() {
+            return Err(ApiError::Unauthenticated(
+                "Your link expired after a week, please request another".to_owned(),
+            )
+            .into());
+        }
+
+        Self::mark_verified(&verification, None, Some(ip), &mut transaction).await?;
+        transaction.commit().await?;
+
+        Ok(verification.member)
+    }
+
+    /// Lets an officer confirm a member's email without a link
+    #[tracing::instrument(skip_all, fields(email = %email, officer = %officer))]
+    pub async fn override_for(email: &str, officer: &str, pool: &PgPool) -> Result<()> {
+        let mut transaction = pool.begin().await?;
+        let member = Member::with_email(email, &mut *transaction).await?;
+        if member.email_verified {
+            return Err(ApiError::conflict(format!("{} has already been verified", email)).into());
+        }
+
+        let verification = sqlx::query_as!(
+            EmailVerification,
+            "DELETE FROM email_verifications WHERE member = $1
+             RETURNING member, time, registration",
+            email
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        .unwrap_or(EmailVerification {
+            member: member.email,
+            time: current_time(),
+            registration: false,
+        });
+
+        Self::mark_verified(&verification, Some(officer), None, &mut transaction).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// New members only get attendance for the semester once they've been verified
+    #[tracing::instrument(skip_all)]
+    async fn mark_verified(
+        verification: &EmailVerification,
+        actor: Option<&str>,
+        ip: Option<&str>,
+        conn: &mut PgConnection,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE members SET email_verified = true WHERE email = $1",
+            verification.member
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        if verification.registration {
+            let current_semester = Semester::get_current(&mut *conn).await?;
+            Attendance::create_for_new_member(&verification.member, &current_semester.name, conn)
+                .await?;
+        }
+
+        SecurityEvent::record_by(
+            SecurityEventKind::EmailVerified,
+            &verification.member,
+            actor,
+            ip,
+            None,
+            conn,
+        )
+        .await
+    }
+}
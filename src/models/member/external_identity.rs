@@ -0,0 +1,176 @@
+use async_graphql::{ComplexObject, Result, SimpleObject};
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::models::member::security_event::{SecurityEvent, SecurityEventKind};
+use crate::models::DateTime;
+use crate::util::{current_time, hash_token};
+
+/// An account at a single sign-on provider that can log in as a member
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct ExternalIdentity {
+    /// The provider's issuer URL
+    pub issuer: String,
+    /// The provider's ID for the account
+    pub subject: String,
+    /// The email the provider has for the account
+    pub email: String,
+
+    #[graphql(skip)]
+    pub member: String,
+    #[graphql(skip)]
+    pub created_at: OffsetDateTime,
+}
+
+#[ComplexObject]
+impl ExternalIdentity {
+    /// When the account was linked
+    pub async fn created_at(&self) -> DateTime {
+        DateTime::from(self.created_at)
+    }
+}
+
+impl ExternalIdentity {
+    /// How long a member has to claim a provider account after signing in with it
+    pub const PENDING_LINK_LIFETIME: Duration = Duration::minutes(15);
+
+    #[tracing::instrument(skip_all, fields(issuer = %issuer))]
+    pub async fn for_subject_opt(
+        issuer: &str,
+        subject: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>> {
+        sqlx::query_as!(
+            Self,
+            "SELECT issuer, subject, member, email, created_at
+             FROM external_identities WHERE issuer = $1 AND subject = $2",
+            issuer,
+            subject
+        )
+        .fetch_optional(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip_all, fields(member = %member))]
+    pub async fn for_member(member: &str, pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            "SELECT issuer, subject, member, email, created_at
+             FROM external_identities WHERE member = $1 ORDER BY created_at",
+            member
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Lets the provider account log in as the member from now on
+    #[tracing::instrument(skip_all, fields(issuer = %issuer, email = %email, member = %member))]
+    pub async fn link(
+        issuer: &str,
+        subject: &str,
+        email: &str,
+        member: &str,
+        pool: &PgPool,
+    ) -> Result<Self> {
+        sqlx::query!(
+            "INSERT INTO external_identities (issuer, subject, member, email)
+             VALUES ($1, $2, $3, $4)",
+            issuer,
+            subject,
+            member,
+            email
+        )
+        .execute(pool)
+        .await?;
+        SecurityEvent::record(
+            SecurityEventKind::SingleSignOnLinked,
+            member,
+            None,
+            Some(&format!("{} account {}", issuer, email)),
+            pool,
+        )
+        .await?;
+
+        Self::for_subject_opt(issuer, subject, pool)
+            .await?
+            .ok_or_else(|| ApiError::not_found("single sign-on account", "subject", subject).into())
+    }
+
+    /// Saves a provider account that didn't match any member, returning
+    /// a code a logged in member can use to claim it
+    #[tracing::instrument(skip_all, fields(issuer = %issuer, email = %email))]
+    pub async fn create_pending_link(
+        issuer: &str,
+        subject: &str,
+        email: &str,
+        pool: &PgPool,
+    ) -> Result<String> {
+        sqlx::query!(
+            "DELETE FROM pending_identity_links WHERE created_at < $1",
+            current_time() - Self::PENDING_LINK_LIFETIME
+        )
+        .execute(pool)
+        .await?;
+
+        let code = Uuid::new_v4().to_string();
+        sqlx::query!(
+            "INSERT INTO pending_identity_links (code_hash, issuer, subject, email)
+             VALUES ($1, $2, $3, $4)",
+            hash_token(&code),
+            issuer,
+            subject,
+            email
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(code)
+    }
+
+    /// Links a pending provider account to the given member
+    #[tracing::instrument(skip_all, fields(member = %member))]
+    pub async fn claim_pending_link(code: &str, member: &str, pool: &PgPool) -> Result<Self> {
+        let pending = sqlx::query!(
+            "DELETE FROM pending_identity_links WHERE code_hash = $1
+             RETURNING issuer, subject, email, created_at",
+            hash_token(code)
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| {
+            ApiError::Unauthenticated(
+                "No single sign-on account is waiting to be linked with that code".to_owned(),
+            )
+        })?;
+
+        if pending.created_at + Self::PENDING_LINK_LIFETIME < current_time() {
+            return Err(ApiError::Unauthenticated(
+                "That single sign-on link has expired, please sign in again".to_owned(),
+            )
+            .into());
+        }
+        if Self::for_subject_opt(&pending.issuer, &pending.subject, pool)
+            .await?
+            .is_some()
+        {
+            return Err(ApiError::conflict(
+                "That single sign-on account is already linked to a member",
+            )
+            .into());
+        }
+
+        Self::link(
+            &pending.issuer,
+            &pending.subject,
+            &pending.email,
+            member,
+            pool,
+        )
+        .await
+    }
+}
@@ -0,0 +1,310 @@
+use std::cmp::Reverse;
+
+use async_graphql::{ComplexObject, Enum, Result, SimpleObject};
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+
+use crate::error::ApiError;
+use crate::models::DateTime;
+use crate::util::current_time;
+
+/// The kind of unauthenticated request being throttled
+#[derive(Clone, Copy, PartialEq, Eq, Enum, sqlx::Type)]
+#[sqlx(type_name = "login_attempt_kind", rename_all = "snake_case")]
+pub enum LoginAttemptKind {
+    /// A login with the wrong email or password
+    Login,
+    /// A request for a password reset email
+    PasswordReset,
+}
+
+/// How quickly repeated attempts get locked out
+pub struct Backoff {
+    /// How many attempts are allowed before any lockout
+    pub free_attempts: i64,
+    /// The first lockout, which doubles with each further attempt
+    pub base_delay: Duration,
+    /// The longest a single lockout can last
+    pub max_delay: Duration,
+}
+
+impl Backoff {
+    /// When the next attempt is allowed, if it has to wait at all
+    pub fn locked_until(
+        &self,
+        attempts: i64,
+        last_attempt: OffsetDateTime,
+    ) -> Option<OffsetDateTime> {
+        if attempts < self.free_attempts {
+            return None;
+        }
+
+        let mut delay = self.base_delay;
+        for _ in self.free_attempts..attempts {
+            if delay >= self.max_delay {
+                break;
+            }
+            delay *= 2;
+        }
+
+        Some(last_attempt + delay.min(self.max_delay))
+    }
+}
+
+impl LoginAttemptKind {
+    /// The backoff for attempts against a single account
+    pub fn account_backoff(self) -> Backoff {
+        match self {
+            LoginAttemptKind::Login => Backoff {
+                free_attempts: 5,
+                base_delay: Duration::seconds(30),
+                max_delay: Duration::hours(1),
+            },
+            LoginAttemptKind::PasswordReset => Backoff {
+                free_attempts: 3,
+                base_delay: Duration::minutes(5),
+                max_delay: Duration::hours(6),
+            },
+        }
+    }
+
+    /// The backoff for attempts from a single IP address, which is looser
+    /// since many members share the campus network
+    pub fn ip_backoff(self) -> Backoff {
+        match self {
+            LoginAttemptKind::Login => Backoff {
+                free_attempts: 20,
+                base_delay: Duration::seconds(30),
+                max_delay: Duration::hours(1),
+            },
+            LoginAttemptKind::PasswordReset => Backoff {
+                free_attempts: 10,
+                base_delay: Duration::minutes(5),
+                max_delay: Duration::hours(6),
+            },
+        }
+    }
+}
+
+pub struct LoginAttempt;
+
+impl LoginAttempt {
+    /// How long an attempt counts against an account or IP address
+    pub const WINDOW: Duration = Duration::days(1);
+
+    /// Fails with a `LOCKED_OUT` error if the account or IP address
+    /// has to wait before trying again
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn ensure_allowed(
+        kind: LoginAttemptKind,
+        email: &str,
+        ip: &str,
+        pool: &PgPool,
+    ) -> Result<()> {
+        let email = email.to_lowercase();
+        let since = current_time() - Self::WINDOW;
+
+        let for_account = sqlx::query!(
+            "SELECT count(*) as \"attempts!\", max(time) as \"last_attempt: OffsetDateTime\"
+             FROM login_attempts WHERE kind = $1 AND email = $2 AND time > $3",
+            kind as _,
+            email,
+            since
+        )
+        .fetch_one(pool)
+        .await?;
+        let for_ip = sqlx::query!(
+            "SELECT count(*) as \"attempts!\", max(time) as \"last_attempt: OffsetDateTime\"
+             FROM login_attempts WHERE kind = $1 AND ip = $2 AND time > $3",
+            kind as _,
+            ip,
+            since
+        )
+        .fetch_one(pool)
+        .await?;
+
+        let account_lockout = for_account.last_attempt.and_then(|last_attempt| {
+            kind.account_backoff()
+                .locked_until(for_account.attempts, last_attempt)
+        });
+        let ip_lockout = for_ip.last_attempt.and_then(|last_attempt| {
+            kind.ip_backoff()
+                .locked_until(for_ip.attempts, last_attempt)
+        });
+
+        match account_lockout.max(ip_lockout) {
+            Some(locked_until) if locked_until > current_time() => {
+                Err(ApiError::locked_out(locked_until - current_time()).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn record(
+        kind: LoginAttemptKind,
+        email: &str,
+        ip: &str,
+        pool: &PgPool,
+    ) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO login_attempts (kind, email, ip) VALUES ($1, $2, $3)",
+            kind as _,
+            email.to_lowercase(),
+            ip
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Forgets an account's failed logins after it logs in successfully
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn clear_failed_logins(email: &str, pool: &PgPool) -> Result<()> {
+        sqlx::query!(
+            "DELETE FROM login_attempts WHERE kind = $1 AND email = $2",
+            LoginAttemptKind::Login as _,
+            email.to_lowercase()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Clears all attempts for the given account and/or IP address
+    #[tracing::instrument(skip_all)]
+    pub async fn clear(email: Option<&str>, ip: Option<&str>, pool: &PgPool) -> Result<()> {
+        if email.is_none() && ip.is_none() {
+            return Err(ApiError::validation(
+                "Must provide an email or IP address to clear lockouts for",
+            )
+            .into());
+        }
+
+        sqlx::query!(
+            "DELETE FROM login_attempts WHERE email = $1 OR ip = $2",
+            email.map(str::to_lowercase),
+            ip
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// An account or IP address that has used up its free attempts
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Lockout {
+    /// What was being attempted
+    pub kind: LoginAttemptKind,
+    /// The account being attempted, if locked out by account
+    pub email: Option<String>,
+    /// The client's IP address, if locked out by IP address
+    pub ip: Option<String>,
+    /// How many attempts have been made recently
+    pub attempts: i64,
+
+    #[graphql(skip)]
+    pub last_attempt: OffsetDateTime,
+}
+
+#[ComplexObject]
+impl Lockout {
+    /// When the next attempt will be allowed
+    pub async fn locked_until(&self) -> Option<DateTime> {
+        self.locked_until_inner().map(DateTime::from)
+    }
+}
+
+impl Lockout {
+    pub fn locked_until_inner(&self) -> Option<OffsetDateTime> {
+        let backoff = if self.email.is_some() {
+            self.kind.account_backoff()
+        } else {
+            self.kind.ip_backoff()
+        };
+
+        backoff.locked_until(self.attempts, self.last_attempt)
+    }
+
+    /// All accounts and IP addresses that have run out of free attempts
+    #[tracing::instrument(skip_all)]
+    pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
+        let since = current_time() - LoginAttempt::WINDOW;
+
+        let mut lockouts = sqlx::query_as!(
+            Self,
+            "SELECT kind as \"kind: _\", email as \"email?\", NULL as \"ip?: String\",
+                 count(*) as \"attempts!\", max(time) as \"last_attempt!: _\"
+             FROM login_attempts WHERE time > $1
+             GROUP BY kind, email",
+            since
+        )
+        .fetch_all(pool)
+        .await?;
+        lockouts.extend(
+            sqlx::query_as!(
+                Self,
+                "SELECT kind as \"kind: _\", NULL as \"email?: String\", ip as \"ip?\",
+                     count(*) as \"attempts!\", max(time) as \"last_attempt!: _\"
+                 FROM login_attempts WHERE time > $1
+                 GROUP BY kind, ip",
+                since
+            )
+            .fetch_all(pool)
+            .await?,
+        );
+
+        lockouts.retain(|lockout| lockout.locked_until_inner().is_some());
+        lockouts.sort_by_key(|lockout| Reverse(lockout.last_attempt));
+
+        Ok(lockouts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Duration, Month};
+
+    use crate::models::member::login_attempt::Backoff;
+
+    #[test]
+    fn backoff_doubles_until_max_delay() {
+        let backoff = Backoff {
+            free_attempts: 5,
+            base_delay: Duration::seconds(30),
+            max_delay: Duration::minutes(5),
+        };
+        let last = Date::from_calendar_date(2022, Month::August, 1)
+            .unwrap()
+            .with_hms(12, 0, 0)
+            .unwrap()
+            .assume_utc();
+
+        assert_eq!(backoff.locked_until(4, last), None);
+        assert_eq!(
+            backoff.locked_until(5, last),
+            Some(last + Duration::seconds(30))
+        );
+        assert_eq!(
+            backoff.locked_until(6, last),
+            Some(last + Duration::minutes(1))
+        );
+        assert_eq!(
+            backoff.locked_until(8, last),
+            Some(last + Duration::minutes(4))
+        );
+        assert_eq!(
+            backoff.locked_until(9, last),
+            Some(last + Duration::minutes(5))
+        );
+        assert_eq!(
+            backoff.locked_until(500, last),
+            Some(last + Duration::minutes(5))
+        );
+    }
+}
@@ -1,17 +1,33 @@
 use async_graphql::{ComplexObject, Context, InputObject, Result, SimpleObject};
-use sqlx::PgPool;
+use sqlx::postgres::{PgConnection, PgExecutor};
+use sqlx::{Connection, PgPool};
 
+use crate::email::verify_email::VerifyEmailEmail;
+use crate::error::ApiError;
 use crate::graphql::guards::{LoggedIn, Permission};
+use crate::graphql::pagination::PageWindow;
 use crate::models::event::attendance::Attendance;
 use crate::models::grades::Grades;
 use crate::models::member::active_semester::{ActiveSemester, Enrollment, NewActiveSemester};
+use crate::models::member::email_verification::EmailVerification;
+use crate::models::member::password::{is_legacy_hash, Password};
+use crate::models::member::privacy::Visibility;
+use crate::models::member::security_event::{SecurityEvent, SecurityEventKind};
 use crate::models::member::session::Session;
+use crate::models::member::two_factor::TwoFactor;
 use crate::models::money::ClubTransaction;
 use crate::models::permissions::{MemberPermission, Role};
 use crate::models::semester::Semester;
 
 pub mod active_semester;
+pub mod email_verification;
+pub mod external_identity;
+pub mod login_attempt;
+pub mod password;
+pub mod privacy;
+pub mod security_event;
 pub mod session;
+pub mod two_factor;
 
 /// A member in the Glee Club
 #[derive(SimpleObject, Clone)]
@@ -25,14 +41,10 @@ pub struct Member {
     pub preferred_name: Option<String>,
     /// The member's last name
     pub last_name: String,
-    /// The member's phone number
-    pub phone_number: String,
     /// An optional link to a profile picture for the member
     pub picture: String,
     /// How many people the member can drive to events (besides themself)
     pub passengers: i64,
-    /// Where the member lives
-    pub location: String,
     /// Whether the member lives on campus
     pub on_campus: bool,
     /// A short biography written by the member
@@ -47,11 +59,21 @@ pub struct Member {
     pub arrived_at_tech: Option<i64>,
     /// What got them to join Glee Club
     pub gateway_drug: String,
-    /// What conflicts with rehearsal the member may have
+    /// Whether the member has confirmed they can receive mail at their email
+    pub email_verified: bool,
+
+    #[graphql(skip)]
+    pub phone_number: String,
+    #[graphql(skip)]
+    pub location: String,
+    #[graphql(skip)]
     pub conflicts: String,
-    /// Any dietary restrictions the member may have
+    #[graphql(skip)]
     pub dietary_restrictions: String,
-
+    #[graphql(skip)]
+    pub share_phone_number: bool,
+    #[graphql(skip)]
+    pub share_location: bool,
     #[graphql(skip)]
     pub pass_hash: String,
 }
@@ -63,6 +85,76 @@ impl Member {
         self.full_name_inner()
     }
 
+    /// The member's phone number, if they share it or the current user can see it anyway
+    pub async fn phone_number(&self, ctx: &Context<'_>) -> Option<&str> {
+        Visibility::shared_or_officers(self.share_phone_number)
+            .allows(ctx, &self.email)
+            .then_some(self.phone_number.as_str())
+    }
+
+    /// Where the member lives, if they share it or the current user can see it anyway
+    pub async fn location(&self, ctx: &Context<'_>) -> Option<&str> {
+        Visibility::shared_or_officers(self.share_location)
+            .allows(ctx, &self.email)
+            .then_some(self.location.as_str())
+    }
+
+    /// What conflicts with rehearsal the member may have, only visible to officers
+    pub async fn conflicts(&self, ctx: &Context<'_>) -> Option<&str> {
+        Visibility::Officers
+            .allows(ctx, &self.email)
+            .then_some(self.conflicts.as_str())
+    }
+
+    /// Any dietary restrictions the member may have, only visible to officers
+    pub async fn dietary_restrictions(&self, ctx: &Context<'_>) -> Option<&str> {
+        Visibility::Officers
+            .allows(ctx, &self.email)
+            .then_some(self.dietary_restrictions.as_str())
+    }
+
+    /// Whether the member shares their phone number with all members,
+    /// only visible to the member themself
+    pub async fn share_phone_number(&self, ctx: &Context<'_>) -> Option<bool> {
+        Visibility::OnlySelf
+            .allows(ctx, &self.email)
+            .then_some(self.share_phone_number)
+    }
+
+    /// Whether the member shares where they live with all members,
+    /// only visible to the member themself
+    pub async fn share_location(&self, ctx: &Context<'_>) -> Option<bool> {
+        Visibility::OnlySelf
+            .allows(ctx, &self.email)
+            .then_some(self.share_location)
+    }
+
+    /// The officer logged in as this member, if the current user is being impersonated
+    pub async fn impersonated_by(&self, ctx: &Context<'_>) -> Result<Option<Member>> {
+        let pool: &PgPool = ctx.data_unchecked();
+        match ctx.data_opt::<Session>() {
+            Some(Session {
+                member,
+                impersonated_by: Some(impersonator),
+                ..
+            }) if member == &self.email => Member::with_email(impersonator, pool).await.map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether the member has turned on two-factor authentication
+    pub async fn two_factor_enabled(&self, ctx: &Context<'_>) -> Result<bool> {
+        let pool: &PgPool = ctx.data_unchecked();
+        TwoFactor::is_enabled(&self.email, pool).await
+    }
+
+    /// Whether the member's roles grant permissions that only take effect
+    /// with two-factor authentication turned on
+    pub async fn two_factor_required(&self, ctx: &Context<'_>) -> Result<bool> {
+        let pool: &PgPool = ctx.data_unchecked();
+        TwoFactor::is_required(&self.email, pool).await
+    }
+
     /// Info on the member for the current semester, if they are active
     pub async fn semester(&self, ctx: &Context<'_>) -> Result<Option<ActiveSemester>> {
         let pool: &PgPool = ctx.data_unchecked();
@@ -84,6 +176,7 @@ impl Member {
     }
 
     /// Info for each semester the member was active
+    #[graphql(complexity = "5 * child_complexity")]
     pub async fn semesters(&self, ctx: &Context<'_>) -> Result<Vec<ActiveSemester>> {
         let pool: &PgPool = ctx.data_unchecked();
         ActiveSemester::all_for_member(&self.email, pool).await
@@ -101,15 +194,12 @@ impl Member {
     }
 
     /// The grades for the member in the given semester (default the current semester)
-    #[graphql(guard = "LoggedIn")]
+    #[graphql(guard = "LoggedIn", complexity = "50 + child_complexity")]
     pub async fn grades(&self, ctx: &Context<'_>, semester: Option<String>) -> Result<Grades> {
         let pool: &PgPool = ctx.data_unchecked();
-        let user = ctx.data_unchecked::<Member>();
-        if &user.email != &self.email {
-            Permission::VIEW_USER_PRIVATE_DETAILS
-                .ensure_granted_to(&user.email, pool)
-                .await?;
-        }
+        Permission::VIEW_USER_PRIVATE_DETAILS
+            .decide_for_member(ctx, &self.email)
+            .into_result()?;
 
         let semester = if let Some(name) = semester {
             name
@@ -124,105 +214,188 @@ impl Member {
     #[graphql(guard = "LoggedIn")]
     pub async fn transactions(&self, ctx: &Context<'_>) -> Result<Vec<ClubTransaction>> {
         let pool: &PgPool = ctx.data_unchecked();
-        let user = ctx.data_unchecked::<Member>();
-        if user.email != self.email {
-            Permission::VIEW_USER_PRIVATE_DETAILS
-                .ensure_granted_to(&user.email, pool)
-                .await?;
-        }
+        Permission::VIEW_USER_PRIVATE_DETAILS
+            .decide_for_member(ctx, &self.email)
+            .into_result()?;
 
         ClubTransaction::for_member(&self.email, pool).await
     }
 }
 
-pub struct IncludeContext {
-    pub class: bool,
-    pub club: bool,
-    pub inactive: bool,
+/// Which members to load for the roster
+#[derive(InputObject)]
+pub struct MemberFilter {
+    /// Load members that are active in the class for this semester
+    #[graphql(default = true)]
+    pub include_class: bool,
+    /// Load members that are registered in the club for this semester
+    #[graphql(default = true)]
+    pub include_club: bool,
+    /// Load members that are currently inactive
+    #[graphql(default = false)]
+    pub include_inactive: bool,
+    /// Only load members in this section for this semester
+    pub section: Option<String>,
+    /// Only load members whose name or email contains this
+    pub search: Option<String>,
+}
+
+impl Default for MemberFilter {
+    fn default() -> Self {
+        Self {
+            include_class: true,
+            include_club: true,
+            include_inactive: false,
+            section: None,
+            search: None,
+        }
+    }
+}
+
+impl MemberFilter {
+    /// Matches the search anywhere, treating `%` and `_` in it as plain characters
+    fn search_pattern(&self) -> Option<String> {
+        self.search.as_ref().map(|search| {
+            let escaped = search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+    }
 }
 
 impl Member {
-    pub async fn with_email(email: &str, pool: &PgPool) -> Result<Member> {
-        Self::with_email_opt(email, pool)
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn with_email(email: &str, db: impl PgExecutor<'_>) -> Result<Member> {
+        Self::with_email_opt(email, db)
             .await?
-            .ok_or_else(|| format!("No member with email {}", email).into())
+            .ok_or_else(|| ApiError::not_found("member", "email", email).into())
     }
 
-    pub async fn with_email_opt(email: &str, pool: &PgPool) -> Result<Option<Member>> {
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn with_email_opt(email: &str, db: impl PgExecutor<'_>) -> Result<Option<Member>> {
         sqlx::query_as!(
             Member,
             "SELECT email, first_name, preferred_name, last_name, phone_number, picture, passengers,
                  location, on_campus, about, major, minor, hometown,
-                 arrived_at_tech, gateway_drug, conflicts, dietary_restrictions, pass_hash
+                 arrived_at_tech, gateway_drug, conflicts, dietary_restrictions, pass_hash,
+                 email_verified, share_phone_number, share_location
              FROM members WHERE email = $1",
             email
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await
         .map_err(Into::into)
     }
 
-    pub async fn with_token(token: &str, pool: &PgPool) -> Result<Member> {
-        let session = Session::with_token(token, pool).await?;
-        Self::with_email(&session.member, pool).await
+    /// How many members match the filter for the given semester
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn count(filter: &MemberFilter, semester: &str, pool: &PgPool) -> Result<i64> {
+        sqlx::query_scalar!(
+            "SELECT COUNT(*) as \"count!\"
+             FROM members m
+             LEFT JOIN active_semesters a ON a.member = m.email AND a.semester = $1
+             WHERE (CASE a.enrollment WHEN 'class' THEN $2::boolean
+                        WHEN 'club' THEN $3::boolean ELSE $4::boolean END)
+                 AND ($5::text IS NULL OR a.section = $5)
+                 AND ($6::text IS NULL OR m.first_name || ' ' || m.last_name ILIKE $6
+                     OR m.preferred_name ILIKE $6 OR m.email ILIKE $6)",
+            semester,
+            filter.include_class,
+            filter.include_club,
+            filter.include_inactive,
+            filter.section,
+            filter.search_pattern()
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(Into::into)
     }
 
-    pub async fn all_included(
-        included: IncludeContext,
+    /// A page of the members that match the filter for the given semester, sorted by name
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn page(
+        filter: &MemberFilter,
         semester: &str,
+        window: PageWindow,
         pool: &PgPool,
     ) -> Result<Vec<Self>> {
-        let all_members = sqlx::query_as!(
+        sqlx::query_as!(
             Self,
-            "SELECT email, first_name, preferred_name, last_name, phone_number, picture, passengers,
-                 location, on_campus, about, major, minor, hometown,
-                 arrived_at_tech, gateway_drug, conflicts, dietary_restrictions, pass_hash
-             FROM members ORDER BY last_name, first_name",
-        )
-        .fetch_all(pool)
-        .await?;
-        let active_semesters = sqlx::query_as!(
-            ActiveSemester,
-            "SELECT member, enrollment as \"enrollment: _\", semester, section
-             FROM active_semesters WHERE semester = $1",
-            semester
+            "SELECT m.email, m.first_name, m.preferred_name, m.last_name, m.phone_number, m.picture,
+                 m.passengers, m.location, m.on_campus, m.about, m.major, m.minor, m.hometown,
+                 m.arrived_at_tech, m.gateway_drug, m.conflicts, m.dietary_restrictions, m.pass_hash,
+                 m.email_verified, m.share_phone_number, m.share_location
+             FROM members m
+             LEFT JOIN active_semesters a ON a.member = m.email AND a.semester = $1
+             WHERE (CASE a.enrollment WHEN 'class' THEN $2::boolean
+                        WHEN 'club' THEN $3::boolean ELSE $4::boolean END)
+                 AND ($5::text IS NULL OR a.section = $5)
+                 AND ($6::text IS NULL OR m.first_name || ' ' || m.last_name ILIKE $6
+                     OR m.preferred_name ILIKE $6 OR m.email ILIKE $6)
+             ORDER BY m.last_name, m.first_name, m.email
+             LIMIT $7 OFFSET $8",
+            semester,
+            filter.include_class,
+            filter.include_club,
+            filter.include_inactive,
+            filter.section,
+            filter.search_pattern(),
+            window.limit,
+            window.offset
         )
         .fetch_all(pool)
-        .await?;
-
-        Ok(all_members
-            .into_iter()
-            .filter(|m| {
-                let enrollment = active_semesters
-                    .iter()
-                    .find(|a| a.member == m.email)
-                    .map(|a| a.enrollment);
-
-                match enrollment {
-                    Some(Enrollment::Class) => included.class,
-                    Some(Enrollment::Club) => included.club,
-                    None => included.inactive,
-                }
-            })
-            .collect())
+        .await
+        .map_err(Into::into)
     }
 
     /// The members that were active during the given semester
-    pub async fn active_during(semester: &str, pool: &PgPool) -> Result<Vec<Self>> {
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn active_during(semester: &str, db: impl PgExecutor<'_>) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
             "SELECT email, first_name, preferred_name, last_name, phone_number, picture, passengers,
                  location, on_campus, about, major, minor, hometown,
-                 arrived_at_tech, gateway_drug, conflicts, dietary_restrictions, pass_hash
+                 arrived_at_tech, gateway_drug, conflicts, dietary_restrictions, pass_hash,
+                 email_verified, share_phone_number, share_location
              FROM members WHERE email IN
              (SELECT member FROM active_semesters WHERE semester = $1)",
             semester
         )
-        .fetch_all(pool)
+        .fetch_all(db)
         .await
         .map_err(Into::into)
     }
 
+    pub fn ensure_email_verified(&self) -> Result<()> {
+        if self.email_verified {
+            Ok(())
+        } else {
+            Err(ApiError::EmailUnverified.into())
+        }
+    }
+
+    /// Updates who the member shares their contact details with
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn update_privacy(
+        email: &str,
+        share_phone_number: bool,
+        share_location: bool,
+        pool: &PgPool,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE members SET share_phone_number = $1, share_location = $2 WHERE email = $3",
+            share_phone_number,
+            share_location,
+            email
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
     pub fn full_name_inner(&self) -> String {
         format!(
             "{} {}",
@@ -234,34 +407,60 @@ impl Member {
         )
     }
 
-    pub async fn login_is_valid(email: &str, pass_hash: &str, pool: &PgPool) -> Result<bool> {
-        if let Some(hash) =
+    /// Checks the member's password, upgrading a legacy hash to argon2id
+    /// when the member logs in with their plaintext password
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn login_is_valid(email: &str, password: &Password, pool: &PgPool) -> Result<bool> {
+        let hash = if let Some(hash) =
             sqlx::query_scalar!("SELECT pass_hash FROM members WHERE email = $1", email)
                 .fetch_optional(pool)
                 .await?
         {
-            Ok(bcrypt::verify(pass_hash, &hash).unwrap_or(false))
+            hash
         } else {
-            Ok(false)
+            return Ok(false);
+        };
+
+        if !password.verify(&hash) {
+            return Ok(false);
         }
+
+        if is_legacy_hash(&hash) && matches!(password, Password::Plaintext(_)) {
+            sqlx::query!(
+                "UPDATE members SET pass_hash = $1 WHERE email = $2",
+                password.hash()?,
+                email
+            )
+            .execute(pool)
+            .await?;
+        }
+
+        Ok(true)
     }
 
-    pub async fn register(new_member: NewMember, pool: &PgPool) -> Result<()> {
+    /// Adds the member, returning the email that asks them to verify their address
+    #[tracing::instrument(skip_all)]
+    pub async fn register(
+        new_member: NewMember,
+        conn: &mut PgConnection,
+    ) -> Result<VerifyEmailEmail> {
+        let mut transaction = conn.begin().await?;
         if sqlx::query!(
             "SELECT email FROM members WHERE email = $1",
             new_member.email
         )
-        .fetch_optional(pool)
+        .fetch_optional(&mut *transaction)
         .await?
         .is_some()
         {
-            return Err(
-                format!("Another member already has the email {}", new_member.email).into(),
-            );
+            return Err(ApiError::conflict(format!(
+                "Another member already has the email {}",
+                new_member.email
+            ))
+            .into());
         }
 
-        let pass_hash = bcrypt::hash(new_member.pass_hash, 10)
-            .map_err(|err| format!("Failed to hash password: {}", err))?;
+        let pass_hash = Password::from_fields(new_member.password, new_member.pass_hash)?.hash()?;
 
         sqlx::query!(
             "INSERT INTO members
@@ -289,22 +488,28 @@ impl Member {
             new_member.conflicts,
             new_member.dietary_restrictions
         )
-        .execute(pool)
+        .execute(&mut *transaction)
         .await?;
 
-        let current_semester = Semester::get_current(pool).await?;
-        Attendance::create_for_new_member(&new_member.email, &current_semester.name, pool).await
+        // attendance is only created once the new member confirms their email
+        let verification_email =
+            EmailVerification::generate(&new_member.email, true, &mut transaction).await?;
+        transaction.commit().await?;
+
+        Ok(verification_email)
     }
 
+    #[tracing::instrument(skip_all, fields(email = %email))]
     pub async fn register_for_current_semester(
         email: String,
         form: RegisterForSemesterForm,
-        pool: &PgPool,
+        conn: &mut PgConnection,
     ) -> Result<()> {
-        let current_semester = Semester::get_current(pool).await?;
+        let mut transaction = conn.begin().await?;
+        let current_semester = Semester::get_current(&mut *transaction).await?;
         ActiveSemester::create_for_member(
             form.active_semester(email.clone(), current_semester.name.clone()),
-            pool,
+            &mut transaction,
         )
         .await?;
 
@@ -317,40 +522,48 @@ impl Member {
             form.dietary_restrictions,
             email
         )
-        .execute(pool)
+        .execute(&mut *transaction)
         .await?;
 
-        Attendance::create_for_new_member(&email, &current_semester.name, pool).await
+        Attendance::create_for_new_member(&email, &current_semester.name, &mut transaction).await?;
+        transaction.commit().await?;
+
+        Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(email = %email))]
     pub async fn update(
         email: &str,
         update: MemberUpdate,
         as_self: bool,
-        pool: &PgPool,
-    ) -> Result<()> {
+        conn: &mut PgConnection,
+    ) -> Result<Option<VerifyEmailEmail>> {
+        let mut transaction = conn.begin().await?;
         if email != &update.email
             && sqlx::query!("SELECT email FROM members WHERE email = $1", update.email)
-                .fetch_optional(pool)
+                .fetch_optional(&mut *transaction)
                 .await?
                 .is_some()
         {
-            return Err(format!(
+            return Err(ApiError::conflict(format!(
                 "Cannot change email to {}, as another member has that email",
                 update.email
-            )
+            ))
             .into());
         }
 
-        let pass_hash = if let Some(new_hash) = update.pass_hash {
+        let new_password = Password::from_optional_fields(update.password, update.pass_hash)?;
+        let pass_hash = if let Some(password) = &new_password {
             if as_self {
-                bcrypt::hash(new_hash, 10)?
+                password.hash()?
             } else {
-                return Err("Only members themselves can change their own passwords".into());
+                return Err(Permission::EDIT_USER
+                    .denied_because("Only members themselves can change their own passwords")
+                    .into());
             }
         } else {
             sqlx::query_scalar!("SELECT pass_hash FROM members WHERE email = $1", email)
-                .fetch_one(pool)
+                .fetch_one(&mut *transaction)
                 .await?
         };
 
@@ -380,19 +593,46 @@ impl Member {
             pass_hash,
             email
         )
-        .execute(pool)
+        .execute(&mut *transaction)
         .await?;
 
-        let current_semester = Semester::get_current(pool).await?;
+        let verification_email = if email != update.email {
+            sqlx::query!(
+                "UPDATE members SET email_verified = false WHERE email = $1",
+                update.email
+            )
+            .execute(&mut *transaction)
+            .await?;
+            Some(EmailVerification::generate(&update.email, false, &mut transaction).await?)
+        } else {
+            None
+        };
+
+        if new_password.is_some() {
+            SecurityEvent::record(
+                SecurityEventKind::PasswordChanged,
+                &update.email,
+                None,
+                None,
+                &mut *transaction,
+            )
+            .await?;
+        }
+
+        let current_semester = Semester::get_current(&mut *transaction).await?;
         let active_semester_update = NewActiveSemester {
             member: email.to_owned(),
             semester: current_semester.name,
             enrollment: update.enrollment,
             section: update.section,
         };
-        ActiveSemester::update(active_semester_update, pool).await
+        ActiveSemester::update(active_semester_update, &mut transaction).await?;
+        transaction.commit().await?;
+
+        Ok(verification_email)
     }
 
+    #[tracing::instrument(skip_all, fields(email = %email))]
     pub async fn delete(email: &str, pool: &PgPool) -> Result<()> {
         // verify member exists
         Member::with_email(email, pool).await?;
@@ -413,6 +653,7 @@ pub struct SectionType {
 }
 
 impl SectionType {
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM section_types ORDER BY name")
             .fetch_all(pool)
@@ -432,8 +673,10 @@ pub struct NewMember {
     pub preferred_name: Option<String>,
     /// The member's last name
     pub last_name: String,
-    /// The member's password, MD5-encrypted
-    pub pass_hash: String,
+    /// The member's password
+    pub password: Option<String>,
+    /// Deprecated: the member's password, MD5-encrypted. Use `password` instead
+    pub pass_hash: Option<String>,
     /// The member's phone number
     pub phone_number: String,
     /// An optional link to a profile picture for the member
@@ -478,7 +721,9 @@ pub struct MemberUpdate {
     pub preferred_name: Option<String>,
     /// The member's last name
     pub last_name: String,
-    /// The member's password (MD5-encrypted), if they want to update it
+    /// The member's new password, if they want to update it
+    pub password: Option<String>,
+    /// Deprecated: the member's new password (MD5-encrypted). Use `password` instead
     pub pass_hash: Option<String>,
     /// The member's phone number
     pub phone_number: String,
@@ -539,3 +784,21 @@ impl RegisterForSemesterForm {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::models::member::MemberFilter;
+
+    #[test]
+    fn searches_match_wildcards_literally() {
+        let filter = MemberFilter {
+            include_class: true,
+            include_club: true,
+            include_inactive: false,
+            section: None,
+            search: Some(r"50%_off\".to_owned()),
+        };
+
+        assert_eq!(filter.search_pattern().as_deref(), Some(r"%50\%\_off\\%"));
+    }
+}
@@ -0,0 +1,117 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use async_graphql::Result;
+use md5::{Digest, Md5};
+use rand::rngs::OsRng;
+
+use crate::error::ApiError;
+
+/// A password sent by a client, either in plaintext or as the
+/// MD5 hash that older clients send instead
+pub enum Password {
+    Plaintext(String),
+    LegacyMd5(String),
+}
+
+impl Password {
+    /// Picks out whichever of the plaintext or legacy MD5 password was provided
+    pub fn from_fields(password: Option<String>, pass_hash: Option<String>) -> Result<Self> {
+        match (password, pass_hash) {
+            (Some(password), None) => Ok(Self::Plaintext(password)),
+            (None, Some(pass_hash)) => Ok(Self::LegacyMd5(pass_hash)),
+            (Some(_), Some(_)) => Err(ApiError::validation(
+                "Provide either a password or a pass hash, not both",
+            )
+            .into()),
+            (None, None) => Err(ApiError::validation("A password is required").into()),
+        }
+    }
+
+    /// Like `from_fields`, but for updates where the password is optional
+    pub fn from_optional_fields(
+        password: Option<String>,
+        pass_hash: Option<String>,
+    ) -> Result<Option<Self>> {
+        if password.is_none() && pass_hash.is_none() {
+            Ok(None)
+        } else {
+            Self::from_fields(password, pass_hash).map(Some)
+        }
+    }
+
+    /// Hashes the password for storage, using argon2id for plaintext passwords.
+    /// Legacy MD5 passwords are still stored in the old bcrypt format until
+    /// their owner logs in with a plaintext password.
+    pub fn hash(&self) -> Result<String> {
+        match self {
+            Self::Plaintext(password) => {
+                let salt = SaltString::generate(&mut OsRng);
+                argon2()
+                    .hash_password(password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(|err| {
+                        ApiError::internal(format!("Failed to hash password: {}", err)).into()
+                    })
+            }
+            Self::LegacyMd5(pass_hash) => bcrypt::hash(pass_hash, 10).map_err(|err| {
+                ApiError::internal(format!("Failed to hash password: {}", err)).into()
+            }),
+        }
+    }
+
+    /// Checks the password against a stored hash in either format
+    pub fn verify(&self, stored_hash: &str) -> bool {
+        if is_legacy_hash(stored_hash) {
+            let md5 = match self {
+                Self::Plaintext(password) => format!("{:x}", Md5::digest(password)),
+                Self::LegacyMd5(pass_hash) => pass_hash.clone(),
+            };
+
+            bcrypt::verify(md5, stored_hash).unwrap_or(false)
+        } else if let Self::Plaintext(password) = self {
+            PasswordHash::new(stored_hash)
+                .map(|hash| argon2().verify_password(password.as_bytes(), &hash).is_ok())
+                .unwrap_or(false)
+        } else {
+            // argon2 hashes are of the plaintext, which we can't get back from MD5
+            false
+        }
+    }
+}
+
+/// Whether the stored hash is bcrypt over the client's MD5, instead of argon2id
+pub fn is_legacy_hash(stored_hash: &str) -> bool {
+    stored_hash.starts_with("$2")
+}
+
+/// Argon2id with OWASP's recommended minimum parameters
+fn argon2() -> Argon2<'static> {
+    let params = Params::new(19 * 1024, 2, 1, None).expect("argon2 parameters are valid");
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::member::password::{is_legacy_hash, Password};
+
+    #[test]
+    fn legacy_hashes_accept_plaintext_and_md5() {
+        // MD5 of "password"
+        let md5 = "5f4dcc3b5aa765d61d8327deb882cf99".to_owned();
+        let stored = Password::LegacyMd5(md5.clone()).hash().unwrap();
+
+        assert!(is_legacy_hash(&stored));
+        assert!(Password::LegacyMd5(md5).verify(&stored));
+        assert!(Password::Plaintext("password".to_owned()).verify(&stored));
+        assert!(!Password::Plaintext("wrong".to_owned()).verify(&stored));
+    }
+
+    #[test]
+    fn plaintext_passwords_are_hashed_with_argon2() {
+        let stored = Password::Plaintext("password".to_owned()).hash().unwrap();
+
+        assert!(stored.starts_with("$argon2id$"));
+        assert!(Password::Plaintext("password".to_owned()).verify(&stored));
+        assert!(!Password::Plaintext("wrong".to_owned()).verify(&stored));
+    }
+}
@@ -0,0 +1,44 @@
+use async_graphql::Context;
+
+use crate::graphql::guards::Permission;
+use crate::models::member::Member;
+
+/// Who can see one of a member's fields
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    /// Any logged in member
+    Members,
+    /// The member and anyone with `VIEW_USER_PRIVATE_DETAILS`
+    Officers,
+    /// Only the member
+    OnlySelf,
+}
+
+impl Visibility {
+    /// For fields that members can choose to share with everyone
+    pub fn shared_or_officers(shared: bool) -> Self {
+        if shared {
+            Self::Members
+        } else {
+            Self::Officers
+        }
+    }
+
+    /// Whether the current user can see a field with this visibility on the given member
+    pub fn allows(self, ctx: &Context<'_>, member: &str) -> bool {
+        let user = match ctx.data_opt::<Member>() {
+            Some(user) => user,
+            None => return false,
+        };
+
+        match self {
+            Self::Members => true,
+            Self::Officers => {
+                Permission::VIEW_USER_PRIVATE_DETAILS
+                    .decide_for_member(ctx, member)
+                    .allowed
+            }
+            Self::OnlySelf => user.email == member,
+        }
+    }
+}
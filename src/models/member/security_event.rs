@@ -0,0 +1,135 @@
+use async_graphql::{ComplexObject, Context, Enum, Result, SimpleObject};
+use sqlx::postgres::PgExecutor;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use crate::models::member::Member;
+use crate::models::DateTime;
+
+/// An entry in the security log for a member's account
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct SecurityEvent {
+    /// The ID of the event
+    pub id: i64,
+    /// What happened
+    pub kind: SecurityEventKind,
+    /// The IP address of the client that caused the event, if any
+    pub ip: Option<String>,
+    /// Any extra details about what happened
+    pub details: Option<String>,
+
+    #[graphql(skip)]
+    pub member: String,
+    #[graphql(skip)]
+    pub actor: Option<String>,
+    #[graphql(skip)]
+    pub time: OffsetDateTime,
+}
+
+#[ComplexObject]
+impl SecurityEvent {
+    /// When the event happened
+    pub async fn time(&self) -> DateTime {
+        DateTime::from(self.time)
+    }
+
+    /// The member whose account the event happened to
+    pub async fn member(&self, ctx: &Context<'_>) -> Result<Member> {
+        let pool: &PgPool = ctx.data_unchecked();
+        Member::with_email(&self.member, pool).await
+    }
+
+    /// The member that actually caused the event, if it wasn't the member themself
+    pub async fn actor(&self, ctx: &Context<'_>) -> Result<Option<Member>> {
+        if let Some(actor) = &self.actor {
+            let pool: &PgPool = ctx.data_unchecked();
+            Member::with_email(actor, pool).await.map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A kind of security-sensitive event
+#[derive(Clone, Copy, PartialEq, Eq, Enum, sqlx::Type)]
+#[sqlx(type_name = "security_event_kind", rename_all = "snake_case")]
+pub enum SecurityEventKind {
+    /// The member was sent a password reset email
+    PasswordResetRequested,
+    /// The member's password was reset with an emailed token
+    PasswordResetUsed,
+    /// Someone tried to use a password reset token after it expired
+    PasswordResetExpired,
+    /// The member changed their password from their profile
+    PasswordChanged,
+    /// A single sign-on account was linked to the member
+    SingleSignOnLinked,
+    /// An officer logged in as the member
+    ImpersonationStarted,
+    /// An officer stopped being logged in as the member
+    ImpersonationEnded,
+    /// An officer made changes while logged in as the member
+    ImpersonatedMutation,
+    /// The member turned on two-factor authentication
+    TwoFactorEnabled,
+    /// Two-factor authentication was turned off for the member
+    TwoFactorDisabled,
+    /// The member logged in with one of their two-factor recovery codes
+    RecoveryCodeUsed,
+    /// The member confirmed their email, or an officer confirmed it for them
+    EmailVerified,
+}
+
+impl SecurityEvent {
+    #[tracing::instrument(skip_all, fields(member = %member))]
+    pub async fn record(
+        kind: SecurityEventKind,
+        member: &str,
+        ip: Option<&str>,
+        details: Option<&str>,
+        db: impl PgExecutor<'_>,
+    ) -> Result<()> {
+        Self::record_by(kind, member, None, ip, details, db).await
+    }
+
+    /// Records an event that someone else caused on the member's account
+    #[tracing::instrument(skip_all, fields(member = %member))]
+    pub async fn record_by(
+        kind: SecurityEventKind,
+        member: &str,
+        actor: Option<&str>,
+        ip: Option<&str>,
+        details: Option<&str>,
+        db: impl PgExecutor<'_>,
+    ) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO security_events (kind, member, actor, ip, details)
+             VALUES ($1, $2, $3, $4, $5)",
+            kind as _,
+            member,
+            actor,
+            ip,
+            details
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Security events, most recent first, optionally for just one member
+    #[tracing::instrument(skip_all)]
+    pub async fn all(member: Option<&str>, pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            "SELECT id, kind as \"kind: _\", member, actor, time, ip, details
+             FROM security_events WHERE $1::text IS NULL OR member = $1
+             ORDER BY time DESC, id DESC",
+            member
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+}
@@ -1,51 +1,203 @@
-use async_graphql::Result;
+use async_graphql::{ComplexObject, Context, Result, SimpleObject};
 use sqlx::PgPool;
 use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
 
 use crate::email::reset_password::ResetPasswordEmail;
 use crate::email::send_email;
+use crate::error::ApiError;
+use crate::models::member::password::Password;
+use crate::models::member::security_event::{SecurityEvent, SecurityEventKind};
 use crate::models::member::Member;
-use crate::util::current_time;
+use crate::models::DateTime;
+use crate::util::{current_time, hash_token};
 
-/// A login session for a member
+/// A login session for a member on a single device
+#[derive(SimpleObject)]
+#[graphql(complex)]
 pub struct Session {
-    /// The email of the member
+    /// The ID of the session
+    pub id: i64,
+    /// The browser or app that logged in, if it identified itself
+    pub user_agent: Option<String>,
+
+    #[graphql(skip)]
     pub member: String,
-    /// A random session key
+    #[graphql(skip)]
     pub key: String,
+    #[graphql(skip)]
+    pub created_at: OffsetDateTime,
+    #[graphql(skip)]
+    pub last_seen: OffsetDateTime,
+    #[graphql(skip)]
+    pub impersonated_by: Option<String>,
+    #[graphql(skip)]
+    pub original_session: Option<i64>,
 }
 
+#[ComplexObject]
 impl Session {
+    /// When the member logged in
+    pub async fn created_at(&self) -> DateTime {
+        DateTime::from(self.created_at)
+    }
+
+    /// When the session was last used to make a request
+    pub async fn last_seen(&self) -> DateTime {
+        DateTime::from(self.last_seen)
+    }
+
+    /// When the session will expire if it isn't used again
+    pub async fn expires_at(&self) -> DateTime {
+        DateTime::from(self.expires_at_inner())
+    }
+
+    /// The officer logged in as the member, if this is an impersonation session
+    pub async fn impersonated_by(&self, ctx: &Context<'_>) -> Result<Option<Member>> {
+        if let Some(impersonator) = &self.impersonated_by {
+            let pool: &PgPool = ctx.data_unchecked();
+            Member::with_email(impersonator, pool).await.map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Whether this is the session making the current request
+    pub async fn current(&self, ctx: &Context<'_>) -> bool {
+        ctx.data_opt::<Session>()
+            .map(|session| session.id == self.id)
+            .unwrap_or(false)
+    }
+}
+
+impl Session {
+    /// How long a session can go unused before it expires
+    pub const IDLE_TIMEOUT: Duration = Duration::days(30);
+    /// How long a session can last in total, regardless of use
+    pub const MAX_AGE: Duration = Duration::days(180);
+    /// How long an officer can stay logged in as another member
+    pub const IMPERSONATION_MAX_AGE: Duration = Duration::hours(1);
+
+    pub fn expires_at_inner(&self) -> OffsetDateTime {
+        let max_age = if self.impersonated_by.is_some() {
+            Self::IMPERSONATION_MAX_AGE
+        } else {
+            Self::MAX_AGE
+        };
+
+        (self.last_seen + Self::IDLE_TIMEOUT).min(self.created_at + max_age)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at_inner() <= current_time()
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn with_token(token: &str, pool: &PgPool) -> Result<Self> {
-        Self::with_token_opt(token, pool)
-            .await?
-            .ok_or("No login tied to the provided API token")
-            .map_err(Into::into)
+        let session = Self::with_token_opt(token, pool).await?.ok_or_else(|| {
+            ApiError::Unauthenticated("No login tied to the provided API token".to_owned())
+        })?;
+
+        if session.is_expired() {
+            Self::remove(session.id, pool).await?;
+            return Err(ApiError::Unauthenticated(
+                "Your login has expired, please log in again".to_owned(),
+            )
+            .into());
+        }
+
+        sqlx::query!(
+            "UPDATE sessions SET last_seen = now() WHERE id = $1",
+            session.id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(session)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn with_token_opt(token: &str, pool: &PgPool) -> Result<Option<Self>> {
-        sqlx::query_as!(Self, "SELECT * FROM sessions WHERE key = $1", token)
-            .fetch_optional(pool)
-            .await
-            .map_err(Into::into)
+        sqlx::query_as!(
+            Self,
+            "SELECT id, member, key, created_at as \"created_at: _\",
+                 last_seen as \"last_seen: _\", user_agent, impersonated_by, original_session
+             FROM sessions WHERE key = $1",
+            token
+        )
+        .fetch_optional(pool)
+        .await
+        .map_err(Into::into)
     }
 
-    pub async fn get_or_generate_token(email: &str, pool: &PgPool) -> Result<String> {
+    /// All unexpired sessions for the given member, most recently used first
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn for_member(email: &str, pool: &PgPool) -> Result<Vec<Self>> {
+        let now = current_time();
+
+        let mut sessions = sqlx::query_as!(
+            Self,
+            "SELECT id, member, key, created_at as \"created_at: _\",
+                 last_seen as \"last_seen: _\", user_agent, impersonated_by, original_session
+             FROM sessions
+             WHERE member = $1 AND last_seen > $2 AND created_at > $3
+             ORDER BY last_seen DESC",
+            email,
+            now - Self::IDLE_TIMEOUT,
+            now - Self::MAX_AGE
+        )
+        .fetch_all(pool)
+        .await?;
+        sessions.retain(|session| !session.is_expired());
+
+        Ok(sessions)
+    }
+
+    /// Starts a new session for the member and returns its token
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn generate(email: &str, user_agent: Option<&str>, pool: &PgPool) -> Result<String> {
         Member::with_email(email, pool).await?; // ensure that member exists
+        Self::remove_expired_for_member(email, pool).await?;
 
-        let session = sqlx::query_scalar!("SELECT key FROM sessions WHERE member = $1", email)
-            .fetch_optional(pool)
-            .await?;
-        if let Some(session_key) = session {
-            return Ok(session_key);
+        let token = Uuid::new_v4().to_string();
+        sqlx::query!(
+            "INSERT INTO sessions (member, key, user_agent) VALUES ($1, $2, $3)",
+            email,
+            token,
+            user_agent
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// Starts a time-limited session for an officer to act as another member,
+    /// returning its token
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn impersonate(
+        email: &str,
+        original_session: &Session,
+        user_agent: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<String> {
+        if original_session.impersonated_by.is_some() {
+            return Err(ApiError::conflict("Already logged in as another member").into());
+        }
+        if original_session.member == email {
+            return Err(ApiError::validation("Cannot log in as yourself").into());
         }
+        Member::with_email(email, pool).await?; // ensure that member exists
 
         let token = Uuid::new_v4().to_string();
         sqlx::query!(
-            "INSERT INTO sessions (member, key) VALUES ($1, $2)",
+            "INSERT INTO sessions (member, key, user_agent, impersonated_by, original_session)
+             VALUES ($1, $2, $3, $4, $5)",
             email,
-            token
+            token,
+            user_agent,
+            original_session.member,
+            original_session.id
         )
         .execute(pool)
         .await?;
@@ -53,27 +205,96 @@ impl Session {
         Ok(token)
     }
 
-    pub async fn remove(email: &str, pool: &PgPool) -> Result<()> {
+    /// Ends an impersonation session, returning the token for the officer's own session
+    #[tracing::instrument(skip_all)]
+    pub async fn end_impersonation(&self, pool: &PgPool) -> Result<String> {
+        let original_session = self
+            .original_session
+            .ok_or_else(|| ApiError::conflict("Not currently logged in as another member"))?;
+        Self::remove(self.id, pool).await?;
+
+        let key = sqlx::query_scalar!("SELECT key FROM sessions WHERE id = $1", original_session)
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| {
+                ApiError::Unauthenticated(
+                    "Your original login has expired, please log in again".to_owned(),
+                )
+            })?;
+
+        Self::with_token(&key, pool)
+            .await
+            .map(|session| session.key)
+    }
+
+    /// Revokes one of the member's sessions
+    #[tracing::instrument(skip_all, fields(id = id, email = %email))]
+    pub async fn revoke(id: i64, email: &str, pool: &PgPool) -> Result<()> {
+        let result = sqlx::query!(
+            "DELETE FROM sessions WHERE id = $1 AND member = $2",
+            id,
+            email
+        )
+        .execute(pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            Err(ApiError::not_found("session", "ID", id).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn remove(id: i64, pool: &PgPool) -> Result<()> {
+        sqlx::query!("DELETE FROM sessions WHERE id = $1", id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Logs the member out everywhere
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn remove_all_for_member(email: &str, pool: &PgPool) -> Result<()> {
         sqlx::query!("DELETE FROM sessions WHERE member = $1", email)
             .execute(pool)
             .await?;
 
         Ok(())
     }
+
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    async fn remove_expired_for_member(email: &str, pool: &PgPool) -> Result<()> {
+        let now = current_time();
+
+        sqlx::query!(
+            "DELETE FROM sessions WHERE member = $1 AND (last_seen <= $2 OR created_at <= $3)",
+            email,
+            now - Self::IDLE_TIMEOUT,
+            now - Self::MAX_AGE
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
 }
 
-/// A 24-hour password reset for a member
+/// A single-use password reset for a member
 pub struct PasswordReset {
     /// The email of the member
     pub member: String,
     /// When the password reset was requested
     pub time: OffsetDateTime,
-    /// The random token emailed to the member
-    pub token: String,
 }
 
 impl PasswordReset {
-    pub async fn generate(email: &str, pool: &PgPool) -> Result<()> {
+    /// How long a reset token can be used after it is sent
+    pub const EXPIRATION: Duration = Duration::DAY;
+
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn generate(email: &str, ip: &str, pool: &PgPool) -> Result<()> {
         let member = Member::with_email(email, pool).await?;
 
         let new_token = Uuid::new_v4().to_string();
@@ -81,12 +302,20 @@ impl PasswordReset {
             .execute(pool)
             .await?;
         sqlx::query!(
-            "INSERT INTO password_resets (member, token) VALUES ($1, $2)",
+            "INSERT INTO password_resets (member, token_hash) VALUES ($1, $2)",
             email,
-            new_token
+            hash_token(&new_token)
         )
         .execute(pool)
         .await?;
+        SecurityEvent::record(
+            SecurityEventKind::PasswordResetRequested,
+            email,
+            Some(ip),
+            None,
+            pool,
+        )
+        .await?;
 
         let email = ResetPasswordEmail {
             member: &member,
@@ -97,36 +326,63 @@ impl PasswordReset {
         Ok(())
     }
 
-    pub async fn reset_from_token(token: &str, pass_hash: &str, pool: &PgPool) -> Result<()> {
-        let session = sqlx::query_as!(
+    /// Sets a new password using an emailed token, which can only be used once.
+    /// This also logs the member out everywhere.
+    #[tracing::instrument(skip_all)]
+    pub async fn reset_from_token(
+        token: &str,
+        password: &Password,
+        ip: &str,
+        pool: &PgPool,
+    ) -> Result<()> {
+        let reset = sqlx::query_as!(
             PasswordReset,
-            "SELECT * FROM password_resets WHERE token = $1",
-            token
+            "DELETE FROM password_resets WHERE token_hash = $1 RETURNING member, time",
+            hash_token(token)
         )
         .fetch_optional(pool)
         .await?
         .ok_or_else(|| {
-            "No password reset request was found for the given token, please request another"
+            ApiError::Unauthenticated(
+                "No password reset request was found for the given token, please request another"
+                    .to_owned(),
+            )
         })?;
 
-        if session.time > current_time() + Duration::DAY {
-            return Err("Your token expired after 24 hours, please request another reset".into());
+        if reset.time + Self::EXPIRATION < current_time() {
+            SecurityEvent::record(
+                SecurityEventKind::PasswordResetExpired,
+                &reset.member,
+                Some(ip),
+                None,
+                pool,
+            )
+            .await?;
+            return Err(ApiError::Unauthenticated(
+                "Your token expired after 24 hours, please request another reset".to_owned(),
+            )
+            .into());
         }
 
-        Self::remove(&session.member, pool).await?;
-        let hash = bcrypt::hash(pass_hash, 10)
-            .map_err(|err| format!("Failed to hash password: {}", err))?;
         sqlx::query!(
             "UPDATE members SET pass_hash = $1 WHERE email = $2",
-            hash,
-            session.member
+            password.hash()?,
+            reset.member
         )
         .execute(pool)
         .await?;
-
-        Ok(())
+        Session::remove_all_for_member(&reset.member, pool).await?;
+        SecurityEvent::record(
+            SecurityEventKind::PasswordResetUsed,
+            &reset.member,
+            Some(ip),
+            None,
+            pool,
+        )
+        .await
     }
 
+    #[tracing::instrument(skip_all, fields(email = %email))]
     pub async fn remove(email: &str, pool: &PgPool) -> Result<()> {
         sqlx::query!("DELETE FROM password_resets WHERE member = $1", email)
             .execute(pool)
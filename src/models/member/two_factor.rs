@@ -0,0 +1,384 @@
+use async_graphql::{Result, SimpleObject};
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::models::member::security_event::{SecurityEvent, SecurityEventKind};
+use crate::util::{current_time, hash_token};
+
+/// How long each code from an authenticator app is valid for, in seconds
+const TIME_STEP: i64 = 30;
+/// How many steps of clock drift to allow in either direction
+const ALLOWED_DRIFT: i64 = 1;
+const DIGITS: u32 = 6;
+const SECRET_BYTES: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+const BASE32: Alphabet = Alphabet::RFC4648 { padding: false };
+/// How long a member has to enter their code after signing in with single sign-on
+const CHALLENGE_LIFETIME: Duration = Duration::minutes(5);
+
+/// What a member needs to add GlubHub to their authenticator app
+#[derive(SimpleObject)]
+pub struct TwoFactorEnrollment {
+    /// The shared secret, for typing into an authenticator app
+    pub secret: String,
+    /// An `otpauth://` URL with the secret, for showing as a QR code
+    pub otpauth_url: String,
+}
+
+/// Time-based one-time passwords (RFC 6238) as a second login step
+pub struct TwoFactor;
+
+impl TwoFactor {
+    /// Whether the member has finished setting up two-factor authentication
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn is_enabled(email: &str, pool: &PgPool) -> Result<bool> {
+        let confirmed = sqlx::query_scalar!(
+            "SELECT confirmed FROM totp_credentials WHERE member = $1",
+            email
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(confirmed.unwrap_or(false))
+    }
+
+    /// Whether any of the member's roles or grants give a permission that
+    /// requires two-factor authentication
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn is_required(email: &str, pool: &PgPool) -> Result<bool> {
+        sqlx::query_scalar!(
+            "SELECT EXISTS(
+                 SELECT 1 FROM role_permissions
+                 INNER JOIN member_roles ON role_permissions.role = member_roles.role
+                 INNER JOIN permissions ON role_permissions.permission = permissions.name
+                 WHERE member_roles.member = $1 AND member_roles.ended_at IS NULL
+                     AND permissions.requires_two_factor
+             ) OR EXISTS(
+                 SELECT 1 FROM member_permissions
+                 INNER JOIN permissions ON member_permissions.permission = permissions.name
+                 WHERE member_permissions.member = $1 AND member_permissions.expires_at > now()
+                     AND permissions.requires_two_factor
+             ) as \"required!\"",
+            email
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Generates a new secret for the member, which won't be used
+    /// until it is confirmed with a code
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn begin_enrollment(email: &str, pool: &PgPool) -> Result<TwoFactorEnrollment> {
+        if Self::is_enabled(email, pool).await? {
+            return Err(ApiError::conflict("Two-factor authentication is already enabled").into());
+        }
+
+        let secret_bytes: [u8; SECRET_BYTES] = rand::thread_rng().gen();
+        let secret = base32::encode(BASE32, &secret_bytes);
+        sqlx::query!(
+            "INSERT INTO totp_credentials (member, secret) VALUES ($1, $2)
+             ON CONFLICT (member) DO UPDATE SET secret = $2, created_at = now()",
+            email,
+            secret
+        )
+        .execute(pool)
+        .await?;
+
+        let otpauth_url = format!(
+            "otpauth://totp/GlubHub:{}?secret={}&issuer=GlubHub&digits={}&period={}",
+            email, secret, DIGITS, TIME_STEP
+        );
+
+        Ok(TwoFactorEnrollment {
+            secret,
+            otpauth_url,
+        })
+    }
+
+    /// Turns on two-factor authentication once the member proves their app
+    /// works, returning their recovery codes
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn confirm_enrollment(email: &str, code: &str, pool: &PgPool) -> Result<Vec<String>> {
+        if Self::is_enabled(email, pool).await? {
+            return Err(ApiError::conflict("Two-factor authentication is already enabled").into());
+        }
+        if !Self::check_app_code(email, code, pool).await? {
+            return Err(ApiError::validation(
+                "Incorrect code, please check your authenticator app's clock",
+            )
+            .into());
+        }
+
+        sqlx::query!(
+            "UPDATE totp_credentials SET confirmed = true WHERE member = $1",
+            email
+        )
+        .execute(pool)
+        .await?;
+
+        Self::generate_recovery_codes(email, pool).await
+    }
+
+    /// Checks a code from the member's authenticator app or one of their
+    /// recovery codes, which can then not be used again
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn verify(email: &str, code: &str, pool: &PgPool) -> Result<bool> {
+        if !Self::is_enabled(email, pool).await? {
+            return Ok(false);
+        }
+
+        if Self::check_app_code(email, code, pool).await? {
+            Ok(true)
+        } else if Self::use_recovery_code(email, code, pool).await? {
+            SecurityEvent::record(SecurityEventKind::RecoveryCodeUsed, email, None, None, pool)
+                .await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn disable(email: &str, pool: &PgPool) -> Result<()> {
+        sqlx::query!("DELETE FROM totp_credentials WHERE member = $1", email)
+            .execute(pool)
+            .await?;
+        sqlx::query!("DELETE FROM totp_recovery_codes WHERE member = $1", email)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Holds off on a login that still needs a two-factor code, returning
+    /// a token to send back along with the code
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    pub async fn create_challenge(email: &str, pool: &PgPool) -> Result<String> {
+        sqlx::query!(
+            "DELETE FROM two_factor_challenges WHERE created_at < $1",
+            current_time() - CHALLENGE_LIFETIME
+        )
+        .execute(pool)
+        .await?;
+
+        let token = Uuid::new_v4().to_string();
+        sqlx::query!(
+            "INSERT INTO two_factor_challenges (token_hash, member) VALUES ($1, $2)",
+            hash_token(&token),
+            email
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// The member logging in with the given challenge token
+    #[tracing::instrument(skip_all)]
+    pub async fn challenged_member(token: &str, pool: &PgPool) -> Result<String> {
+        sqlx::query_scalar!(
+            "SELECT member FROM two_factor_challenges WHERE token_hash = $1 AND created_at > $2",
+            hash_token(token),
+            current_time() - CHALLENGE_LIFETIME
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| {
+            ApiError::Unauthenticated(
+                "Your login attempt timed out, please sign in again".to_owned(),
+            )
+            .into()
+        })
+    }
+
+    /// Uses up a challenge once its code has been checked
+    #[tracing::instrument(skip_all)]
+    pub async fn remove_challenge(token: &str, pool: &PgPool) -> Result<()> {
+        sqlx::query!(
+            "DELETE FROM two_factor_challenges WHERE token_hash = $1",
+            hash_token(token)
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    async fn check_app_code(email: &str, code: &str, pool: &PgPool) -> Result<bool> {
+        let secret = sqlx::query_scalar!(
+            "SELECT secret FROM totp_credentials WHERE member = $1",
+            email
+        )
+        .fetch_optional(pool)
+        .await?;
+        let secret = if let Some(secret) = secret {
+            secret
+        } else {
+            return Ok(false);
+        };
+
+        let secret = base32::decode(BASE32, &secret)
+            .ok_or_else(|| ApiError::internal("Two-factor authentication secret is corrupted"))?;
+        let step = match matching_step(&secret, code.trim(), current_time()) {
+            Some(step) => step,
+            None => return Ok(false),
+        };
+
+        // Each code can only be used once, so a stolen code can't be replayed.
+        // Checking and claiming the step in one statement keeps two requests
+        // with the same code from both getting in
+        let result = sqlx::query!(
+            "UPDATE totp_credentials SET last_used_step = $1
+             WHERE member = $2 AND (last_used_step IS NULL OR last_used_step < $1)",
+            step,
+            email
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    async fn use_recovery_code(email: &str, code: &str, pool: &PgPool) -> Result<bool> {
+        let code = code.trim().to_lowercase();
+        let result = sqlx::query!(
+            "UPDATE totp_recovery_codes SET used_at = now()
+             WHERE member = $1 AND code_hash = $2 AND used_at IS NULL",
+            email,
+            hash_token(&code)
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(skip_all, fields(email = %email))]
+    async fn generate_recovery_codes(email: &str, pool: &PgPool) -> Result<Vec<String>> {
+        sqlx::query!("DELETE FROM totp_recovery_codes WHERE member = $1", email)
+            .execute(pool)
+            .await?;
+
+        let mut codes = Vec::new();
+        for _ in 0..RECOVERY_CODE_COUNT {
+            let bytes: [u8; 5] = rand::thread_rng().gen();
+            let code = base32::encode(BASE32, &bytes).to_lowercase();
+            let code = format!("{}-{}", &code[..4], &code[4..]);
+
+            sqlx::query!(
+                "INSERT INTO totp_recovery_codes (member, code_hash) VALUES ($1, $2)",
+                email,
+                hash_token(&code)
+            )
+            .execute(pool)
+            .await?;
+            codes.push(code);
+        }
+
+        Ok(codes)
+    }
+}
+
+/// The time step a code was generated for, if it is valid around the given time
+fn matching_step(secret: &[u8], code: &str, time: OffsetDateTime) -> Option<i64> {
+    let current_step = time.unix_timestamp() / TIME_STEP;
+
+    (current_step - ALLOWED_DRIFT..=current_step + ALLOWED_DRIFT).find(|step| {
+        format!(
+            "{:0width$}",
+            hotp(secret, *step as u64),
+            width = DIGITS as usize
+        ) == code
+    })
+}
+
+/// An HMAC-based one-time password (RFC 4226)
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join_all;
+    use time::OffsetDateTime;
+
+    use crate::models::member::two_factor::{hotp, matching_step, TwoFactor, BASE32, TIME_STEP};
+    use crate::util::{current_time, with_cleanup};
+
+    #[test]
+    fn matches_rfc_6238_test_vectors() {
+        let secret = b"12345678901234567890";
+        let at = |timestamp| OffsetDateTime::from_unix_timestamp(timestamp).unwrap();
+
+        assert_eq!(matching_step(secret, "287082", at(59)), Some(1));
+        assert_eq!(
+            matching_step(secret, "081804", at(1111111109)),
+            Some(37037036)
+        );
+        assert_eq!(
+            matching_step(secret, "050471", at(1111111111)),
+            Some(37037037)
+        );
+        assert_eq!(matching_step(secret, "000000", at(59)), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn codes_only_work_once_even_when_sent_together() {
+        let cleanup = ["DELETE FROM members WHERE email = 'replay@two-factor.test'"];
+        with_cleanup(&cleanup, |pool| async move {
+            let email = "replay@two-factor.test";
+
+            let secret = b"12345678901234567890";
+            sqlx::query(
+                "INSERT INTO members (email, first_name, last_name, pass_hash, phone_number, location)
+                 VALUES ($1, 'Tobi', 'Totp', '', '', '')",
+            )
+            .bind(email)
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO totp_credentials (member, secret, confirmed) VALUES ($1, $2, true)",
+            )
+            .bind(email)
+            .bind(base32::encode(BASE32, secret))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+            let step = current_time().unix_timestamp() / TIME_STEP;
+            let code = format!("{:06}", hotp(secret, step as u64));
+            let results = join_all((0..4).map(|_| TwoFactor::verify(email, &code, &pool))).await;
+
+            let accepted = results
+                .into_iter()
+                .map(Result::unwrap)
+                .filter(|accepted| *accepted)
+                .count();
+            assert_eq!(accepted, 1);
+        })
+        .await;
+    }
+}
@@ -1,8 +1,9 @@
 use async_graphql::{ComplexObject, Context, InputObject, Result, SimpleObject};
 use sqlx::PgPool;
 
+use crate::error::ApiError;
 use crate::graphql::guards::Permission;
-use crate::models::member::Member;
+use crate::graphql::pagination::PageWindow;
 use crate::models::DateScalar;
 
 /// Meeting minutes from officer meetings
@@ -17,6 +18,8 @@ pub struct Minutes {
     pub date: DateScalar,
     /// The public, redacted notes visible by all members
     pub public: Option<String>,
+    /// Goes up with every edit, so updates based on an older version can be rejected
+    pub version: i64,
 
     #[graphql(skip)]
     pub private: Option<String>,
@@ -26,31 +29,27 @@ pub struct Minutes {
 impl Minutes {
     /// The private, complete officer notes
     pub async fn private(&self, ctx: &Context<'_>) -> Result<Option<&String>> {
-        if let Some(user) = ctx.data_opt::<Member>() {
-            let pool: &PgPool = ctx.data_unchecked();
-            if Permission::VIEW_COMPLETE_MINUTES
-                .granted_to(&user.email, pool)
-                .await?
-            {
-                return Ok(self.private.as_ref());
-            }
+        if Permission::VIEW_COMPLETE_MINUTES.granted(ctx) {
+            Ok(self.private.as_ref())
+        } else {
+            Ok(None)
         }
-
-        Ok(None)
     }
 }
 
 impl Minutes {
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id(id: i64, pool: &PgPool) -> Result<Self> {
         Self::with_id_opt(id, pool)
             .await?
-            .ok_or_else(|| format!("No meeting minutes with id {}", id).into())
+            .ok_or_else(|| ApiError::not_found("meeting minutes", "ID", id).into())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id_opt(id: i64, pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT id, name, date as \"date: _\", public, private
+            "SELECT id, name, date as \"date: _\", public, private, version
              FROM minutes WHERE id = $1",
             id
         )
@@ -59,42 +58,60 @@ impl Minutes {
         .map_err(Into::into)
     }
 
-    pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
+    #[tracing::instrument(skip_all)]
+    pub async fn count(pool: &PgPool) -> Result<i64> {
+        sqlx::query_scalar!("SELECT COUNT(*) as \"count!\" FROM minutes")
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// A page of meeting minutes, newest first
+    #[tracing::instrument(skip_all)]
+    pub async fn page(window: PageWindow, pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT id, name, date as \"date: _\", public, private
-             FROM minutes ORDER BY date DESC"
+            "SELECT id, name, date as \"date: _\", public, private, version
+             FROM minutes ORDER BY date DESC, id DESC LIMIT $1 OFFSET $2",
+            window.limit,
+            window.offset
         )
         .fetch_all(pool)
         .await
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn create(name: &str, pool: &PgPool) -> Result<i64> {
-        sqlx::query!("INSERT INTO minutes (name) VALUES ($1)", name)
-            .execute(pool)
-            .await?;
-
-        sqlx::query_scalar!("SELECT id FROM minutes ORDER BY id DESC")
+        sqlx::query_scalar!("INSERT INTO minutes (name) VALUES ($1) RETURNING id", name)
             .fetch_one(pool)
             .await
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn update(id: i64, update: UpdatedMeetingMinutes, pool: &PgPool) -> Result<()> {
-        sqlx::query!(
-            "UPDATE minutes SET name = $1, private = $2, public = $3 WHERE id = $4",
+        let updated = sqlx::query!(
+            "UPDATE minutes SET name = $1, private = $2, public = $3, version = version + 1
+             WHERE id = $4 AND ($5::bigint IS NULL OR version = $5)",
             update.name,
             update.private,
             update.public,
-            id
+            id,
+            update.version
         )
         .execute(pool)
         .await?;
 
+        if updated.rows_affected() == 0 {
+            let current = Minutes::with_id(id, pool).await?;
+            return Err(ApiError::stale_version("meeting minutes", id, current.version).into());
+        }
+
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn delete(id: i64, pool: &PgPool) -> Result<()> {
         sqlx::query!("DELETE FROM minutes WHERE id = $1", id)
             .execute(pool)
@@ -113,4 +130,6 @@ pub struct UpdatedMeetingMinutes {
     pub public: String,
     /// The full version of the meeting minutes
     pub private: Option<String>,
+    /// The version being edited, if the edit should be rejected when it's out of date
+    pub version: Option<i64>,
 }
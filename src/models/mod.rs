@@ -9,6 +9,7 @@ use time::{Date, OffsetDateTime, Time};
 
 use crate::util::local_offset;
 
+pub mod api_key;
 pub mod event;
 pub mod grades;
 pub mod link;
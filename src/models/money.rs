@@ -1,8 +1,12 @@
 use async_graphql::{ComplexObject, Context, InputObject, Result, SimpleObject};
-use sqlx::PgPool;
+use sqlx::postgres::{PgConnection, PgExecutor};
+use sqlx::{Connection, PgPool};
 use time::OffsetDateTime;
 
 use super::DateTime;
+use crate::error::ApiError;
+use crate::graphql::loader::load_member;
+use crate::graphql::pagination::PageWindow;
 use crate::models::member::Member;
 use crate::models::semester::Semester;
 
@@ -25,20 +29,23 @@ impl Fee {
     pub const DUES_DESCRIPTION: &'static str = "Semesterly Dues";
     pub const LATE_DUES_DESCRIPTION: &'static str = "Late Dues";
 
-    pub async fn with_name(name: &str, pool: &PgPool) -> Result<Self> {
-        Self::with_name_opt(name, pool)
+    #[tracing::instrument(skip_all, fields(name = %name))]
+    pub async fn with_name(name: &str, db: impl PgExecutor<'_>) -> Result<Self> {
+        Self::with_name_opt(name, db)
             .await?
-            .ok_or_else(|| format!("No fee named {}", name))
+            .ok_or_else(|| ApiError::not_found("fee", "name", name))
             .map_err(Into::into)
     }
 
-    pub async fn with_name_opt(name: &str, pool: &PgPool) -> Result<Option<Self>> {
+    #[tracing::instrument(skip_all, fields(name = %name))]
+    pub async fn with_name_opt(name: &str, db: impl PgExecutor<'_>) -> Result<Option<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM fees WHERE name = $1", name)
-            .fetch_optional(pool)
+            .fetch_optional(db)
             .await
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM fees ORDER BY NAME")
             .fetch_all(pool)
@@ -46,6 +53,7 @@ impl Fee {
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn set_amount(name: &str, new_amount: i64, pool: &PgPool) -> Result<()> {
         sqlx::query!(
             "UPDATE fees SET amount = $1 WHERE name = $2",
@@ -58,9 +66,11 @@ impl Fee {
         Ok(())
     }
 
-    pub async fn charge_dues_for_semester(pool: &PgPool) -> Result<()> {
-        let dues = Self::with_name(Self::DUES, pool).await?;
-        let current_semester = Semester::get_current(pool).await?;
+    #[tracing::instrument(skip_all)]
+    pub async fn charge_dues_for_semester(conn: &mut PgConnection) -> Result<()> {
+        let mut transaction = conn.begin().await?;
+        let dues = Self::with_name(Self::DUES, &mut *transaction).await?;
+        let current_semester = Semester::get_current(&mut *transaction).await?;
 
         let members_who_havent_paid = sqlx::query_scalar!(
             "SELECT member FROM active_semesters WHERE semester = $1 AND member NOT IN \
@@ -69,7 +79,7 @@ impl Fee {
             Self::DUES_NAME,
             Self::DUES_DESCRIPTION
         )
-        .fetch_all(pool)
+        .fetch_all(&mut *transaction)
         .await?;
 
         for email in members_who_havent_paid {
@@ -82,16 +92,20 @@ impl Fee {
                 Self::DUES_DESCRIPTION,
                 current_semester.name,
             )
-            .execute(pool)
+            .execute(&mut *transaction)
             .await?;
         }
 
+        transaction.commit().await?;
+
         Ok(())
     }
 
-    pub async fn charge_late_dues_for_semester(pool: &PgPool) -> Result<()> {
-        let late_dues = Self::with_name(Self::LATE_DUES, pool).await?;
-        let current_semester = Semester::get_current(pool).await?;
+    #[tracing::instrument(skip_all)]
+    pub async fn charge_late_dues_for_semester(conn: &mut PgConnection) -> Result<()> {
+        let mut transaction = conn.begin().await?;
+        let late_dues = Self::with_name(Self::LATE_DUES, &mut *transaction).await?;
+        let current_semester = Semester::get_current(&mut *transaction).await?;
 
         let members_who_havent_paid = sqlx::query_scalar!(
             "SELECT member FROM active_semesters WHERE semester = $1 AND member NOT IN \
@@ -100,7 +114,7 @@ impl Fee {
             Self::DUES_NAME,
             Self::DUES_DESCRIPTION
         )
-        .fetch_all(pool)
+        .fetch_all(&mut *transaction)
         .await?;
 
         for email in members_who_havent_paid {
@@ -113,10 +127,12 @@ impl Fee {
                 Self::LATE_DUES_DESCRIPTION,
                 current_semester.name,
             )
-            .execute(pool)
+            .execute(&mut *transaction)
             .await?;
         }
 
+        transaction.commit().await?;
+
         Ok(())
     }
 }
@@ -129,6 +145,7 @@ pub struct TransactionType {
 }
 
 impl TransactionType {
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM transaction_types ORDER BY name")
             .fetch_all(pool)
@@ -136,20 +153,22 @@ impl TransactionType {
             .map_err(Into::into)
     }
 
-    pub async fn with_name(name: &str, pool: &PgPool) -> Result<Self> {
-        Self::with_name_opt(name, pool)
+    #[tracing::instrument(skip_all, fields(name = %name))]
+    pub async fn with_name(name: &str, db: impl PgExecutor<'_>) -> Result<Self> {
+        Self::with_name_opt(name, db)
             .await?
-            .ok_or_else(|| format!("No transaction type named {}", name))
+            .ok_or_else(|| ApiError::not_found("transaction type", "name", name))
             .map_err(Into::into)
     }
 
-    pub async fn with_name_opt(name: &str, pool: &PgPool) -> Result<Option<Self>> {
+    #[tracing::instrument(skip_all, fields(name = %name))]
+    pub async fn with_name_opt(name: &str, db: impl PgExecutor<'_>) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
             "SELECT * FROM transaction_types WHERE name = $1",
             name
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await
         .map_err(Into::into)
     }
@@ -182,8 +201,7 @@ pub struct ClubTransaction {
 impl ClubTransaction {
     /// The member this transaction was charged to
     async fn member(&self, ctx: &Context<'_>) -> Result<Member> {
-        let pool: &PgPool = ctx.data_unchecked();
-        Member::with_email(&self.member, pool).await
+        load_member(ctx, &self.member).await
     }
 
     /// When this transaction was charged
@@ -193,12 +211,14 @@ impl ClubTransaction {
 }
 
 impl ClubTransaction {
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id(id: i64, pool: &PgPool) -> Result<Self> {
         Self::with_id_opt(id, pool)
             .await?
-            .ok_or_else(|| format!("No transaction with ID {}", id).into())
+            .ok_or_else(|| ApiError::not_found("transaction", "ID", id).into())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id_opt(id: i64, pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
@@ -211,18 +231,64 @@ impl ClubTransaction {
         .map_err(Into::into)
     }
 
-    pub async fn for_semester(semester: &str, pool: &PgPool) -> Result<Vec<Self>> {
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn for_semester(semester: &str, db: impl PgExecutor<'_>) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
             "SELECT id, member, \"time\" as \"time: _\", amount, description, semester, type, resolved
              FROM transactions WHERE semester = $1 ORDER BY time",
             semester
         )
+        .fetch_all(db)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// How many transactions in the semester match the filter
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn count(filter: &TransactionFilter, semester: &str, pool: &PgPool) -> Result<i64> {
+        sqlx::query_scalar!(
+            "SELECT COUNT(*) as \"count!\" FROM transactions
+             WHERE semester = $1 AND ($2::boolean IS NULL OR resolved = $2)
+                 AND ($3::text IS NULL OR type = $3) AND ($4::text IS NULL OR member = $4)",
+            semester,
+            filter.resolved,
+            filter.r#type,
+            filter.member
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// A page of the transactions in the semester that match the filter, oldest first
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn page(
+        filter: &TransactionFilter,
+        semester: &str,
+        window: PageWindow,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            "SELECT id, member, \"time\" as \"time: _\", amount, description, semester, type, resolved
+             FROM transactions
+             WHERE semester = $1 AND ($2::boolean IS NULL OR resolved = $2)
+                 AND ($3::text IS NULL OR type = $3) AND ($4::text IS NULL OR member = $4)
+             ORDER BY time, id LIMIT $5 OFFSET $6",
+            semester,
+            filter.resolved,
+            filter.r#type,
+            filter.member,
+            window.limit,
+            window.offset
+        )
         .fetch_all(pool)
         .await
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(member = %member))]
     pub async fn for_member(member: &str, pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
@@ -235,20 +301,25 @@ impl ClubTransaction {
         .map_err(Into::into)
     }
 
-    pub async fn add_batch(batch: TransactionBatch, pool: &PgPool) -> Result<()> {
-        let current_semester = Semester::get_current(pool).await?;
-        let transaction_type = TransactionType::with_name(&batch.r#type, pool).await?;
+    #[tracing::instrument(skip_all)]
+    pub async fn add_batch(batch: TransactionBatch, conn: &mut PgConnection) -> Result<()> {
+        let mut transaction = conn.begin().await?;
+        let current_semester = Semester::get_current(&mut *transaction).await?;
+        let transaction_type = TransactionType::with_name(&batch.r#type, &mut *transaction).await?;
 
         for member in batch.members {
             sqlx::query!(
                 "INSERT INTO transactions (member, amount, type, description, semester) VALUES ($1, $2, $3, $4, $5)",
                 member, batch.amount, transaction_type.name, batch.description, current_semester.name)
-                .execute(pool).await?;
+                .execute(&mut *transaction).await?;
         }
 
+        transaction.commit().await?;
+
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn resolve(id: i64, resolved: bool, pool: &PgPool) -> Result<()> {
         sqlx::query!(
             "UPDATE transactions SET resolved = $1 WHERE id = $2",
@@ -262,6 +333,17 @@ impl ClubTransaction {
     }
 }
 
+/// Which transactions to load for a semester
+#[derive(InputObject, Default)]
+pub struct TransactionFilter {
+    /// Only load transactions that have or haven't been paid
+    pub resolved: Option<bool>,
+    /// Only load transactions of this type
+    pub r#type: Option<String>,
+    /// Only load transactions charged to this member
+    pub member: Option<String>,
+}
+
 /// A batch of transactions to charge to multiple members
 #[derive(InputObject)]
 pub struct TransactionBatch {
@@ -1,7 +1,16 @@
 use async_graphql::{ComplexObject, Context, Enum, InputObject, Result, SimpleObject};
-use sqlx::PgPool;
+use sqlx::postgres::{PgConnection, PgExecutor};
+use sqlx::{Connection, PgPool};
+use time::OffsetDateTime;
 
+use crate::error::ApiError;
+use crate::graphql::guards;
+use crate::graphql::loader::load_member;
+use crate::models::event::EventType;
 use crate::models::member::Member;
+use crate::models::semester::Semester;
+use crate::models::{DateTime, DateTimeInput};
+use crate::util::current_time;
 
 /// Roles that can be held by members to grant permissions
 #[derive(SimpleObject)]
@@ -16,6 +25,7 @@ pub struct Role {
 }
 
 impl Role {
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM roles ORDER BY rank")
             .fetch_all(pool)
@@ -23,11 +33,22 @@ impl Role {
             .map_err(Into::into)
     }
 
+    /// Loads the role, keeping anyone else from locking it until the transaction ends
+    #[tracing::instrument(skip_all, fields(name = %name))]
+    pub async fn lock(name: &str, db: impl PgExecutor<'_>) -> Result<Self> {
+        sqlx::query_as!(Self, "SELECT * FROM roles WHERE name = $1 FOR UPDATE", name)
+            .fetch_optional(db)
+            .await?
+            .ok_or_else(|| ApiError::not_found("role", "name", name).into())
+    }
+
+    /// The roles the member currently holds
+    #[tracing::instrument(skip_all, fields(email = %email))]
     pub async fn for_member(email: &str, pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT * FROM roles WHERE name in 
-             (SELECT role FROM member_roles WHERE member = $1) 
+            "SELECT * FROM roles WHERE name in
+             (SELECT role FROM member_roles WHERE member = $1 AND ended_at IS NULL)
              ORDER BY rank",
             email
         )
@@ -37,69 +58,138 @@ impl Role {
     }
 }
 
-/// A member that holds a role
+/// A term of a member holding a role
 #[derive(SimpleObject)]
 #[graphql(complex)]
 pub struct MemberRole {
+    /// The ID of the term
+    pub id: i64,
     /// The name of the role being held
     pub role: String,
 
     #[graphql(skip)]
     pub member: String,
+    #[graphql(skip)]
+    pub started_at: OffsetDateTime,
+    #[graphql(skip)]
+    pub ended_at: Option<OffsetDateTime>,
 }
 
 #[ComplexObject]
 impl MemberRole {
     /// The member holding the role
     pub async fn member(&self, ctx: &Context<'_>) -> Result<Member> {
-        let pool: &PgPool = ctx.data_unchecked();
-        Member::with_email(&self.member, pool).await
+        load_member(ctx, &self.member).await
+    }
+
+    /// When the member was given the role
+    pub async fn started_at(&self) -> DateTime {
+        DateTime::from(self.started_at)
+    }
+
+    /// When the member stopped holding the role, if they have
+    pub async fn ended_at(&self) -> Option<DateTime> {
+        self.ended_at.map(DateTime::from)
     }
 }
 
 impl MemberRole {
+    #[tracing::instrument(skip_all)]
     pub async fn current_officers(pool: &PgPool) -> Result<Vec<Self>> {
-        sqlx::query_as!(Self, "SELECT * FROM member_roles ORDER BY role, member")
-            .fetch_all(pool)
-            .await
-            .map_err(Into::into)
+        sqlx::query_as!(
+            Self,
+            "SELECT id, member, role, started_at, ended_at FROM member_roles
+             WHERE ended_at IS NULL ORDER BY role, member"
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
     }
 
-    pub async fn member_has_role(member: &str, role: &str, pool: &PgPool) -> Result<bool> {
+    /// Every term that overlapped with the given semester
+    #[tracing::instrument(skip_all, fields(semester = %semester))]
+    pub async fn officers_for_semester(semester: &str, pool: &PgPool) -> Result<Vec<Self>> {
+        let semester = Semester::with_name(semester, pool).await?;
+
+        sqlx::query_as!(
+            Self,
+            "SELECT id, member, role, started_at, ended_at FROM member_roles
+             WHERE started_at::date <= $1 AND (ended_at IS NULL OR ended_at::date >= $2)
+             ORDER BY role, started_at, member",
+            semester.end_date.0,
+            semester.start_date.0
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip_all, fields(member = %member, role = %role))]
+    pub async fn member_has_role(
+        member: &str,
+        role: &str,
+        db: impl PgExecutor<'_>,
+    ) -> Result<bool> {
         let member_role = sqlx::query!(
-            "SELECT * FROM member_roles WHERE member = $1 AND role = $2",
+            "SELECT id FROM member_roles WHERE member = $1 AND role = $2 AND ended_at IS NULL",
             member,
             role
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await?;
 
         Ok(member_role.is_some())
     }
 
-    pub async fn add(member: &str, role: &str, pool: &PgPool) -> Result<()> {
-        if Self::member_has_role(member, role, pool).await? {
-            return Err("Member already has that role".into());
+    #[tracing::instrument(skip_all, fields(member = %member, role = %role))]
+    pub async fn add(member: &str, role: &str, conn: &mut PgConnection) -> Result<()> {
+        let mut transaction = conn.begin().await?;
+        // Locking the role makes concurrent assignments wait for each other,
+        // so they can't both fit under its limit
+        let role = Role::lock(role, &mut *transaction).await?;
+        if Self::member_has_role(member, &role.name, &mut *transaction).await? {
+            return Err(ApiError::conflict("Member already has that role").into());
+        }
+
+        if role.max_quantity > 0 {
+            let holders = sqlx::query_scalar!(
+                "SELECT COUNT(*) as \"count!\" FROM member_roles
+                 WHERE role = $1 AND ended_at IS NULL",
+                role.name
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+            if holders >= role.max_quantity {
+                return Err(ApiError::conflict(format!(
+                    "The {} position can only be held by {} member(s) at once",
+                    role.name, role.max_quantity
+                ))
+                .into());
+            }
         }
 
         sqlx::query!(
             "INSERT INTO member_roles (member, role) VALUES ($1, $2)",
             member,
-            role
+            role.name
         )
-        .execute(pool)
+        .execute(&mut *transaction)
         .await?;
+        transaction.commit().await?;
 
         Ok(())
     }
 
+    /// Ends the member's current term in the role
+    #[tracing::instrument(skip_all, fields(member = %member, role = %role))]
     pub async fn remove(member: &str, role: &str, pool: &PgPool) -> Result<()> {
         if !Self::member_has_role(member, role, pool).await? {
-            return Err("Member does not have that role".into());
+            return Err(ApiError::conflict("Member does not have that role").into());
         }
 
         sqlx::query!(
-            "DELETE FROM member_roles WHERE member = $1 AND role = $2",
+            "UPDATE member_roles SET ended_at = now()
+             WHERE member = $1 AND role = $2 AND ended_at IS NULL",
             member,
             role
         )
@@ -129,19 +219,116 @@ pub struct Permission {
     pub description: Option<String>,
     /// Whether the permission applies to a type of event or generally
     pub r#type: PermissionType,
+    /// Whether the permission only takes effect for members with
+    /// two-factor authentication turned on
+    pub requires_two_factor: bool,
 }
 
 impl Permission {
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT name, description, type as \"type: _\"
+            "SELECT name, description, type as \"type: _\", requires_two_factor
              FROM permissions ORDER BY name"
         )
         .fetch_all(pool)
         .await
         .map_err(Into::into)
     }
+
+    #[tracing::instrument(skip_all, fields(name = %name))]
+    pub async fn set_requires_two_factor(name: &str, required: bool, pool: &PgPool) -> Result<()> {
+        let result = sqlx::query!(
+            "UPDATE permissions SET requires_two_factor = $1 WHERE name = $2",
+            required,
+            name
+        )
+        .execute(pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            Err(ApiError::not_found("permission", "name", name).into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Differences between the permissions and event types the API checks for
+/// and the ones in the database
+#[derive(SimpleObject)]
+pub struct CatalogDrift {
+    /// Permissions the API checks for that aren't in the database
+    pub missing_permissions: Vec<String>,
+    /// Permissions whose type in the database doesn't match the API
+    pub mismatched_permissions: Vec<String>,
+    /// Permissions in the database that the API never checks for
+    pub unused_permissions: Vec<String>,
+    /// Event types the API refers to that aren't in the database
+    pub missing_event_types: Vec<String>,
+}
+
+impl CatalogDrift {
+    #[tracing::instrument(skip_all)]
+    pub async fn check(pool: &PgPool) -> Result<Self> {
+        let existing = Permission::all(pool).await?;
+        let event_types = EventType::all(pool).await?;
+
+        let mut drift = Self {
+            missing_permissions: Vec::new(),
+            mismatched_permissions: Vec::new(),
+            unused_permissions: Vec::new(),
+            missing_event_types: Vec::new(),
+        };
+        for permission in guards::Permission::ALL {
+            match existing.iter().find(|p| p.name == permission.name()) {
+                None => drift.missing_permissions.push(permission.name().to_owned()),
+                Some(p) if p.r#type != permission.r#type() => drift
+                    .mismatched_permissions
+                    .push(permission.name().to_owned()),
+                Some(_) => {}
+            }
+        }
+        drift.unused_permissions = existing
+            .into_iter()
+            .filter(|p| !guards::Permission::ALL.iter().any(|gp| gp.name() == p.name))
+            .map(|p| p.name)
+            .collect();
+        drift.missing_event_types = EventType::ALL
+            .iter()
+            .filter(|name| !event_types.iter().any(|et| &et.name == *name))
+            .map(|name| name.to_string())
+            .collect();
+
+        Ok(drift)
+    }
+
+    /// Inserts any permissions the API checks for that are missing
+    #[tracing::instrument(skip_all)]
+    pub async fn seed_missing_permissions(pool: &PgPool) -> Result<()> {
+        for permission in guards::Permission::ALL {
+            sqlx::query!(
+                "INSERT INTO permissions (name, description, type) VALUES ($1, $2, $3)
+                 ON CONFLICT (name) DO NOTHING",
+                permission.name(),
+                permission.description(),
+                permission.r#type() as _
+            )
+            .execute(pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the API can't work correctly with the current database.
+    /// Unused permissions are harmless, so they are only reported
+    pub fn is_breaking(&self) -> bool {
+        !self.missing_permissions.is_empty()
+            || !self.mismatched_permissions.is_empty()
+            || !self.missing_event_types.is_empty()
+    }
 }
 
 /// A new assignment of a permission to a role
@@ -169,6 +356,7 @@ pub struct RolePermission {
 }
 
 impl RolePermission {
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM role_permissions")
             .fetch_all(pool)
@@ -176,6 +364,7 @@ impl RolePermission {
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn add(role_permission: NewRolePermission, pool: &PgPool) -> Result<()> {
         sqlx::query_as!(
             Self,
@@ -192,6 +381,7 @@ impl RolePermission {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn remove(role_permission: NewRolePermission, pool: &PgPool) -> Result<()> {
         sqlx::query_as!(
             Self,
@@ -207,22 +397,169 @@ impl RolePermission {
     }
 }
 
-/// A permission that a member holds
+/// A permission granted directly to a member until it expires
 #[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct PermissionGrant {
+    /// The ID of the grant
+    pub id: i64,
+    /// The name of the permission granted
+    pub permission: String,
+    /// Optionally, the type of event the permission applies to
+    pub event_type: Option<String>,
+    /// Why the member was given the permission
+    pub reason: String,
+
+    #[graphql(skip)]
+    pub member: String,
+    #[graphql(skip)]
+    pub granted_by: Option<String>,
+    #[graphql(skip)]
+    pub granted_at: OffsetDateTime,
+    #[graphql(skip)]
+    pub expires_at: OffsetDateTime,
+}
+
+#[ComplexObject]
+impl PermissionGrant {
+    /// The member given the permission
+    pub async fn member(&self, ctx: &Context<'_>) -> Result<Member> {
+        load_member(ctx, &self.member).await
+    }
+
+    /// The officer that granted the permission, if they're still a member
+    pub async fn granted_by(&self, ctx: &Context<'_>) -> Result<Option<Member>> {
+        if let Some(granted_by) = &self.granted_by {
+            let pool: &PgPool = ctx.data_unchecked();
+            Member::with_email_opt(granted_by, pool).await
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// When the permission was granted
+    pub async fn granted_at(&self) -> DateTime {
+        DateTime::from(self.granted_at)
+    }
+
+    /// When the permission stops taking effect
+    pub async fn expires_at(&self) -> DateTime {
+        DateTime::from(self.expires_at)
+    }
+}
+
+/// A new direct grant of a permission to a member
+#[derive(InputObject)]
+pub struct NewPermissionGrant {
+    /// The email of the member to grant the permission to
+    pub member: String,
+    /// The name of the permission to grant
+    pub permission: String,
+    /// Optionally, the type of event the permission applies to
+    pub event_type: Option<String>,
+    /// Why the member needs the permission
+    pub reason: String,
+    /// When the permission stops taking effect
+    pub expires_at: DateTimeInput,
+}
+
+impl PermissionGrant {
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn with_id(id: i64, pool: &PgPool) -> Result<Self> {
+        sqlx::query_as!(Self, "SELECT * FROM member_permissions WHERE id = $1", id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| ApiError::not_found("permission grant", "ID", id).into())
+    }
+
+    /// All grants that haven't expired yet, soonest to expire first
+    #[tracing::instrument(skip_all)]
+    pub async fn active(pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            "SELECT * FROM member_permissions WHERE expires_at > now() ORDER BY expires_at, member"
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip_all, fields(granted_by = %granted_by))]
+    pub async fn create(grant: NewPermissionGrant, granted_by: &str, pool: &PgPool) -> Result<i64> {
+        let expires_at = OffsetDateTime::from(grant.expires_at);
+        if expires_at <= current_time() {
+            return Err(
+                ApiError::validation("Granted permissions must expire in the future").into(),
+            );
+        }
+        if grant.reason.trim().is_empty() {
+            return Err(ApiError::validation("A reason is required to grant a permission").into());
+        }
+        Member::with_email(&grant.member, pool).await?; // ensure that member exists
+
+        sqlx::query_scalar!(
+            "INSERT INTO member_permissions
+             (member, permission, event_type, reason, granted_by, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id",
+            grant.member,
+            grant.permission,
+            grant.event_type,
+            grant.reason,
+            granted_by,
+            expires_at
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip_all, fields(id = id))]
+    pub async fn revoke(id: i64, pool: &PgPool) -> Result<()> {
+        Self::with_id(id, pool).await?; // ensure that grant exists
+
+        sqlx::query!("DELETE FROM member_permissions WHERE id = $1", id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// A permission that a member holds through one of their roles or a direct grant.
+/// Permissions that require two-factor authentication are left out for members without it
+#[derive(SimpleObject, Clone)]
 pub struct MemberPermission {
     /// The name of the permission
     pub name: String,
     /// Optionally, the type of event the permission applies to
     pub event_type: Option<String>,
+    /// The role the permission comes from, if any
+    pub role: Option<String>,
+    /// The ID of the direct grant the permission comes from, if any
+    pub grant_id: Option<i64>,
 }
 
 impl MemberPermission {
+    #[tracing::instrument(skip_all, fields(member = %member))]
     pub async fn for_member(member: &str, pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT permission as name, event_type FROM role_permissions
-             INNER JOIN member_roles ON role_permissions.role = member_roles.role
-             WHERE member_roles.member = $1",
+            "SELECT granted.permission as \"name!\", granted.event_type, granted.role,
+                 granted.grant_id FROM (
+                 SELECT role_permissions.permission, role_permissions.event_type,
+                     role_permissions.role, NULL::bigint as grant_id
+                 FROM role_permissions
+                 INNER JOIN member_roles ON role_permissions.role = member_roles.role
+                 WHERE member_roles.member = $1 AND member_roles.ended_at IS NULL
+                 UNION
+                 SELECT permission, event_type, NULL, id FROM member_permissions
+                 WHERE member = $1 AND expires_at > now()
+             ) granted
+             INNER JOIN permissions ON granted.permission = permissions.name
+             WHERE NOT permissions.requires_two_factor
+                 OR EXISTS(SELECT 1 FROM totp_credentials WHERE member = $1 AND confirmed)
+             ORDER BY granted.permission, granted.event_type",
             member
         )
         .fetch_all(pool)
@@ -230,3 +567,70 @@ impl MemberPermission {
         .map_err(Into::into)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::join_all;
+
+    use crate::models::permissions::MemberRole;
+    use crate::util::with_cleanup;
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn concurrent_assignments_respect_the_role_limit() {
+        const MEMBERS: usize = 4;
+        let cleanup = [
+            "DELETE FROM members WHERE email LIKE '%@roles.test'",
+            "DELETE FROM roles WHERE name = 'Limit Test'",
+        ];
+
+        with_cleanup(&cleanup, |pool| async move {
+            sqlx::query(
+                "INSERT INTO roles (name, rank, max_quantity) VALUES ('Limit Test', 99, 1)",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+            for index in 0..MEMBERS {
+                sqlx::query(
+                    "INSERT INTO members
+                     (email, first_name, last_name, pass_hash, phone_number, location)
+                     VALUES ($1, 'Rory', 'Role', '', '', '')",
+                )
+                .bind(format!("member{}@roles.test", index))
+                .execute(&pool)
+                .await
+                .unwrap();
+            }
+
+            // connect first so the assignments actually overlap
+            let mut conns = Vec::new();
+            for _ in 0..MEMBERS {
+                conns.push(pool.acquire().await.unwrap());
+            }
+            let emails: Vec<_> = (0..MEMBERS)
+                .map(|index| format!("member{}@roles.test", index))
+                .collect();
+            // each assignment releases its connection when done, which rolls back the losers
+            let results = join_all(
+                conns
+                    .into_iter()
+                    .zip(&emails)
+                    .map(|(mut conn, email)| async move {
+                        MemberRole::add(email, "Limit Test", &mut conn).await
+                    }),
+            )
+            .await;
+            let holders: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM member_roles WHERE role = 'Limit Test' AND ended_at IS NULL",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+            assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+            assert_eq!(holders, 1);
+        })
+        .await;
+    }
+}
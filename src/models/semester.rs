@@ -1,6 +1,8 @@
 use async_graphql::{InputObject, Result, SimpleObject};
-use sqlx::PgPool;
+use sqlx::postgres::{PgConnection, PgExecutor};
+use sqlx::{Connection, PgPool};
 
+use crate::error::ApiError;
 use crate::models::DateScalar;
 
 /// A school semester that events happen during
@@ -19,18 +21,20 @@ pub struct Semester {
 }
 
 impl Semester {
-    pub async fn get_current(pool: &PgPool) -> Result<Self> {
+    #[tracing::instrument(skip_all)]
+    pub async fn get_current(db: impl PgExecutor<'_>) -> Result<Self> {
         sqlx::query_as!(
             Self,
             "SELECT name, start_date as \"start_date: _\", end_date as \"end_date: _\",
                  gig_requirement, current
              FROM semesters WHERE current = true"
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await?
-        .ok_or_else(|| "No current semester set".into())
+        .ok_or_else(|| ApiError::not_found("semester", "status", "current").into())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_previous(pool: &PgPool) -> Result<Option<Self>> {
         let semesters: Vec<_> =
             sqlx::query!("SELECT name, start_date, current FROM semesters ORDER BY start_date")
@@ -58,13 +62,15 @@ impl Semester {
         }
     }
 
-    pub async fn with_name(name: &str, pool: &PgPool) -> Result<Self> {
-        Self::with_name_opt(name, pool)
+    #[tracing::instrument(skip_all, fields(name = %name))]
+    pub async fn with_name(name: &str, db: impl PgExecutor<'_>) -> Result<Self> {
+        Self::with_name_opt(name, db)
             .await?
-            .ok_or_else(|| format!("No semester named {}", name).into())
+            .ok_or_else(|| ApiError::not_found("semester", "name", name).into())
     }
 
-    pub async fn with_name_opt(name: &str, pool: &PgPool) -> Result<Option<Self>> {
+    #[tracing::instrument(skip_all, fields(name = %name))]
+    pub async fn with_name_opt(name: &str, db: impl PgExecutor<'_>) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
             "SELECT name, start_date as \"start_date: _\", end_date as \"end_date: _\",
@@ -72,11 +78,12 @@ impl Semester {
              FROM semesters WHERE name = $1",
             name
         )
-        .fetch_optional(pool)
+        .fetch_optional(db)
         .await
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
@@ -89,12 +96,17 @@ impl Semester {
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn create(new_semester: NewSemester, pool: &PgPool) -> Result<()> {
         if Self::with_name_opt(&new_semester.name, pool)
             .await?
             .is_some()
         {
-            return Err(format!("A semester already exists named {}", new_semester.name).into());
+            return Err(ApiError::conflict(format!(
+                "A semester already exists named {}",
+                new_semester.name
+            ))
+            .into());
         }
 
         sqlx::query!(
@@ -111,12 +123,17 @@ impl Semester {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn update(name: &str, update: NewSemester, pool: &PgPool) -> Result<()> {
         // check that semester exists
         Self::with_name(name, pool).await?;
 
         if name != &update.name && Self::with_name_opt(&update.name, pool).await?.is_some() {
-            return Err(format!("Another semester is already named {}", update.name).into());
+            return Err(ApiError::conflict(format!(
+                "Another semester is already named {}",
+                update.name
+            ))
+            .into());
         }
 
         sqlx::query!(
@@ -135,21 +152,24 @@ impl Semester {
         Ok(())
     }
 
-    pub async fn set_current(name: &str, pool: &PgPool) -> Result<()> {
+    #[tracing::instrument(skip_all, fields(name = %name))]
+    pub async fn set_current(name: &str, conn: &mut PgConnection) -> Result<()> {
+        let mut transaction = conn.begin().await?;
         if sqlx::query!("SELECT name FROM semesters WHERE name = $1", name)
-            .fetch_optional(pool)
+            .fetch_optional(&mut *transaction)
             .await?
             .is_none()
         {
-            return Err(format!("No semester named {}", name).into());
+            return Err(ApiError::not_found("semester", "name", name).into());
         }
 
         sqlx::query!("UPDATE semesters SET current = false")
-            .execute(pool)
+            .execute(&mut *transaction)
             .await?;
         sqlx::query!("UPDATE semesters SET current = true WHERE name = $1", name)
-            .execute(pool)
+            .execute(&mut *transaction)
             .await?;
+        transaction.commit().await?;
 
         Ok(())
     }
@@ -1,6 +1,9 @@
 use async_graphql::{ComplexObject, Context, Enum, InputObject, Result, SimpleObject};
 use sqlx::PgPool;
 
+use crate::error::ApiError;
+use crate::graphql::pagination::PageWindow;
+
 /// A musical note
 #[derive(Clone, Copy, PartialEq, Eq, Enum, sqlx::Type)]
 #[sqlx(type_name = "pitch", rename_all = "snake_case")]
@@ -39,7 +42,7 @@ pub enum SongMode {
 }
 
 /// A song that the Glee Club performs
-#[derive(SimpleObject)]
+#[derive(SimpleObject, Clone)]
 #[graphql(complex)]
 pub struct Song {
     /// The ID of the song
@@ -57,6 +60,8 @@ pub struct Song {
     pub starting_pitch: Option<Pitch>,
     /// The mode of the song (Major or Minor)
     pub mode: Option<SongMode>,
+    /// Goes up with every edit, so updates based on an older version can be rejected
+    pub version: i64,
 }
 
 #[ComplexObject]
@@ -78,17 +83,19 @@ impl Song {
 }
 
 impl Song {
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id(id: i64, pool: &PgPool) -> Result<Self> {
         Self::with_id_opt(id, pool)
             .await?
-            .ok_or_else(|| format!("No song with ID {}", id).into())
+            .ok_or_else(|| ApiError::not_found("song", "ID", id).into())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id_opt(id: i64, pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
             "SELECT id, title, info, current, key as \"key: _\",
-                 starting_pitch as \"starting_pitch: _\", mode as \"mode: _\"
+                 starting_pitch as \"starting_pitch: _\", mode as \"mode: _\", version
              FROM songs WHERE id = $1",
             id
         )
@@ -97,56 +104,61 @@ impl Song {
         .map_err(Into::into)
     }
 
-    pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
-        sqlx::query_as!(
-            Self,
-            "SELECT id, title, info, current, key as \"key: _\",
-                 starting_pitch as \"starting_pitch: _\", mode as \"mode: _\"
-             FROM songs ORDER BY title"
-        )
-        .fetch_all(pool)
-        .await
-        .map_err(Into::into)
+    #[tracing::instrument(skip_all)]
+    pub async fn count(pool: &PgPool) -> Result<i64> {
+        sqlx::query_scalar!("SELECT COUNT(*) as \"count!\" FROM songs")
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
     }
 
-    pub async fn setlist_for_event(event_id: i64, pool: &PgPool) -> Result<Vec<Self>> {
+    /// A page of songs, sorted by title
+    #[tracing::instrument(skip_all)]
+    pub async fn page(window: PageWindow, pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT s.id, s.title, s.info, s.current, s.key as \"key: _\",
-                 s.starting_pitch as \"starting_pitch: _\", s.mode as \"mode: _\"
-             FROM songs s INNER JOIN gig_songs ON s.id = gig_songs.song
-             WHERE gig_songs.event = $1 ORDER BY gig_songs.order",
-            event_id
+            "SELECT id, title, info, current, key as \"key: _\",
+                 starting_pitch as \"starting_pitch: _\", mode as \"mode: _\", version
+             FROM songs ORDER BY title, id LIMIT $1 OFFSET $2",
+            window.limit,
+            window.offset
         )
         .fetch_all(pool)
         .await
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn create(new_song: NewSong, pool: &PgPool) -> Result<i64> {
-        sqlx::query!(
-            "INSERT INTO songs (title, info) VALUES ($1, $2)",
+        sqlx::query_scalar!(
+            "INSERT INTO songs (title, info) VALUES ($1, $2) RETURNING id",
             new_song.title,
             new_song.info
         )
-        .execute(pool)
-        .await?;
-
-        sqlx::query_scalar!("SELECT id FROM songs ORDER BY id DESC")
-            .fetch_one(pool)
-            .await
-            .map_err(Into::into)
+        .fetch_one(pool)
+        .await
+        .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn update(id: i64, updated_song: SongUpdate, pool: &PgPool) -> Result<()> {
-        sqlx::query!(
-            "UPDATE songs SET title = $1, current = $2, info = $3, key = $4, starting_pitch = $5, mode = $6 WHERE id = $7",
-            updated_song.title, updated_song.current, updated_song.info, updated_song.key as _, updated_song.starting_pitch as _, updated_song.mode as _, id
+        let updated = sqlx::query!(
+            "UPDATE songs SET title = $1, current = $2, info = $3, key = $4, starting_pitch = $5, mode = $6,
+                 version = version + 1
+             WHERE id = $7 AND ($8::bigint IS NULL OR version = $8)",
+            updated_song.title, updated_song.current, updated_song.info, updated_song.key as _, updated_song.starting_pitch as _, updated_song.mode as _, id,
+            updated_song.version
         ).execute(pool).await?;
 
+        if updated.rows_affected() == 0 {
+            let current = Song::with_id(id, pool).await?;
+            return Err(ApiError::stale_version("song", id, current.version).into());
+        }
+
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn delete(id: i64, pool: &PgPool) -> Result<()> {
         // verify exists
         Song::with_id(id, pool).await?;
@@ -171,6 +183,7 @@ pub struct PublicSong {
 }
 
 impl PublicSong {
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         let mut all_public_videos = sqlx::query!(
             "SELECT name, url, song FROM song_links WHERE type = $1",
@@ -239,12 +252,14 @@ pub enum StorageType {
 }
 
 impl MediaType {
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn with_name(name: &str, pool: &PgPool) -> Result<Self> {
         Self::with_name_opt(name, pool)
             .await?
-            .ok_or_else(|| format!("No media type named {}", name).into())
+            .ok_or_else(|| ApiError::not_found("media type", "name", name).into())
     }
 
+    #[tracing::instrument(skip_all, fields(name = %name))]
     pub async fn with_name_opt(name: &str, pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as!(
             Self,
@@ -257,6 +272,7 @@ impl MediaType {
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn all(pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
@@ -297,7 +313,7 @@ impl SongLink {
         } else if let Some(file) = &self.file {
             Ok(format!("https://grease.fly.dev/files/{}", file))
         } else {
-            Err("Song link is malformed and has no URL".into())
+            Err(ApiError::internal("Song link is malformed and has no URL").into())
         }
     }
 }
@@ -305,12 +321,14 @@ impl SongLink {
 impl SongLink {
     pub const PERFORMANCES: &'static str = "Performances";
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id(id: i64, pool: &PgPool) -> Result<Self> {
         Self::with_id_opt(id, pool)
             .await?
-            .ok_or_else(|| format!("No song link with ID {}", id).into())
+            .ok_or_else(|| ApiError::not_found("song link", "ID", id).into())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn with_id_opt(id: i64, pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM song_links WHERE id = $1", id)
             .fetch_optional(pool)
@@ -318,6 +336,7 @@ impl SongLink {
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(song_id = song_id))]
     pub async fn for_song(song_id: i64, pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as!(
             Self,
@@ -329,51 +348,48 @@ impl SongLink {
         .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all, fields(song_id = song_id))]
     pub async fn create(song_id: i64, new_link: NewSongLink, pool: &PgPool) -> Result<i64> {
         MediaType::with_name(&new_link.r#type, pool).await?;
 
-        if let Some(content) = new_link.content {
-            let data = base64::decode(&content)
-                .map_err(|err| format!("Failed to decode file content: {err}"))?;
+        let mut transaction = pool.begin().await?;
+        // uploaded files are linked by name instead of by URL
+        let (url, file) = if let Some(content) = new_link.content {
+            let data = base64::decode(&content).map_err(|err| {
+                ApiError::validation(format!("Failed to decode file content: {err}"))
+            })?;
 
             sqlx::query!(
                 "INSERT INTO song_files (name, data) VALUES ($1, $2)",
                 new_link.url,
                 data
             )
-            .execute(pool)
-            .await?;
-            sqlx::query!(
-                "INSERT INTO song_links (song, type, name, url, file)
-                 VALUES ($1, $2, $3, $4, $5)",
-                song_id,
-                new_link.r#type,
-                new_link.name,
-                Option::<String>::None,
-                new_link.url,
-            )
-            .execute(pool)
+            .execute(&mut *transaction)
             .await?;
+
+            (None, Some(new_link.url))
         } else {
-            sqlx::query!(
-                "INSERT INTO song_links (song, type, name, url, file)
-                 VALUES ($1, $2, $3, $4, $5)",
-                song_id,
-                new_link.r#type,
-                new_link.name,
-                new_link.url,
-                Option::<String>::None,
-            )
-            .execute(pool)
-            .await?;
-        }
+            (Some(new_link.url), None)
+        };
+
+        let id = sqlx::query_scalar!(
+            "INSERT INTO song_links (song, type, name, url, file)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING id",
+            song_id,
+            new_link.r#type,
+            new_link.name,
+            url,
+            file
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
 
-        sqlx::query_scalar!("SELECT id FROM song_links ORDER BY id DESC")
-            .fetch_one(pool)
-            .await
-            .map_err(Into::into)
+        Ok(id)
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn update(id: i64, update: SongLinkUpdate, pool: &PgPool) -> Result<()> {
         let song_link = SongLink::with_id(id, pool).await?;
 
@@ -387,9 +403,9 @@ impl SongLink {
             .execute(pool)
             .await?;
         } else {
-            let old_file_name = song_link
-                .file
-                .ok_or_else(|| "Can't update file name because old name is missing")?;
+            let old_file_name = song_link.file.ok_or_else(|| {
+                ApiError::internal("Can't update file name because old name is missing")
+            })?;
 
             sqlx::query!(
                 "UPDATE song_links SET name = $1 WHERE id = $2",
@@ -410,6 +426,7 @@ impl SongLink {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(id = id))]
     pub async fn delete(id: i64, pool: &PgPool) -> Result<()> {
         let song_link = SongLink::with_id(id, pool).await?;
 
@@ -451,6 +468,8 @@ pub struct SongUpdate {
     pub starting_pitch: Option<Pitch>,
     /// Whether the song is in major or minor
     pub mode: Option<SongMode>,
+    /// The version being edited, if the edit should be rejected when it's out of date
+    pub version: Option<i64>,
 }
 
 /// A new link to media under a song
@@ -1,6 +1,7 @@
 use async_graphql::{Result, SimpleObject};
 use sqlx::{FromRow, PgPool};
 
+use crate::error::ApiError;
 /// Arbitrary variables for developer usage.
 #[derive(SimpleObject, FromRow)]
 pub struct Variable {
@@ -11,13 +12,15 @@ pub struct Variable {
 }
 
 impl Variable {
+    #[tracing::instrument(skip_all)]
     pub async fn with_key(key: &str, pool: &PgPool) -> Result<Self> {
         Self::with_key_opt(key, pool)
             .await?
-            .ok_or_else(|| format!("No variable with key {}", key))
+            .ok_or_else(|| ApiError::not_found("variable", "key", key))
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn with_key_opt(key: &str, pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as!(Self, "SELECT * FROM variables WHERE key = $1", key)
             .fetch_optional(pool)
@@ -25,6 +28,7 @@ impl Variable {
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn set(key: &str, value: &str, pool: &PgPool) -> Result<()> {
         if Self::with_key_opt(key, pool).await?.is_some() {
             sqlx::query!("UPDATE variables SET value = $1 WHERE key = $2", value, key)
@@ -43,6 +47,7 @@ impl Variable {
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn unset(key: &str, pool: &PgPool) -> Result<()> {
         sqlx::query!("DELETE FROM variables WHERE key = $1", key)
             .execute(pool)
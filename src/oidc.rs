@@ -0,0 +1,522 @@
+//! Optional single sign-on through an OpenID Connect provider.
+//!
+//! Members are sent to `/auth/oidc/login`, which redirects them to the provider.
+//! The provider sends them back to `/auth/oidc/callback`, which redirects them to
+//! the frontend with either a login `token`, a `link` code for a provider account
+//! that didn't match any member, a `twoFactor` challenge for members with two-factor
+//! authentication to finish with `finishTwoFactorLogin`, or an `error` in the URL fragment.
+
+use std::env::var;
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_graphql::Result;
+use axum::extract::Query;
+use axum::headers::HeaderMap;
+use axum::http::header::USER_AGENT;
+use axum::response::Redirect;
+use axum::routing::get;
+use axum::{Extension, Router};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use url::{form_urlencoded, Url};
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::models::member::external_identity::ExternalIdentity;
+use crate::models::member::session::Session;
+use crate::models::member::two_factor::TwoFactor;
+use crate::models::member::Member;
+use crate::util::current_time;
+
+/// How long a member has to finish logging in at the provider
+const LOGIN_STATE_LIFETIME: Duration = Duration::minutes(10);
+
+/// Settings for the OpenID Connect provider
+pub struct OidcConfig {
+    /// The provider's issuer URL, which serves `/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Where the provider sends members back to, i.e. this API's `/auth/oidc/callback`
+    pub redirect_url: String,
+    /// The frontend page that gets the result of logging in
+    pub return_url: String,
+}
+
+impl OidcConfig {
+    /// Reads the config from `OIDC_*` environment variables, or returns
+    /// `None` if single sign-on isn't configured
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let issuer = if let Ok(issuer) = var("OIDC_ISSUER") {
+            issuer
+        } else {
+            return Ok(None);
+        };
+        let required =
+            |name: &str| var(name).with_context(|| format!("{name} must be set with OIDC_ISSUER"));
+
+        Ok(Some(Self {
+            issuer,
+            client_id: required("OIDC_CLIENT_ID")?,
+            client_secret: required("OIDC_CLIENT_SECRET")?,
+            redirect_url: required("OIDC_REDIRECT_URL")?,
+            return_url: required("OIDC_RETURN_URL")?,
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// What the provider tells us about the account that logged in
+#[derive(Deserialize)]
+pub struct UserInfo {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+}
+
+pub struct OidcProvider {
+    config: OidcConfig,
+    metadata: ProviderMetadata,
+    client: reqwest::Client,
+}
+
+impl OidcProvider {
+    /// Fetches the provider's endpoints from its discovery document
+    pub async fn discover(config: OidcConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::new();
+        let discovery_url = format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer.trim_end_matches('/')
+        );
+        let metadata = client
+            .get(&discovery_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to fetch {discovery_url}"))?
+            .json()
+            .await
+            .context("Invalid OpenID Connect discovery document")?;
+
+        Ok(Self {
+            config,
+            metadata,
+            client,
+        })
+    }
+
+    /// The provider page to send the member to, using PKCE to tie the
+    /// eventual callback to this login
+    pub fn authorization_url(&self, state: &str, code_verifier: &str) -> anyhow::Result<Url> {
+        let code_challenge =
+            base64::encode_config(&Sha256::digest(code_verifier), base64::URL_SAFE_NO_PAD);
+
+        let mut url = Url::parse(&self.metadata.authorization_endpoint)
+            .context("Invalid authorization endpoint")?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("scope", "openid email")
+            .append_pair("state", state)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url)
+    }
+
+    /// Trades an authorization code for the account's info
+    pub async fn user_info(&self, code: &str, code_verifier: &str) -> anyhow::Result<UserInfo> {
+        let token: TokenResponse = self
+            .client
+            .post(&self.metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.config.redirect_url),
+                ("client_id", &self.config.client_id),
+                ("client_secret", &self.config.client_secret),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("The provider rejected the login")?
+            .json()
+            .await
+            .context("Invalid token response from the provider")?;
+
+        self.client
+            .get(&self.metadata.userinfo_endpoint)
+            .bearer_auth(token.access_token)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Failed to get account info from the provider")?
+            .json()
+            .await
+            .context("Invalid account info from the provider")
+    }
+
+    async fn start_login(&self, pool: &PgPool) -> Result<Url> {
+        sqlx::query!(
+            "DELETE FROM oidc_login_states WHERE created_at < $1",
+            current_time() - LOGIN_STATE_LIFETIME
+        )
+        .execute(pool)
+        .await?;
+
+        let state = Uuid::new_v4().to_simple().to_string();
+        let code_verifier = format!(
+            "{}{}",
+            Uuid::new_v4().to_simple(),
+            Uuid::new_v4().to_simple()
+        );
+        sqlx::query!(
+            "INSERT INTO oidc_login_states (state, code_verifier) VALUES ($1, $2)",
+            state,
+            code_verifier
+        )
+        .execute(pool)
+        .await?;
+
+        self.authorization_url(&state, &code_verifier)
+            .map_err(|err| ApiError::internal(format!("{err:#}")).into())
+    }
+
+    async fn finish_login(
+        &self,
+        params: CallbackParams,
+        user_agent: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<LoginResult> {
+        if let Some(error) = params.error {
+            return Err(ApiError::Unauthenticated(format!(
+                "The provider refused the login: {}",
+                params.error_description.unwrap_or(error)
+            ))
+            .into());
+        }
+        let (code, state) = params
+            .code
+            .zip(params.state)
+            .ok_or_else(|| ApiError::validation("The provider didn't send back a login code"))?;
+
+        let login_state = sqlx::query!(
+            "DELETE FROM oidc_login_states WHERE state = $1
+             RETURNING code_verifier, created_at as \"created_at: OffsetDateTime\"",
+            state
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| {
+            ApiError::Unauthenticated("Unknown login attempt, please try again".to_owned())
+        })?;
+        if login_state.created_at + LOGIN_STATE_LIFETIME < current_time() {
+            return Err(ApiError::Unauthenticated(
+                "Your login attempt timed out, please try again".to_owned(),
+            )
+            .into());
+        }
+
+        let info = self
+            .user_info(&code, &login_state.code_verifier)
+            .await
+            .map_err(|err| ApiError::internal(format!("{err:#}")))?;
+        let email = info.email.filter(|_| info.email_verified).ok_or_else(|| {
+            ApiError::Unauthenticated("The provider hasn't verified the account's email".to_owned())
+        })?;
+
+        let issuer = &self.config.issuer;
+        let member = if let Some(identity) =
+            ExternalIdentity::for_subject_opt(issuer, &info.sub, pool).await?
+        {
+            identity.member
+        } else if Member::with_email_opt(&email, pool).await?.is_some() {
+            ExternalIdentity::link(issuer, &info.sub, &email, &email, pool).await?;
+            email
+        } else {
+            let code =
+                ExternalIdentity::create_pending_link(issuer, &info.sub, &email, pool).await?;
+            return Ok(LoginResult::PendingLink(code));
+        };
+
+        // Signing in elsewhere doesn't get around the member's second factor
+        if TwoFactor::is_enabled(&member, pool).await? {
+            let challenge = TwoFactor::create_challenge(&member, pool).await?;
+            return Ok(LoginResult::PendingTwoFactor(challenge));
+        }
+
+        let token = Session::generate(&member, user_agent, pool).await?;
+        Ok(LoginResult::LoggedIn(token))
+    }
+
+    /// Sends the member back to the frontend with a single value in the URL fragment
+    fn return_with(&self, key: &str, value: &str) -> Redirect {
+        let fragment = form_urlencoded::Serializer::new(String::new())
+            .append_pair(key, value)
+            .finish();
+
+        Redirect::to(&format!("{}#{}", self.config.return_url, fragment))
+    }
+}
+
+enum LoginResult {
+    LoggedIn(String),
+    PendingLink(String),
+    PendingTwoFactor(String),
+}
+
+#[derive(Deserialize)]
+struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// The single sign-on routes, to be merged into the main router
+pub fn routes(provider: OidcProvider) -> Router {
+    Router::new()
+        .route("/auth/oidc/login", get(login))
+        .route("/auth/oidc/callback", get(callback))
+        .layer(Extension(Arc::new(provider)))
+}
+
+async fn login(
+    Extension(provider): Extension<Arc<OidcProvider>>,
+    Extension(pool): Extension<PgPool>,
+) -> Redirect {
+    match provider.start_login(&pool).await {
+        Ok(url) => Redirect::to(url.as_str()),
+        Err(error) => provider.return_with("error", &error.message),
+    }
+}
+
+async fn callback(
+    Query(params): Query<CallbackParams>,
+    headers: HeaderMap,
+    Extension(provider): Extension<Arc<OidcProvider>>,
+    Extension(pool): Extension<PgPool>,
+) -> Redirect {
+    let user_agent = headers
+        .get(USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok());
+
+    match provider.finish_login(params, user_agent, &pool).await {
+        Ok(LoginResult::LoggedIn(token)) => provider.return_with("token", &token),
+        Ok(LoginResult::PendingLink(code)) => provider.return_with("link", &code),
+        Ok(LoginResult::PendingTwoFactor(challenge)) => {
+            provider.return_with("twoFactor", &challenge)
+        }
+        Err(error) => provider.return_with("error", &error.message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+
+    use async_graphql::Request;
+    use axum::extract::Form;
+    use axum::headers::authorization::Bearer;
+    use axum::headers::Authorization;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::{Json, Router, TypedHeader};
+    use serde_json::json;
+
+    use crate::graphql::{build_schema, ClientInfo, QueryLimits};
+    use crate::oidc::{CallbackParams, LoginResult, OidcConfig, OidcProvider};
+    use crate::util::{hash_token, with_cleanup};
+
+    /// Starts a minimal OpenID Connect provider on a random local port
+    fn mock_provider() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let discovery = json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+            "userinfo_endpoint": format!("{issuer}/userinfo"),
+        });
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(|| async move { Json(discovery) }),
+            )
+            .route(
+                "/token",
+                post(|Form(form): Form<HashMap<String, String>>| async move {
+                    let valid = form.get("code").map(String::as_str) == Some("good-code")
+                        && form.contains_key("code_verifier");
+                    if valid {
+                        Ok(Json(
+                            json!({ "access_token": "access", "token_type": "Bearer" }),
+                        ))
+                    } else {
+                        Err(StatusCode::BAD_REQUEST)
+                    }
+                }),
+            )
+            .route(
+                "/userinfo",
+                get(
+                    |TypedHeader(auth): TypedHeader<Authorization<Bearer>>| async move {
+                        if auth.token() == "access" {
+                            Ok(Json(json!({
+                                "sub": "12345",
+                                "email": "gburdell3@gatech.edu",
+                                "email_verified": true,
+                            })))
+                        } else {
+                            Err(StatusCode::UNAUTHORIZED)
+                        }
+                    },
+                ),
+            );
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        issuer
+    }
+
+    async fn discover_mock_provider() -> OidcProvider {
+        OidcProvider::discover(OidcConfig {
+            issuer: mock_provider(),
+            client_id: "grease".to_owned(),
+            client_secret: "secret".to_owned(),
+            redirect_url: "http://localhost:3000/auth/oidc/callback".to_owned(),
+            return_url: "http://localhost:8080/#/login".to_owned(),
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn logs_in_against_mock_provider() {
+        let provider = discover_mock_provider().await;
+
+        let url = provider
+            .authorization_url("some-state", "verifier")
+            .unwrap();
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["state"], "some-state");
+        assert_eq!(params["code_challenge_method"], "S256");
+
+        let info = provider.user_info("good-code", "verifier").await.unwrap();
+        assert_eq!(info.sub, "12345");
+        assert_eq!(info.email.as_deref(), Some("gburdell3@gatech.edu"));
+        assert!(info.email_verified);
+
+        assert!(provider.user_info("bad-code", "verifier").await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database at DATABASE_URL"]
+    async fn two_factor_members_need_a_code_after_signing_in() {
+        let cleanup = [
+            "DELETE FROM members WHERE email = 'two-factor@sso.test'",
+            "DELETE FROM login_attempts WHERE email = 'two-factor@sso.test'",
+        ];
+        with_cleanup(&cleanup, |pool| async move {
+            const EMAIL: &str = "two-factor@sso.test";
+            let provider = discover_mock_provider().await;
+
+            sqlx::query(
+                "INSERT INTO members (email, first_name, last_name, pass_hash, phone_number, location)
+                 VALUES ($1, 'Tess', 'Totp', '', '', '')",
+            )
+            .bind(EMAIL)
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO totp_credentials (member, secret, confirmed) VALUES ($1, 'AA', true)",
+            )
+            .bind(EMAIL)
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query("INSERT INTO totp_recovery_codes (member, code_hash) VALUES ($1, $2)")
+                .bind(EMAIL)
+                .bind(hash_token("abcd-efgh"))
+                .execute(&pool)
+                .await
+                .unwrap();
+            sqlx::query(
+                "INSERT INTO external_identities (issuer, subject, member, email)
+                 VALUES ($1, '12345', $2, $2)",
+            )
+            .bind(&provider.config.issuer)
+            .bind(EMAIL)
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query("INSERT INTO oidc_login_states (state, code_verifier) VALUES ($1, 'verifier')")
+                .bind(&provider.config.issuer)
+                .execute(&pool)
+                .await
+                .unwrap();
+
+            let params = CallbackParams {
+                code: Some("good-code".to_owned()),
+                state: Some(provider.config.issuer.clone()),
+                error: None,
+                error_description: None,
+            };
+            let challenge = match provider.finish_login(params, None, &pool).await.unwrap() {
+                LoginResult::PendingTwoFactor(challenge) => challenge,
+                _ => panic!("logged in without a two-factor code"),
+            };
+            let sessions = || async {
+                sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM sessions WHERE member = $1")
+                    .bind(EMAIL)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+            };
+            assert_eq!(sessions().await, 0);
+
+            let schema = build_schema(&QueryLimits::DEFAULT);
+            let finish = |code: &str| {
+                let request = Request::new(format!(
+                    "mutation {{ finishTwoFactorLogin(challenge: \"{}\", code: \"{}\") }}",
+                    challenge, code
+                ))
+                .data(pool.clone())
+                .data(ClientInfo {
+                    user_agent: None,
+                    ip: "127.0.0.1".to_owned(),
+                });
+                schema.execute(request)
+            };
+            assert!(!finish("000000").await.errors.is_empty());
+            assert_eq!(sessions().await, 0);
+            assert!(finish("abcd-efgh").await.errors.is_empty());
+            assert_eq!(sessions().await, 1);
+        })
+        .await;
+    }
+}
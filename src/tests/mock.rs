@@ -25,6 +25,7 @@ pub fn mock_event() -> Event {
         location: String::from("Somewhere out there"),
         gig_count: false,
         default_attend: true,
+        version: 0,
         call_time: OffsetDateTime::from_unix_timestamp(1_000_000)
             .unwrap()
             .into(),
@@ -1,3 +1,4 @@
+use sha2::{Digest, Sha256};
 use time::{OffsetDateTime, UtcOffset};
 
 /// Gets the current time.
@@ -12,3 +13,48 @@ pub fn current_time() -> OffsetDateTime {
 pub fn local_offset() -> UtcOffset {
     UtcOffset::current_local_offset().expect("Failed to get current local timezone offset")
 }
+
+/// Hex-encoded SHA-256 of a secret token, for storing it at rest
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token))
+}
+
+/// The database at `DATABASE_URL`. Tests either write in a transaction that they never
+/// commit, or commit their rows and clean them up with [`with_cleanup`]
+#[cfg(test)]
+pub async fn test_pool() -> sqlx::PgPool {
+    dotenv::dotenv().ok();
+    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+
+    sqlx::PgPool::connect(&url)
+        .await
+        .expect("Failed to connect to the database")
+}
+
+/// Runs a test that has to commit its rows, like one where several connections need
+/// to see each other's writes. The `cleanup` statements run before the test, in case
+/// an earlier run was interrupted, and after it, even if it fails
+#[cfg(test)]
+pub async fn with_cleanup<F, Fut>(cleanup: &[&str], test: F)
+where
+    F: FnOnce(sqlx::PgPool) -> Fut,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    let pool = test_pool().await;
+    let clean_up = || async {
+        for statement in cleanup {
+            sqlx::query(statement)
+                .execute(&pool)
+                .await
+                .expect("Failed to clean up after the test");
+        }
+    };
+
+    clean_up().await;
+    let outcome = tokio::spawn(test(pool.clone())).await;
+    clean_up().await;
+
+    if let Err(error) = outcome {
+        std::panic::resume_unwind(error.into_panic());
+    }
+}